@@ -0,0 +1,344 @@
+//! A versioned JSON API mirroring the HTML pages, for scripts and alternative
+//! clients. Responses carry the same sanitised, URL-rewritten data the
+//! templates get, and respect the same filters, NSFW settings and SFW-only mode.
+
+use crate::client::json;
+use crate::post::parse_comments;
+use crate::search::{search_subreddits, Subreddit as SubredditResult};
+use crate::server::RequestExt;
+use crate::subreddit::{can_access_quarantine, subreddit};
+use crate::user::user;
+use crate::utils::{filter_posts, get_filters, param, parse_post, setting, sfw_only, should_be_nsfw_gated, Comment, Post, Subreddit, User};
+
+use hyper::{header::CONTENT_TYPE, Body, Request, Response};
+use serde::Serialize;
+use serde_json::json as json_value;
+
+const POST_SORTS: [&str; 5] = ["hot", "new", "top", "rising", "controversial"];
+const USER_LISTINGS: [&str; 3] = ["overview", "submitted", "comments"];
+
+/// A page of posts. Comments in user listings are posts with an empty title.
+#[derive(Serialize)]
+struct PostListing {
+	/// Whether the subreddit or user itself is filtered, in which case nothing is fetched
+	filtered: bool,
+	posts: Vec<Post>,
+	/// Pass as `before` to get the previous page
+	before: Option<String>,
+	/// Pass as `after` to get the next page
+	after: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SubredditResponse {
+	/// Missing for feeds like `popular`, `all` and `sub1+sub2`
+	subreddit: Option<Subreddit>,
+	sort: String,
+	#[serde(flatten)]
+	listing: PostListing,
+}
+
+#[derive(Serialize)]
+struct UserResponse {
+	user: User,
+	listing: String,
+	sort: String,
+	#[serde(flatten)]
+	listing_posts: PostListing,
+}
+
+#[derive(Serialize)]
+struct CommentsResponse {
+	post: Post,
+	sort: String,
+	comments: Vec<Comment>,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+	query: String,
+	sort: String,
+	/// Only included when the search isn't restricted to a subreddit
+	subreddits: Vec<SubredditResult>,
+	#[serde(flatten)]
+	listing: PostListing,
+}
+
+fn respond<T: Serialize>(status: u16, body: &T) -> Response<Body> {
+	let body = serde_json::to_string(body).unwrap_or_else(|e| json_value!({ "error": e.to_string() }).to_string());
+	Response::builder()
+		.status(status)
+		.header(CONTENT_TYPE, "application/json")
+		.body(body.into())
+		.unwrap_or_default()
+}
+
+fn api_error(status: u16, msg: &str) -> Response<Body> {
+	respond(status, &json_value!({ "error": msg }))
+}
+
+/// Translate errors from Reddit like the HTML pages do
+fn upstream_error(msg: &str, name: &str) -> Response<Body> {
+	match msg {
+		"quarantined" | "gated" => api_error(403, &format!("{name} is {msg}. Opt in from the web interface first")),
+		"private" => api_error(403, &format!("{name} is a private community")),
+		"banned" => api_error(404, &format!("{name} has been banned from Reddit")),
+		_ => api_error(502, msg),
+	}
+}
+
+fn nsfw_error() -> Response<Body> {
+	if sfw_only() {
+		api_error(403, "This instance only shows SFW content")
+	} else {
+		api_error(403, "This content is NSFW. Enable the show_nsfw setting to view it")
+	}
+}
+
+/// Fetch a listing, dropping filtered posts and NSFW posts the user can't see
+async fn listing(req: &Request<Body>, path: &str, quarantined: bool, sort: &str) -> Result<PostListing, String> {
+	let (mut posts, after) = Post::fetch(path, quarantined).await?;
+	let before = before(path, posts.first().map(fullname));
+	filter_posts(&mut posts, &get_filters(req));
+	if should_be_nsfw_gated(req, "") {
+		posts.retain(|post| !post.flags.nsfw);
+	}
+	if sort == "new" {
+		posts.sort_by_key(|p| std::cmp::Reverse(p.created_ts));
+		posts.sort_by_key(|p| std::cmp::Reverse(p.flags.stickied));
+	}
+
+	Ok(PostListing {
+		filtered: false,
+		posts,
+		before,
+		after: Some(after).filter(|after| !after.is_empty()),
+	})
+}
+
+/// The cursor for the page before this one: the fullname of its first item,
+/// before any are filtered out. The first page has nothing before it.
+fn before(path: &str, first: Option<String>) -> Option<String> {
+	let paged = ["after", "before"].iter().any(|cursor| param(path, cursor).is_some_and(|value| !value.is_empty()));
+	first.filter(|_| paged)
+}
+
+fn fullname(post: &Post) -> String {
+	// Comments in user listings are posts without a title
+	let kind = if post.title.is_empty() { "t1" } else { "t3" };
+	format!("{kind}_{}", post.id)
+}
+
+fn filtered_listing() -> PostListing {
+	PostListing {
+		filtered: true,
+		posts: Vec::new(),
+		before: None,
+		after: None,
+	}
+}
+
+/// `/api/v1/r/:sub` and `/api/v1/r/:sub/:sort`
+pub async fn subreddit_posts(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub_name = req.param("sub").unwrap_or_default();
+	let sort = req
+		.param("sort")
+		.unwrap_or_else(|| Some(setting(&req, "post_sort")).filter(|s| !s.is_empty()).unwrap_or_else(|| "hot".to_string()));
+	if !POST_SORTS.contains(&sort.as_str()) {
+		return Ok(api_error(400, &format!("Unknown sort \"{sort}\"")));
+	}
+
+	let quarantined = can_access_quarantine(&req, &sub_name);
+	let about = if sub_name.contains('+') || sub_name == "popular" || sub_name == "all" {
+		None
+	} else {
+		match subreddit(&sub_name, quarantined).await {
+			Ok(sub) if sub.nsfw && should_be_nsfw_gated(&req, "") => return Ok(nsfw_error()),
+			Ok(sub) => Some(sub),
+			Err(msg) => return Ok(upstream_error(&msg, &format!("r/{sub_name}"))),
+		}
+	};
+
+	let filters = get_filters(&req);
+	let listing = if sub_name.split('+').all(|s| filters.contains(s)) {
+		filtered_listing()
+	} else {
+		let path = format!("/r/{}/{sort}.json?{}&raw_json=1", sub_name.replace('+', "%2B"), req.uri().query().unwrap_or_default());
+		match listing(&req, &path, quarantined, &sort).await {
+			Ok(listing) => listing,
+			Err(msg) => return Ok(upstream_error(&msg, &format!("r/{sub_name}"))),
+		}
+	};
+
+	Ok(respond(200, &SubredditResponse { subreddit: about, sort, listing }))
+}
+
+/// `/api/v1/r/:sub/about`
+pub async fn subreddit_about(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub_name = req.param("sub").unwrap_or_default();
+	Ok(match subreddit(&sub_name, can_access_quarantine(&req, &sub_name)).await {
+		Ok(sub) if sub.nsfw && should_be_nsfw_gated(&req, "") => nsfw_error(),
+		Ok(sub) => respond(200, &sub),
+		Err(msg) => upstream_error(&msg, &format!("r/{sub_name}")),
+	})
+}
+
+/// `/api/v1/comments/:id`, optionally narrowed to a single thread with `/:comment_id`
+pub async fn comments(req: Request<Body>) -> Result<Response<Body>, String> {
+	let id = req.param("id").unwrap_or_default();
+	let sub_name = req.param("sub").unwrap_or_default();
+	let highlighted = req.param("comment_id").unwrap_or_default();
+	let sort = param(&format!("?{}", req.uri().query().unwrap_or_default()), "sort").unwrap_or_else(|| setting(&req, "comment_sort"));
+
+	let thread = if highlighted.is_empty() { String::new() } else { format!("/_/{highlighted}") };
+	let sort_param = if sort.is_empty() { String::new() } else { format!("&sort={sort}") };
+	let path = format!("/comments/{id}{thread}.json?{}{sort_param}&raw_json=1", req.uri().query().unwrap_or_default());
+
+	let response = match json(path, can_access_quarantine(&req, &sub_name)).await {
+		Ok(response) => response,
+		Err(msg) => return Ok(upstream_error(&msg, "This post's subreddit")),
+	};
+
+	let post = parse_post(&response[0]["data"]["children"][0]).await;
+	if post.nsfw && should_be_nsfw_gated(&req, "") {
+		return Ok(nsfw_error());
+	}
+
+	let comments = parse_comments(&response[1], &post.permalink, &post.author.name, &highlighted, &get_filters(&req), &req);
+	Ok(respond(200, &CommentsResponse { post, sort, comments }))
+}
+
+/// `/api/v1/user/:name` and `/api/v1/user/:name/:listing`
+pub async fn user_posts(req: Request<Body>) -> Result<Response<Body>, String> {
+	let name = req.param("name").unwrap_or_default();
+	let listing_name = req.param("listing").unwrap_or_else(|| "overview".to_string());
+	if !USER_LISTINGS.contains(&listing_name.as_str()) {
+		return Ok(api_error(400, &format!("Unknown listing \"{listing_name}\"")));
+	}
+
+	let about = match user(&name).await {
+		Ok(about) if about.nsfw && should_be_nsfw_gated(&req, "") => return Ok(nsfw_error()),
+		Ok(about) => about,
+		Err(msg) => return Ok(upstream_error(&msg, &format!("u/{name}"))),
+	};
+
+	let path = format!("/user/{name}/{listing_name}.json?{}&raw_json=1", req.uri().query().unwrap_or_default());
+	let sort = param(&path, "sort").unwrap_or_default();
+	let posts = if get_filters(&req).contains(&["u_", &name].concat()) {
+		filtered_listing()
+	} else {
+		match listing(&req, &path, false, "").await {
+			Ok(posts) => posts,
+			Err(msg) => return Ok(upstream_error(&msg, &format!("u/{name}"))),
+		}
+	};
+
+	Ok(respond(
+		200,
+		&UserResponse {
+			user: about,
+			listing: listing_name,
+			sort,
+			listing_posts: posts,
+		},
+	))
+}
+
+/// `/api/v1/search` and `/api/v1/r/:sub/search`
+pub async fn search(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub_name = req.param("sub").unwrap_or_default();
+	let query_string = format!("?{}", req.uri().query().unwrap_or_default());
+	let Some(query) = param(&query_string, "q").filter(|q| !q.is_empty()) else {
+		return Ok(api_error(400, "Missing search query \"q\""));
+	};
+	let sort = param(&query_string, "sort").unwrap_or_else(|| "relevance".to_string());
+	let typed = param(&query_string, "type").unwrap_or_default();
+
+	// Don't ask Reddit for NSFW results the user wouldn't see
+	let nsfw_results = if should_be_nsfw_gated(&req, "") { "" } else { "&include_over_18=on" };
+	let base = if sub_name.is_empty() {
+		String::new()
+	} else {
+		format!("/r/{}", sub_name.replace('+', "%2B"))
+	};
+	let path = format!("{base}/search.json{query_string}{nsfw_results}&raw_json=1");
+
+	let filters = get_filters(&req);
+	let subreddits = if param(&path, "restrict_sr").is_none() {
+		let mut subreddits = search_subreddits(&query, &typed).await;
+		subreddits.retain(|s| !filters.contains(s.name.as_str()));
+		subreddits
+	} else {
+		Vec::new()
+	};
+
+	let listing = if !sub_name.is_empty() && sub_name.split('+').all(|s| filters.contains(s)) {
+		filtered_listing()
+	} else {
+		match listing(&req, &path, can_access_quarantine(&req, &sub_name), "").await {
+			Ok(listing) => listing,
+			Err(msg) => return Ok(upstream_error(&msg, &format!("r/{sub_name}"))),
+		}
+	};
+
+	Ok(respond(200, &SearchResponse { query, sort, subreddits, listing }))
+}
+
+/// Anything else under `/api/v1`
+pub async fn not_found(_req: Request<Body>) -> Result<Response<Body>, String> {
+	Ok(api_error(404, "Unknown API endpoint"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_listing_serialization() {
+		let response = SubredditResponse {
+			subreddit: None,
+			sort: "hot".to_string(),
+			listing: PostListing {
+				filtered: false,
+				posts: Vec::new(),
+				before: None,
+				after: Some("t3_abc".to_string()),
+			},
+		};
+
+		let value: serde_json::Value = serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap();
+		assert_eq!(value["after"], "t3_abc");
+		assert!(value["before"].is_null());
+		assert!(value["posts"].as_array().unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_before_cursor() {
+		let first = |fullname: &str| Some(fullname.to_string());
+
+		assert_eq!(before("/r/rust/hot.json?raw_json=1", first("t3_a")), None);
+		assert_eq!(before("/r/rust/hot.json?after=t3_b&raw_json=1", first("t3_c")), first("t3_c"));
+		// Going back again from that page starts from its first item
+		assert_eq!(before("/r/rust/hot.json?before=t3_c&raw_json=1", first("t3_a")), first("t3_a"));
+		assert_eq!(before("/r/rust/hot.json?before=t3_a", None), None);
+	}
+
+	#[tokio::test]
+	async fn test_fullname() {
+		let post = crate::utils::parse_post(&serde_json::json!({"kind": "t3", "data": {"id": "abc", "title": "A post"}})).await;
+		assert_eq!(fullname(&post), "t3_abc");
+		let comment = crate::utils::parse_post(&serde_json::json!({"kind": "t1", "data": {"id": "def", "body": "A comment"}})).await;
+		assert_eq!(fullname(&comment), "t1_def");
+	}
+
+	#[tokio::test]
+	async fn test_error_response() {
+		let response = upstream_error("private", "r/test");
+		assert_eq!(response.status(), 403);
+		assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		assert_eq!(value["error"], "r/test is a private community");
+	}
+}
@@ -0,0 +1,667 @@
+//! Exporting preferences as human-readable JSON or TOML files, and importing
+//! them along with subscriptions from Reddit, Teddit and Libreddit exports.
+//! Subscriptions can also be exported to and imported from feed readers as OPML.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+
+use crate::config;
+use crate::outbound::Frontends;
+use crate::saved::valid_bookmark;
+use crate::settings::{set_preference_cookies, PREFS};
+use crate::utils::{base_url, error, redirect, template, valid_group_name, Preferences, SubscriptionGroup};
+
+use askama::Template;
+use htmlescape::encode_minimal;
+use hyper::{header::CONTENT_TYPE, Body, Request, Response};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+
+/// Subreddit feeds from Reddit, Redlib and Libreddit, like `/r/rust/.rss`, `/r/rust/new/.rss` or `/r/rust.atom`
+static SUBREDDIT_FEED_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"/r/([A-Za-z0-9_+]+)(?:/(?:hot|new|top|rising|controversial))?/?\.(?:rss|atom|json)\b").unwrap());
+
+/// Preferences that are switched on and off
+const TOGGLES: [&str; 16] = [
+	"wide",
+	"blur_spoiler",
+	"show_nsfw",
+	"blur_nsfw",
+	"use_hls",
+	"hide_hls_notification",
+	"autoplay_videos",
+	"hide_sidebar_and_summary",
+	"fixed_navbar",
+	"hide_awards",
+	"hide_score",
+	"disable_visit_reddit_confirmation",
+	"remove_default_feeds",
+	"keep_tracking_params",
+	"disable_syntax_highlighting",
+	"lite",
+];
+
+/// The human-readable settings file. Every part is optional, so a file that
+/// only lists subscriptions leaves the other settings alone.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsFile {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	subscriptions: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	filters: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	bookmarks: Option<Vec<String>>,
+	/// Single-value preferences by cookie name. An empty value means the instance default.
+	settings: BTreeMap<String, String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	subscription_groups: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl SettingsFile {
+	fn from_prefs(prefs: &Preferences) -> Self {
+		Self {
+			subscriptions: Some(prefs.subscriptions.clone()),
+			filters: Some(prefs.filters.clone()),
+			bookmarks: Some(prefs.bookmarks.clone()),
+			settings: PREFS
+				.iter()
+				.filter_map(|&name| pref(prefs, name).map(|value| (name.to_string(), value.to_string())))
+				.collect(),
+			subscription_groups: Some(prefs.subscription_groups.iter().map(|group| (group.name.clone(), group.subs.clone())).collect()),
+		}
+	}
+
+	/// Apply the file on top of existing preferences, dropping invalid values
+	/// and describing each one in the returned warnings
+	fn apply(self, prefs: &mut Preferences) -> Vec<String> {
+		let mut warnings = Vec::new();
+
+		if let Some(subs) = self.subscriptions {
+			prefs.subscriptions = valid_subreddits(subs, "subscription", &mut warnings);
+		}
+		if let Some(filters) = self.filters {
+			prefs.filters = valid_subreddits(filters, "filter", &mut warnings);
+		}
+		if let Some(groups) = self.subscription_groups {
+			prefs.subscription_groups = groups
+				.into_iter()
+				.filter_map(|(name, subs)| {
+					if !valid_group_name(&name) {
+						warnings.push(format!("Skipped subscription group \"{name}\": invalid name"));
+						return None;
+					}
+					let subs = valid_subreddits(subs, "group subreddit", &mut warnings);
+					(!subs.is_empty()).then_some(SubscriptionGroup { name, subs })
+				})
+				.collect();
+		}
+		if let Some(bookmarks) = self.bookmarks {
+			let (valid, invalid): (Vec<String>, Vec<String>) = bookmarks.into_iter().partition(|id| valid_bookmark(id));
+			warnings.extend(invalid.into_iter().map(|id| format!("Skipped bookmark \"{id}\": not a post or comment")));
+			prefs.bookmarks = valid;
+		}
+
+		// Groups are in place by now, so the front page can point at one
+		for (name, value) in self.settings {
+			match validate(prefs, &name, &value) {
+				Ok(()) => {
+					if let Some(field) = pref_mut(prefs, &name) {
+						*field = value;
+					}
+				}
+				Err(msg) => warnings.push(msg),
+			}
+		}
+
+		// Or it may still point at a group that was just removed
+		if let Err(msg) = validate(prefs, "front_page", &prefs.front_page) {
+			warnings.push(msg);
+			prefs.front_page = String::new();
+		}
+
+		warnings
+	}
+}
+
+fn pref<'a>(prefs: &'a Preferences, name: &str) -> Option<&'a String> {
+	Some(match name {
+		"theme" => &prefs.theme,
+		"front_page" => &prefs.front_page,
+		"layout" => &prefs.layout,
+		"wide" => &prefs.wide,
+		"comment_sort" => &prefs.comment_sort,
+		"post_sort" => &prefs.post_sort,
+		"blur_spoiler" => &prefs.blur_spoiler,
+		"show_nsfw" => &prefs.show_nsfw,
+		"blur_nsfw" => &prefs.blur_nsfw,
+		"use_hls" => &prefs.use_hls,
+		"hide_hls_notification" => &prefs.hide_hls_notification,
+		"autoplay_videos" => &prefs.autoplay_videos,
+		"hide_sidebar_and_summary" => &prefs.hide_sidebar_and_summary,
+		"fixed_navbar" => &prefs.fixed_navbar,
+		"hide_awards" => &prefs.hide_awards,
+		"hide_score" => &prefs.hide_score,
+		"disable_visit_reddit_confirmation" => &prefs.disable_visit_reddit_confirmation,
+		"video_quality" => &prefs.video_quality,
+		"remove_default_feeds" => &prefs.remove_default_feeds,
+		"keep_tracking_params" => &prefs.keep_tracking_params,
+		"frontends" => &prefs.frontends,
+		"disable_syntax_highlighting" => &prefs.disable_syntax_highlighting,
+		"lite" => &prefs.lite,
+		_ => return None,
+	})
+}
+
+fn pref_mut<'a>(prefs: &'a mut Preferences, name: &str) -> Option<&'a mut String> {
+	Some(match name {
+		"theme" => &mut prefs.theme,
+		"front_page" => &mut prefs.front_page,
+		"layout" => &mut prefs.layout,
+		"wide" => &mut prefs.wide,
+		"comment_sort" => &mut prefs.comment_sort,
+		"post_sort" => &mut prefs.post_sort,
+		"blur_spoiler" => &mut prefs.blur_spoiler,
+		"show_nsfw" => &mut prefs.show_nsfw,
+		"blur_nsfw" => &mut prefs.blur_nsfw,
+		"use_hls" => &mut prefs.use_hls,
+		"hide_hls_notification" => &mut prefs.hide_hls_notification,
+		"autoplay_videos" => &mut prefs.autoplay_videos,
+		"hide_sidebar_and_summary" => &mut prefs.hide_sidebar_and_summary,
+		"fixed_navbar" => &mut prefs.fixed_navbar,
+		"hide_awards" => &mut prefs.hide_awards,
+		"hide_score" => &mut prefs.hide_score,
+		"disable_visit_reddit_confirmation" => &mut prefs.disable_visit_reddit_confirmation,
+		"video_quality" => &mut prefs.video_quality,
+		"remove_default_feeds" => &mut prefs.remove_default_feeds,
+		"keep_tracking_params" => &mut prefs.keep_tracking_params,
+		"frontends" => &mut prefs.frontends,
+		"disable_syntax_highlighting" => &mut prefs.disable_syntax_highlighting,
+		"lite" => &mut prefs.lite,
+		_ => return None,
+	})
+}
+
+/// Check a single preference against the values the settings page offers
+pub(crate) fn validate(prefs: &Preferences, name: &str, value: &str) -> Result<(), String> {
+	// Empty values fall back to the instance default
+	if value.is_empty() {
+		return if PREFS.contains(&name) {
+			Ok(())
+		} else {
+			Err(format!("Skipped unknown setting \"{name}\""))
+		};
+	}
+
+	let valid = match name {
+		"theme" => prefs.available_themes.iter().any(|theme| theme == value),
+		"front_page" => ["default", "popular", "all"].contains(&value) || value.strip_prefix("g/").is_some_and(|group| prefs.group(group).is_some()),
+		"layout" => ["card", "clean", "compact"].contains(&value),
+		"video_quality" => ["best", "medium", "worst"].contains(&value),
+		"post_sort" => ["hot", "new", "top", "rising", "controversial"].contains(&value),
+		"comment_sort" => ["confidence", "top", "new", "controversial", "old"].contains(&value),
+		"frontends" => Frontends::parse(value).is_ok(),
+		name if TOGGLES.contains(&name) => value == "on" || value == "off",
+		_ => return Err(format!("Skipped unknown setting \"{name}\"")),
+	};
+
+	if valid {
+		Ok(())
+	} else {
+		Err(format!("Skipped \"{name}\": \"{value}\" is not a valid value"))
+	}
+}
+
+/// Keep well-formed subreddit (or `u_` user) names, without duplicates
+fn valid_subreddits(names: Vec<String>, kind: &str, warnings: &mut Vec<String>) -> Vec<String> {
+	let mut valid: Vec<String> = Vec::new();
+	for name in names {
+		let name = name.trim().trim_start_matches("/r/").trim_start_matches("r/").to_string();
+		if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+			warnings.push(format!("Skipped {kind} \"{name}\": invalid subreddit name"));
+		} else if !valid.iter().any(|sub| sub.eq_ignore_ascii_case(&name)) {
+			valid.push(name);
+		}
+	}
+	valid
+}
+
+/// The Teddit settings export, of which only the parts Redlib shares are read
+#[derive(Deserialize)]
+struct TedditExport {
+	subbed_subreddits: Vec<String>,
+	#[serde(default)]
+	nsfw_enabled: Option<String>,
+	#[serde(default)]
+	theme: Option<String>,
+}
+
+/// Parse an uploaded file in any of the supported formats
+fn parse(contents: &str, current: &Preferences) -> Result<(&'static str, SettingsFile), String> {
+	let contents = contents.trim().trim_start_matches('\u{feff}');
+
+	if contents.starts_with('{') {
+		if let Ok(teddit) = serde_json::from_str::<TedditExport>(contents) {
+			return Ok(("Teddit export", from_teddit(teddit, current)));
+		}
+		return serde_json::from_str(contents)
+			.map(|file| ("JSON", file))
+			.map_err(|e| format!("Invalid JSON settings file: {e}"));
+	}
+
+	// Restore links from Redlib and Libreddit, or just their query string
+	if contents.contains("/settings/restore") || contents.starts_with('?') {
+		let query = contents.split_once('?').map_or("", |(_, query)| query);
+		return Ok(("restore link", from_query(query)));
+	}
+
+	if contents.starts_with("<?xml") || contents.starts_with("<opml") {
+		return from_opml(contents, current).map(|file| ("OPML", file));
+	}
+
+	// Reddit's GDPR export has a "subreddit" header, then one subreddit per line
+	let mut lines = contents.lines();
+	if lines.next().is_some_and(|header| header.trim().eq_ignore_ascii_case("subreddit")) {
+		let subs = lines.map(|line| line.trim().trim_matches('"').to_string()).filter(|line| !line.is_empty());
+		return Ok(("Reddit data export", merged_subscriptions(current, subs)));
+	}
+
+	toml::from_str(contents).map(|file| ("TOML", file)).map_err(|e| format!("Unrecognized settings file: {e}"))
+}
+
+/// Imported subscriptions are added to the existing ones rather than replacing them
+fn merged_subscriptions(current: &Preferences, subs: impl IntoIterator<Item = String>) -> SettingsFile {
+	SettingsFile {
+		subscriptions: Some(current.subscriptions.iter().cloned().chain(subs).collect()),
+		..Default::default()
+	}
+}
+
+fn from_teddit(teddit: TedditExport, current: &Preferences) -> SettingsFile {
+	let mut file = merged_subscriptions(current, teddit.subbed_subreddits);
+	if let Some(nsfw) = teddit.nsfw_enabled {
+		file.settings.insert("show_nsfw".to_string(), if nsfw == "true" { "on" } else { "off" }.to_string());
+	}
+	// Teddit's themes are named after the colours they share with ours
+	if let Some(theme) = teddit.theme.filter(|theme| current.available_themes.contains(theme)) {
+		file.settings.insert("theme".to_string(), theme);
+	}
+	file
+}
+
+fn from_query(query: &str) -> SettingsFile {
+	let form: HashMap<String, String> = form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+	let list = |name: &str| form.get(name).map(|value| value.split('+').filter(|s| !s.is_empty()).map(str::to_string).collect());
+
+	SettingsFile {
+		subscriptions: list("subscriptions"),
+		filters: list("filters"),
+		bookmarks: None,
+		settings: form
+			.iter()
+			.filter(|(name, _)| PREFS.contains(&name.as_str()))
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect(),
+		subscription_groups: form
+			.get("subscription_groups")
+			.map(|value| value.split('+').filter_map(SubscriptionGroup::parse).map(|group| (group.name, group.subs)).collect()),
+	}
+}
+
+/// Subscriptions as an OPML 2.0 outline of the feeds on this instance at
+/// `base`. Each subscription group becomes a folder, followed by the
+/// remaining subscriptions.
+fn to_opml(prefs: &Preferences, base: &str) -> String {
+	let outline = |sub: &str| {
+		format!(
+			"<outline type=\"rss\" text=\"{0}\" title=\"{0}\" xmlUrl=\"{1}\" htmlUrl=\"{2}\"/>",
+			encode_minimal(&format!("r/{sub}")),
+			encode_minimal(&format!("{base}/r/{sub}.rss")),
+			encode_minimal(&format!("{base}/r/{sub}")),
+		)
+	};
+
+	let mut body = String::new();
+	for group in &prefs.subscription_groups {
+		body.push_str(&format!("\t\t<outline text=\"{0}\" title=\"{0}\">\n", encode_minimal(&group.name)));
+		for sub in &group.subs {
+			body.push_str(&format!("\t\t\t{}\n", outline(sub)));
+		}
+		body.push_str("\t\t</outline>\n");
+	}
+	for sub in prefs
+		.subscriptions
+		.iter()
+		.filter(|sub| !prefs.subscription_groups.iter().any(|group| group.subs.contains(sub)))
+	{
+		body.push_str(&format!("\t\t{}\n", outline(sub)));
+	}
+
+	format!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n\t<head>\n\t\t<title>Redlib subscriptions</title>\n\t</head>\n\t<body>\n{body}\t</body>\n</opml>\n"
+	)
+}
+
+/// Subreddit feeds in an OPML file are added to the existing subscriptions.
+/// Feeds in a folder also join the subscription group named after it.
+fn from_opml(contents: &str, current: &Preferences) -> Result<SettingsFile, String> {
+	let mut subs = Vec::new();
+	let mut groups: BTreeMap<String, Vec<String>> = current.subscription_groups.iter().map(|group| (group.name.clone(), group.subs.clone())).collect();
+	// Names of the folders the current outline is nested in
+	let mut folders: Vec<String> = Vec::new();
+
+	let mut reader = Reader::from_str(contents);
+	loop {
+		let (tag, folder) = match reader.read_event().map_err(|e| format!("This OPML file is invalid: {e}"))? {
+			Event::Start(tag) if tag.name().as_ref() == b"outline" => (tag, true),
+			Event::Empty(tag) if tag.name().as_ref() == b"outline" => (tag, false),
+			Event::End(tag) if tag.name().as_ref() == b"outline" => {
+				folders.pop();
+				continue;
+			}
+			Event::Eof => break,
+			_ => continue,
+		};
+		let attributes: HashMap<Vec<u8>, String> = tag
+			.attributes()
+			.flatten()
+			.filter_map(|attribute| Some((attribute.key.as_ref().to_vec(), attribute.unescape_value().ok()?.into_owned())))
+			.collect();
+		let attribute = |name: &str| attributes.get(name.as_bytes());
+
+		if let Some(feed) = attribute("xmlUrl").and_then(|url| SUBREDDIT_FEED_REGEX.captures(url)) {
+			let feed_subs: Vec<String> = feed[1].split('+').filter(|sub| !sub.is_empty()).map(String::from).collect();
+			if let Some(folder) = folders.iter().rev().find(|folder| !folder.is_empty()) {
+				groups.entry(folder.clone()).or_default().extend(feed_subs.iter().cloned());
+			}
+			subs.extend(feed_subs);
+		}
+
+		if folder {
+			let name = attribute("text").or_else(|| attribute("title")).map_or("", |name| name.trim());
+			// Group names can't contain spaces, which folder names often do
+			folders.push(name.split_whitespace().collect::<Vec<_>>().join("-"));
+		}
+	}
+
+	if subs.is_empty() {
+		return Err("This OPML file doesn't contain any subreddit feeds".to_string());
+	}
+
+	Ok(SettingsFile {
+		subscription_groups: Some(groups),
+		..merged_subscriptions(current, subs)
+	})
+}
+
+/// One changed single-value preference
+struct Change {
+	name: &'static str,
+	old: String,
+	new: String,
+}
+
+/// Items added to and removed from a list preference
+struct ListChange {
+	name: &'static str,
+	added: Vec<String>,
+	removed: Vec<String>,
+}
+
+fn list_change(name: &'static str, old: &[String], new: &[String]) -> Option<ListChange> {
+	let added: Vec<String> = new.iter().filter(|item| !old.contains(item)).cloned().collect();
+	let removed: Vec<String> = old.iter().filter(|item| !new.contains(item)).cloned().collect();
+	(!added.is_empty() || !removed.is_empty()).then_some(ListChange { name, added, removed })
+}
+
+fn diff(old: &Preferences, new: &Preferences) -> (Vec<Change>, Vec<ListChange>) {
+	let changes = PREFS
+		.iter()
+		.filter_map(|&name| {
+			let (old, new) = (pref(old, name)?, pref(new, name)?);
+			(old != new).then(|| Change {
+				name,
+				old: old.clone(),
+				new: new.clone(),
+			})
+		})
+		.collect();
+
+	let groups = |prefs: &Preferences| prefs.subscription_groups.iter().map(ToString::to_string).collect::<Vec<_>>();
+	let lists = [
+		list_change("Subscriptions", &old.subscriptions, &new.subscriptions),
+		list_change("Filters", &old.filters, &new.filters),
+		list_change("Subscription groups", &groups(old), &groups(new)),
+		list_change("Saved posts and comments", &old.bookmarks, &new.bookmarks),
+	]
+	.into_iter()
+	.flatten()
+	.collect();
+
+	(changes, lists)
+}
+
+/// `ImportTemplate` shows what an import would change before applying it
+#[derive(Template)]
+#[template(path = "settings_import.html")]
+struct ImportTemplate {
+	format: &'static str,
+	warnings: Vec<String>,
+	changes: Vec<Change>,
+	lists: Vec<ListChange>,
+	/// The imported preferences as a complete JSON settings file, submitted to apply them
+	normalized: String,
+	prefs: Preferences,
+	url: String,
+}
+
+/// Download the current preferences as a JSON or TOML file
+pub async fn export(req: Request<Body>) -> Result<Response<Body>, String> {
+	let file = SettingsFile::from_prefs(&Preferences::new(&req));
+	let (body, content_type, extension) = if req.uri().path().ends_with(".toml") {
+		(toml::to_string_pretty(&file).map_err(|e| e.to_string())?, "application/toml", "toml")
+	} else {
+		(serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?, "application/json", "json")
+	};
+
+	Ok(
+		Response::builder()
+			.status(200)
+			.header(CONTENT_TYPE, content_type)
+			.header("content-disposition", format!("attachment; filename=\"redlib-settings.{extension}\""))
+			.body(body.into())
+			.unwrap_or_default(),
+	)
+}
+
+async fn form_field(req: Request<Body>, field: &str) -> Result<String, String> {
+	let body = hyper::body::to_bytes(req.into_body())
+		.await
+		.map_err(|e| format!("Failed to get bytes from request body: {e}"))?;
+
+	if body.len() > 1024 * 1024 {
+		return Err("Request body too large".to_string());
+	}
+
+	form_urlencoded::parse(&body)
+		.find(|(key, _)| key == field)
+		.map(|(_, value)| value.into_owned())
+		.ok_or_else(|| format!("{field} parameter not found in request body"))
+}
+
+/// Download the subscriptions as OPML, for importing into a feed reader
+pub async fn opml(req: Request<Body>) -> Result<Response<Body>, String> {
+	if config::get_setting("REDLIB_ENABLE_RSS").is_none() {
+		return Ok(error(req, "RSS is disabled on this instance.").await.unwrap_or_default());
+	}
+	// Feed readers need absolute URLs
+	let Some(base) = base_url(&req) else {
+		return Ok(error(req, "Set REDLIB_FULL_URL to export subscriptions as OPML.").await.unwrap_or_default());
+	};
+
+	Ok(
+		Response::builder()
+			.status(200)
+			.header(CONTENT_TYPE, "text/x-opml")
+			.header("content-disposition", "attachment; filename=\"redlib-subscriptions.opml\"")
+			.body(to_opml(&Preferences::new(&req), &base).into())
+			.unwrap_or_default(),
+	)
+}
+
+/// Validate an uploaded file and show the changes it would make
+pub async fn import(req: Request<Body>) -> Result<Response<Body>, String> {
+	let current = Preferences::new(&req);
+	let url = req.uri().to_string();
+	let contents = form_field(req, "contents").await?;
+
+	let (format, file) = parse(&contents, &current)?;
+	let mut imported = current.clone();
+	let warnings = file.apply(&mut imported);
+	let (changes, lists) = diff(&current, &imported);
+	let normalized = serde_json::to_string(&SettingsFile::from_prefs(&imported)).map_err(|e| e.to_string())?;
+
+	Ok(template(&ImportTemplate {
+		format,
+		warnings,
+		changes,
+		lists,
+		normalized,
+		prefs: current,
+		url,
+	}))
+}
+
+/// Apply a previewed import
+pub async fn apply(req: Request<Body>) -> Result<Response<Body>, String> {
+	let mut prefs = Preferences::new(&req);
+	let cookies = req.headers().get("cookie").and_then(|hv| hv.to_str().ok()).unwrap_or_default().to_string();
+	let normalized = form_field(req, "normalized").await?;
+
+	let file: SettingsFile = serde_json::from_str(&normalized).map_err(|e| format!("Invalid settings file: {e}"))?;
+	file.apply(&mut prefs);
+
+	let mut response = redirect("/settings");
+	set_preference_cookies(&mut response, &cookies, &prefs)?;
+	Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn current() -> Preferences {
+		Preferences {
+			available_themes: vec!["system".to_string(), "dark".to_string(), "dracula".to_string()],
+			theme: "dark".to_string(),
+			subscriptions: vec!["rust".to_string()],
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_export_roundtrip() {
+		let mut prefs = current();
+		prefs.post_sort = "new".to_string();
+		prefs.subscription_groups = vec![SubscriptionGroup {
+			name: "news".to_string(),
+			subs: vec!["worldnews".to_string()],
+		}];
+		prefs.front_page = "g/news".to_string();
+		let file = SettingsFile::from_prefs(&prefs);
+
+		for contents in [serde_json::to_string_pretty(&file).unwrap(), toml::to_string_pretty(&file).unwrap()] {
+			let (_, parsed) = parse(&contents, &current()).unwrap();
+			let mut imported = current();
+			assert!(parsed.apply(&mut imported).is_empty());
+			assert_eq!(imported, prefs);
+		}
+	}
+
+	#[test]
+	fn test_import_validation() {
+		let contents = r#"{"settings": {"theme": "nope", "post_sort": "best", "layout": "compact", "colour": "red"}, "subscriptions": ["linux", "not a sub"]}"#;
+		let (format, file) = parse(contents, &current()).unwrap();
+		assert_eq!(format, "JSON");
+
+		let mut imported = current();
+		let warnings = file.apply(&mut imported);
+		assert_eq!(warnings.len(), 4);
+		assert_eq!(imported.theme, "dark");
+		assert_eq!(imported.layout, "compact");
+		assert_eq!(imported.subscriptions, vec!["linux"]);
+
+		let (changes, lists) = diff(&current(), &imported);
+		assert_eq!(changes.len(), 1);
+		assert_eq!(lists[0].added, vec!["linux"]);
+		assert_eq!(lists[0].removed, vec!["rust"]);
+	}
+
+	#[test]
+	fn test_import_other_formats() {
+		let csv = "subreddit\r\nlinux\r\n\"AskReddit\"\r\n";
+		let (format, file) = parse(csv, &current()).unwrap();
+		assert_eq!(format, "Reddit data export");
+		assert_eq!(file.subscriptions.unwrap(), vec!["rust", "linux", "AskReddit"]);
+
+		let teddit = r#"{"theme": "dark", "nsfw_enabled": "true", "subbed_subreddits": ["linux"], "flairs": "true"}"#;
+		let (_, file) = parse(teddit, &current()).unwrap();
+		assert_eq!(file.settings.get("show_nsfw").unwrap(), "on");
+		assert_eq!(file.subscriptions.unwrap(), vec!["rust", "linux"]);
+
+		let link = "https://libreddit.example/settings/restore/?theme=dracula&layout=clean&subscriptions=linux%2Bunixporn";
+		let (format, file) = parse(link, &current()).unwrap();
+		assert_eq!(format, "restore link");
+		assert_eq!(file.settings.get("layout").unwrap(), "clean");
+		assert_eq!(file.subscriptions.unwrap(), vec!["linux", "unixporn"]);
+
+		assert!(parse("definitely not settings", &current()).is_err());
+	}
+
+	#[test]
+	fn test_opml() {
+		let mut prefs = current();
+		prefs.subscriptions = vec!["linux".to_string(), "worldnews".to_string()];
+		prefs.subscription_groups = vec![SubscriptionGroup {
+			name: "news".to_string(),
+			subs: vec!["worldnews".to_string()],
+		}];
+		let opml = to_opml(&prefs, "https://redlib.example.com");
+		assert_eq!(opml.matches("type=\"rss\"").count(), 2);
+		assert!(opml.contains("xmlUrl=\"https://redlib.example.com/r/linux.rss\""));
+		let odd = Preferences {
+			subscription_groups: vec![SubscriptionGroup {
+				name: "a&b".to_string(),
+				subs: Vec::new(),
+			}],
+			..current()
+		};
+		assert!(to_opml(&odd, "").contains("<outline text=\"a&amp;b\" title=\"a&amp;b\">"));
+
+		let (format, file) = parse(&opml, &current()).unwrap();
+		assert_eq!(format, "OPML");
+		let mut imported = current();
+		assert!(file.apply(&mut imported).is_empty());
+		assert_eq!(imported.subscriptions, vec!["rust", "worldnews", "linux"]);
+		assert_eq!(imported.subscription_groups, prefs.subscription_groups);
+
+		// A reader's export, with Reddit's own feed URLs and unrelated feeds
+		let reader = r#"<?xml version="1.0"?>
+			<opml version="1.0"><body>
+				<outline text="Blogs"><outline type="rss" text="Blog" xmlUrl="https://example.com/feed.xml"/></outline>
+				<outline text="Tech news" title="Tech news">
+					<outline type="rss" text="r/programming" xmlUrl="https://www.reddit.com/r/programming/.rss"/>
+					<outline type="rss" text="multi" xmlUrl='https://old.reddit.com/r/netsec+crypto/new/.rss?sort=new&amp;limit=50'/>
+				</outline>
+				<outline type="rss" text="r/linux" xmlUrl="https://www.reddit.com/r/linux.rss"/>
+			</body></opml>"#;
+		let (_, file) = parse(reader, &current()).unwrap();
+		assert_eq!(file.subscriptions.unwrap(), vec!["rust", "programming", "netsec", "crypto", "linux"]);
+		assert_eq!(file.subscription_groups.unwrap()["Tech-news"], vec!["programming", "netsec", "crypto"]);
+
+		assert!(parse("<opml><body></body></opml>", &current()).is_err());
+		assert!(parse("<opml><body><outline xmlUrl=\"/r/rust.rss\"></body></opml>", &current()).is_err());
+	}
+}
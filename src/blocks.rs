@@ -0,0 +1,259 @@
+//! Rendered HTML walked into blocks of lines, for the pages that aren't HTML.
+//! The plain-text pages and the Gemini front-end lay these out their own way.
+
+use regex::Regex;
+use std::ops::Range;
+use std::sync::LazyLock;
+
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<(/?)([a-zA-Z0-9]+)([^>]*)>").unwrap());
+static ATTR_HREF: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"href="([^"]*)""#).unwrap());
+static ATTR_ALT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"alt="([^"]*)""#).unwrap());
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Block {
+	/// A line of text. Paragraphs are a single line until they're wrapped.
+	Line(Line),
+	/// Lines to keep as they are
+	Pre(Vec<String>),
+	/// A horizontal rule
+	Rule,
+	/// The end of a paragraph, heading, list or other block
+	Break,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Line {
+	pub text: String,
+	/// The level of the heading this line is, or 0
+	pub heading: usize,
+	/// How many quotes the line is in
+	pub quotes: usize,
+	/// How many lists the line is in
+	pub lists: usize,
+	/// The bullet or number of a list item, on its first line only
+	pub marker: Option<Marker>,
+	/// Links in the line, as the range of their text and where they point
+	pub links: Vec<(Range<usize>, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+	Bullet,
+	Number(usize),
+}
+
+/// Walk rendered, sanitised HTML into blocks
+pub fn parse(html: &str) -> Vec<Block> {
+	let mut walker = Walker::default();
+	let mut last = 0;
+
+	for caps in TAG.captures_iter(html) {
+		let whole = caps.get(0).unwrap();
+		walker.text(&html[last..whole.start()]);
+		last = whole.end();
+
+		let closing = !caps[1].is_empty();
+		let attrs = &caps[3];
+		match (caps[2].to_ascii_lowercase().as_str(), closing) {
+			("p" | "div" | "table", _) => walker.end_block(),
+			("br", _) => walker.end_line(),
+			("hr", _) => {
+				walker.end_block();
+				walker.blocks.push(Block::Rule);
+				walker.end_block();
+			}
+			(tag @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6"), false) => {
+				walker.end_block();
+				walker.heading = tag[1..].parse().unwrap_or(1);
+			}
+			("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => {
+				walker.end_block();
+				walker.heading = 0;
+			}
+			("blockquote", false) => {
+				walker.end_block();
+				walker.quotes += 1;
+			}
+			("blockquote", true) => {
+				walker.end_block();
+				walker.quotes = walker.quotes.saturating_sub(1);
+			}
+			(tag @ ("ul" | "ol"), false) => {
+				if walker.lists.is_empty() {
+					walker.end_block();
+				} else {
+					walker.end_line();
+				}
+				walker.lists.push((tag == "ol").then_some(0));
+			}
+			("ul" | "ol", true) => {
+				walker.end_line();
+				walker.lists.pop();
+				if walker.lists.is_empty() {
+					walker.end_block();
+				}
+			}
+			("li", false) => {
+				walker.end_line();
+				walker.marker = Some(match walker.lists.last_mut() {
+					Some(Some(number)) => {
+						*number += 1;
+						Marker::Number(*number)
+					}
+					_ => Marker::Bullet,
+				});
+			}
+			("li" | "tr", true) => walker.end_line(),
+			("td" | "th", true) => walker.line.push_str(" | "),
+			("pre", false) => {
+				walker.end_block();
+				walker.preformatted = true;
+			}
+			("pre", true) => {
+				walker.preformatted = false;
+				walker.links.clear();
+				let code = std::mem::take(&mut walker.line);
+				walker.blocks.push(Block::Pre(code.trim_end_matches('\n').lines().map(String::from).collect()));
+				walker.end_block();
+			}
+			("a", false) => {
+				walker.href = ATTR_HREF.captures(attrs).map(|caps| decode(&caps[1]));
+				walker.link_start = walker.line.len();
+			}
+			("a", true) => {
+				if let Some(href) = walker.href.take() {
+					walker.links.push((walker.link_start..walker.line.len(), href));
+				}
+			}
+			("img", _) => {
+				if let Some(alt) = ATTR_ALT.captures(attrs) {
+					walker.line.push_str(&decode(&alt[1]));
+				}
+			}
+			("sup", false) => walker.line.push('^'),
+			_ => {}
+		}
+	}
+	walker.text(&html[last..]);
+	walker.end_block();
+
+	walker.blocks
+}
+
+#[derive(Default)]
+struct Walker {
+	blocks: Vec<Block>,
+	line: String,
+	heading: usize,
+	quotes: usize,
+	/// Open lists, with the last number used if they're ordered
+	lists: Vec<Option<usize>>,
+	/// The marker of a list item that hasn't been written yet
+	marker: Option<Marker>,
+	preformatted: bool,
+	href: Option<String>,
+	/// Where the text of the open link starts in the line
+	link_start: usize,
+	/// Links in the line being written
+	links: Vec<(Range<usize>, String)>,
+}
+
+impl Walker {
+	fn text(&mut self, text: &str) {
+		if text.is_empty() {
+			return;
+		}
+		let text = decode(text);
+		if self.preformatted {
+			self.line.push_str(&text);
+			return;
+		}
+		let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+		if text.starts_with(char::is_whitespace) && !self.line.is_empty() && !self.line.ends_with(' ') {
+			self.line.push(' ');
+		}
+		self.line.push_str(&collapsed);
+		if text.ends_with(char::is_whitespace) && !collapsed.is_empty() {
+			self.line.push(' ');
+		}
+	}
+
+	/// Finish the line being written. Lines with nothing but links are kept for the links.
+	fn end_line(&mut self) {
+		let line = std::mem::take(&mut self.line);
+		let links = std::mem::take(&mut self.links);
+		self.link_start = 0;
+
+		let text = line.trim();
+		if text.is_empty() && links.is_empty() {
+			return;
+		}
+		// Link ranges were measured before trimming
+		let leading = line.len() - line.trim_start().len();
+		let clamp = |i: usize| i.saturating_sub(leading).min(text.len());
+		self.blocks.push(Block::Line(Line {
+			text: text.to_string(),
+			heading: self.heading,
+			quotes: self.quotes,
+			lists: self.lists.len(),
+			marker: self.marker.take(),
+			links: links.into_iter().map(|(range, href)| (clamp(range.start)..clamp(range.end), href)).collect(),
+		}));
+	}
+
+	fn end_block(&mut self) {
+		self.end_line();
+		if self.blocks.last().is_some_and(|block| *block != Block::Break) {
+			self.blocks.push(Block::Break);
+		}
+	}
+}
+
+fn decode(text: &str) -> String {
+	htmlescape::decode_html(text).unwrap_or_else(|_| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse() {
+		let html = r#"<h2>Heading</h2><p> Some <a href="/r/rust?a=1&amp;b=2">linked <em>text</em></a> </p><ol><li>one<br>more</li></ol><hr><pre><code>a
+  b
+</code></pre>"#;
+		assert_eq!(
+			parse(html),
+			[
+				Block::Line(Line {
+					text: "Heading".to_string(),
+					heading: 2,
+					..Default::default()
+				}),
+				Block::Break,
+				Block::Line(Line {
+					text: "Some linked text".to_string(),
+					links: vec![(5..16, "/r/rust?a=1&b=2".to_string())],
+					..Default::default()
+				}),
+				Block::Break,
+				Block::Line(Line {
+					text: "one".to_string(),
+					lists: 1,
+					marker: Some(Marker::Number(1)),
+					..Default::default()
+				}),
+				Block::Line(Line {
+					text: "more".to_string(),
+					lists: 1,
+					..Default::default()
+				}),
+				Block::Break,
+				Block::Rule,
+				Block::Break,
+				Block::Pre(vec!["a".to_string(), "  b".to_string()]),
+				Block::Break,
+			]
+		);
+	}
+}
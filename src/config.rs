@@ -108,6 +108,57 @@ pub struct Config {
 
 	#[serde(rename = "REDLIB_DEFAULT_REMOVE_DEFAULT_FEEDS")]
 	pub(crate) default_remove_default_feeds: Option<String>,
+
+	#[serde(rename = "REDLIB_DEFAULT_KEEP_TRACKING_PARAMS")]
+	pub(crate) default_keep_tracking_params: Option<String>,
+
+	#[serde(rename = "REDLIB_DEFAULT_DISABLE_SYNTAX_HIGHLIGHTING")]
+	pub(crate) default_disable_syntax_highlighting: Option<String>,
+
+	#[serde(rename = "REDLIB_DEFAULT_LITE")]
+	pub(crate) default_lite: Option<String>,
+
+	#[serde(rename = "REDLIB_TRACKING_RULES_FILE")]
+	pub(crate) tracking_rules_file: Option<String>,
+
+	#[serde(rename = "REDLIB_FRONTENDS")]
+	pub(crate) frontends: Option<String>,
+
+	#[serde(rename = "REDLIB_ENABLE_SYNC")]
+	pub(crate) enable_sync: Option<String>,
+
+	#[serde(rename = "REDLIB_SYNC_STORAGE_PATH")]
+	pub(crate) sync_storage_path: Option<String>,
+
+	#[serde(rename = "REDLIB_SYNC_MAX_STORAGE_MB")]
+	pub(crate) sync_max_storage_mb: Option<String>,
+
+	#[serde(rename = "REDLIB_TRUSTED_PROXIES")]
+	pub(crate) trusted_proxies: Option<String>,
+
+	#[serde(rename = "REDLIB_SHARE_CACHE_PATH")]
+	pub(crate) share_cache_path: Option<String>,
+
+	#[serde(rename = "REDLIB_RESOLVE_IMGUR")]
+	pub(crate) resolve_imgur: Option<String>,
+
+	#[serde(rename = "REDLIB_RESOLVE_REDGIFS")]
+	pub(crate) resolve_redgifs: Option<String>,
+
+	#[serde(rename = "REDLIB_RESOLVE_STREAMABLE")]
+	pub(crate) resolve_streamable: Option<String>,
+
+	#[serde(rename = "REDLIB_IMGUR_CLIENT_ID")]
+	pub(crate) imgur_client_id: Option<String>,
+
+	#[serde(rename = "REDLIB_GEMINI_CERT")]
+	pub(crate) gemini_cert: Option<String>,
+
+	#[serde(rename = "REDLIB_GEMINI_KEY")]
+	pub(crate) gemini_key: Option<String>,
+
+	#[serde(rename = "REDLIB_GEMINI_ADDRESS")]
+	pub(crate) gemini_address: Option<String>,
 }
 
 impl Config {
@@ -156,6 +207,23 @@ impl Config {
 			enable_rss: parse("REDLIB_ENABLE_RSS"),
 			full_url: parse("REDLIB_FULL_URL"),
 			default_remove_default_feeds: parse("REDLIB_DEFAULT_REMOVE_DEFAULT_FEEDS"),
+			default_keep_tracking_params: parse("REDLIB_DEFAULT_KEEP_TRACKING_PARAMS"),
+			default_disable_syntax_highlighting: parse("REDLIB_DEFAULT_DISABLE_SYNTAX_HIGHLIGHTING"),
+			default_lite: parse("REDLIB_DEFAULT_LITE"),
+			tracking_rules_file: parse("REDLIB_TRACKING_RULES_FILE"),
+			frontends: parse("REDLIB_FRONTENDS"),
+			enable_sync: parse("REDLIB_ENABLE_SYNC"),
+			sync_storage_path: parse("REDLIB_SYNC_STORAGE_PATH"),
+			sync_max_storage_mb: parse("REDLIB_SYNC_MAX_STORAGE_MB"),
+			trusted_proxies: parse("REDLIB_TRUSTED_PROXIES"),
+			share_cache_path: parse("REDLIB_SHARE_CACHE_PATH"),
+			resolve_imgur: parse("REDLIB_RESOLVE_IMGUR"),
+			resolve_redgifs: parse("REDLIB_RESOLVE_REDGIFS"),
+			resolve_streamable: parse("REDLIB_RESOLVE_STREAMABLE"),
+			imgur_client_id: parse("REDLIB_IMGUR_CLIENT_ID"),
+			gemini_cert: parse("REDLIB_GEMINI_CERT"),
+			gemini_key: parse("REDLIB_GEMINI_KEY"),
+			gemini_address: parse("REDLIB_GEMINI_ADDRESS"),
 		}
 	}
 }
@@ -186,6 +254,23 @@ fn get_setting_from_config(name: &str, config: &Config) -> Option<String> {
 		"REDLIB_ENABLE_RSS" => config.enable_rss.clone(),
 		"REDLIB_FULL_URL" => config.full_url.clone(),
 		"REDLIB_DEFAULT_REMOVE_DEFAULT_FEEDS" => config.default_remove_default_feeds.clone(),
+		"REDLIB_DEFAULT_KEEP_TRACKING_PARAMS" => config.default_keep_tracking_params.clone(),
+		"REDLIB_DEFAULT_DISABLE_SYNTAX_HIGHLIGHTING" => config.default_disable_syntax_highlighting.clone(),
+		"REDLIB_DEFAULT_LITE" => config.default_lite.clone(),
+		"REDLIB_TRACKING_RULES_FILE" => config.tracking_rules_file.clone(),
+		"REDLIB_FRONTENDS" => config.frontends.clone(),
+		"REDLIB_ENABLE_SYNC" => config.enable_sync.clone(),
+		"REDLIB_SYNC_STORAGE_PATH" => config.sync_storage_path.clone(),
+		"REDLIB_SYNC_MAX_STORAGE_MB" => config.sync_max_storage_mb.clone(),
+		"REDLIB_TRUSTED_PROXIES" => config.trusted_proxies.clone(),
+		"REDLIB_SHARE_CACHE_PATH" => config.share_cache_path.clone(),
+		"REDLIB_RESOLVE_IMGUR" => config.resolve_imgur.clone(),
+		"REDLIB_RESOLVE_REDGIFS" => config.resolve_redgifs.clone(),
+		"REDLIB_RESOLVE_STREAMABLE" => config.resolve_streamable.clone(),
+		"REDLIB_IMGUR_CLIENT_ID" => config.imgur_client_id.clone(),
+		"REDLIB_GEMINI_CERT" => config.gemini_cert.clone(),
+		"REDLIB_GEMINI_KEY" => config.gemini_key.clone(),
+		"REDLIB_GEMINI_ADDRESS" => config.gemini_address.clone(),
 		_ => None,
 	}
 }
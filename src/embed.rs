@@ -0,0 +1,195 @@
+//! Embedded players for link posts to YouTube, Vimeo, SoundCloud and Twitch.
+//! Reddit describes these with oembed data, including the player's iframe. We
+//! show the proxied thumbnail and title instead, and only load the player once
+//! the user asks for it, with the page's CSP opened up to just that player.
+
+use crate::outbound::{to_frontend, Frontends};
+use crate::utils::{format_url, Preferences};
+
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::LazyLock;
+use url::Url;
+
+static IFRAME_SRC: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"<iframe[^>]*\ssrc="([^"]+)""#).unwrap());
+
+/// A provider whose players can be embedded
+struct Provider {
+	name: &'static str,
+	/// Hosts Reddit's oembed data may point the player at
+	hosts: &'static [&'static str],
+	/// Host to load the player from instead, if the provider has a more private one
+	embed_host: Option<&'static str>,
+	/// Query parameters added to the player's URL
+	params: &'static [(&'static str, &'static str)],
+}
+
+const PROVIDERS: &[Provider] = &[
+	Provider {
+		name: "YouTube",
+		hosts: &["www.youtube.com", "youtube.com"],
+		embed_host: Some("www.youtube-nocookie.com"),
+		params: &[],
+	},
+	Provider {
+		name: "Vimeo",
+		hosts: &["player.vimeo.com"],
+		embed_host: None,
+		params: &[("dnt", "1")],
+	},
+	Provider {
+		name: "SoundCloud",
+		hosts: &["w.soundcloud.com"],
+		embed_host: None,
+		params: &[],
+	},
+	Provider {
+		name: "Twitch",
+		hosts: &["clips.twitch.tv", "player.twitch.tv"],
+		embed_host: None,
+		params: &[],
+	},
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Embed {
+	pub provider: String,
+	pub title: String,
+	pub author: String,
+	pub thumbnail: String,
+	pub width: i64,
+	pub height: i64,
+	/// The player's URL, only used once the user loads it
+	pub src: String,
+	pub loaded: bool,
+}
+
+impl Embed {
+	pub fn parse(data: &Value) -> Option<Self> {
+		let media = [&data["secure_media"], &data["media"], &data["crosspost_parent_list"][0]["secure_media"]]
+			.into_iter()
+			.find(|media| media["oembed"]["html"].is_string())?;
+		let oembed = &media["oembed"];
+
+		// Without raw_json, Reddit escapes the HTML once more
+		let html = oembed["html"].as_str()?.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"");
+		let src = IFRAME_SRC.captures(&html)?[1].replace("&amp;", "&");
+		let mut src = Url::parse(&src).ok()?;
+
+		let provider = PROVIDERS.iter().find(|provider| provider.hosts.contains(&src.host_str().unwrap_or_default()))?;
+		if provider.name == "YouTube" && !src.path().starts_with("/embed/") {
+			return None;
+		}
+		if let Some(host) = provider.embed_host {
+			src.set_host(Some(host)).ok()?;
+		}
+		for (key, value) in provider.params {
+			src.query_pairs_mut().append_pair(key, value);
+		}
+
+		let thumbnail = [&data["preview"]["images"][0]["source"]["url"], &data["thumbnail"]]
+			.into_iter()
+			.map(|url| format_url(url.as_str().unwrap_or_default()))
+			.find(|url| url.starts_with('/'))
+			.unwrap_or_default();
+
+		Some(Self {
+			provider: provider.name.to_string(),
+			title: oembed["title"].as_str().unwrap_or_default().to_string(),
+			author: oembed["author_name"].as_str().unwrap_or_default().to_string(),
+			thumbnail,
+			width: oembed["width"].as_i64().unwrap_or_default(),
+			height: oembed["height"].as_i64().unwrap_or_default(),
+			src: src.to_string(),
+			loaded: false,
+		})
+	}
+
+	/// Play YouTube videos through the user's or the instance's Invidious
+	/// front-end, if one is configured
+	pub fn use_frontend(&mut self, prefs: &Preferences) {
+		if self.provider == "YouTube" {
+			self.src = to_frontend(&self.src, &Frontends::parse(&prefs.frontends).unwrap_or_default());
+		}
+	}
+
+	/// Load the player on a page served from `host`. Twitch only plays inside
+	/// pages it's told about with `parent`, which Reddit sets to its own domain.
+	pub fn load(&mut self, host: &str) {
+		let Ok(mut src) = Url::parse(&self.src) else {
+			return;
+		};
+		if src.query_pairs().any(|(key, _)| key == "parent") {
+			let host = host.split(':').next().unwrap_or_default();
+			let pairs: Vec<(String, String)> = src
+				.query_pairs()
+				.map(|(key, value)| {
+					if key == "parent" {
+						(key.into_owned(), host.to_string())
+					} else {
+						(key.into_owned(), value.into_owned())
+					}
+				})
+				.collect();
+			src.query_pairs_mut().clear().extend_pairs(pairs);
+		}
+		self.src = src.to_string();
+		self.loaded = true;
+	}
+
+	/// The page's CSP, letting the player's origin be framed
+	pub fn csp(&self, policy: &str) -> String {
+		let origin = Url::parse(&self.src).map(|src| src.origin().ascii_serialization()).unwrap_or_default();
+		format!("{policy} frame-src {origin};")
+	}
+
+	/// The host the player loads from, for telling the user who they'll connect to
+	pub fn host(&self) -> String {
+		Url::parse(&self.src).ok().and_then(|src| src.host_str().map(String::from)).unwrap_or_default()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_embed() {
+		let data: Value = serde_json::from_str(
+			r#"{"url":"https://www.youtube.com/watch?v=dQw4w9WgXcQ","thumbnail":"https://b.thumbs.redditmedia.com/abc.jpg",
+			"secure_media":{"type":"youtube.com","oembed":{"provider_name":"YouTube","title":"Never Gonna Give You Up","author_name":"Rick Astley","width":356,"height":200,
+			"html":"<iframe width=\"356\" height=\"200\" src=\"https://www.youtube.com/embed/dQw4w9WgXcQ?feature=oembed&enablejsapi=1\" frameborder=\"0\" allowfullscreen></iframe>",
+			"thumbnail_url":"https://i.ytimg.com/vi/dQw4w9WgXcQ/hqdefault.jpg"}}}"#,
+		)
+		.unwrap();
+		let embed = Embed::parse(&data).unwrap();
+		assert_eq!(embed.provider, "YouTube");
+		assert_eq!(embed.title, "Never Gonna Give You Up");
+		assert_eq!(embed.src, "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ?feature=oembed&enablejsapi=1");
+		assert_eq!(embed.thumbnail, "/thumb/b/abc.jpg");
+		assert!(!embed.loaded);
+		assert_eq!(embed.csp("default-src 'none';"), "default-src 'none'; frame-src https://www.youtube-nocookie.com;");
+
+		let mut invidious = embed.clone();
+		invidious.use_frontend(&Preferences {
+			frontends: "youtube:yewtu.be".to_string(),
+			..Default::default()
+		});
+		assert_eq!(invidious.src, "https://yewtu.be/embed/dQw4w9WgXcQ?feature=oembed&enablejsapi=1");
+		assert_eq!(invidious.host(), "yewtu.be");
+
+		let escaped: Value = serde_json::from_str(
+			r#"{"secure_media":{"type":"clips.twitch.tv","oembed":{"provider_name":"Twitch","title":"Clip",
+			"html":"&lt;iframe src=&quot;https://clips.twitch.tv/embed?clip=FunnyClip&amp;parent=meta.redditmedia.com&amp;autoplay=false&quot;&gt;&lt;/iframe&gt;"}}}"#,
+		)
+		.unwrap();
+		let mut embed = Embed::parse(&escaped).unwrap();
+		embed.load("redlib.example.com:8080");
+		assert_eq!(embed.src, "https://clips.twitch.tv/embed?clip=FunnyClip&parent=redlib.example.com&autoplay=false");
+		assert!(embed.loaded);
+
+		let unknown: Value = serde_json::from_str(r#"{"secure_media":{"oembed":{"html":"<iframe src=\"https://evil.example.com/player\"></iframe>"}}}"#).unwrap();
+		assert_eq!(Embed::parse(&unknown), None);
+	}
+}
@@ -0,0 +1,515 @@
+//! Feeds for subreddits, users, searches, comment threads and live threads.
+//! Handlers describe their content as a [`Feed`], which is rendered as RSS 2.0,
+//! Atom or JSON Feed 1.1 depending on the extension of the requested path.
+
+use crate::config::get_setting;
+use crate::outbound;
+use crate::utils::{filter_posts, format_url, get_filters, get_post_url, param, sfw_only, to_absolute_url, Comment, Post, Preferences};
+
+use chrono::DateTime;
+use hyper::{header::CONTENT_TYPE, Body, Request, Response};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+/// Root-relative `href` and `src` attributes, which feed readers can't resolve
+static RELATIVE_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(href|src)="/([^/"])"#).unwrap());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	Rss,
+	Atom,
+	Json,
+}
+
+impl Format {
+	/// Pick the format from the extension of a feed path like `/r/rust.atom`
+	pub fn from_path(path: &str) -> Self {
+		match path.rsplit_once('.').map(|(_, extension)| extension) {
+			Some("atom") => Self::Atom,
+			Some("json") => Self::Json,
+			_ => Self::Rss,
+		}
+	}
+
+	const fn content_type(self) -> &'static str {
+		match self {
+			Self::Rss => "application/rss+xml",
+			Self::Atom => "application/atom+xml",
+			Self::Json => "application/feed+json",
+		}
+	}
+}
+
+pub struct Feed {
+	pub title: String,
+	pub description: String,
+	/// Path of the page the feed mirrors, such as `/r/rust`
+	pub link: String,
+	pub entries: Vec<Entry>,
+}
+
+/// An item in a feed. Paths on this instance, including those in HTML, are
+/// made absolute with `REDLIB_FULL_URL` when the feed is rendered.
+pub struct Entry {
+	/// Unique, stable ID; an absolute URL where possible
+	pub id: String,
+	pub title: String,
+	/// What the entry points at, which for link posts is the linked page
+	pub link: String,
+	pub author: String,
+	/// HTML content
+	pub content: String,
+	/// Short HTML summary for readers that don't show the content
+	pub summary: Option<String>,
+	/// Main image, used by JSON Feed
+	pub image: Option<String>,
+	pub published: i64,
+	pub enclosures: Vec<Enclosure>,
+}
+
+#[derive(Serialize)]
+pub struct Enclosure {
+	pub url: String,
+	pub mime_type: String,
+}
+
+impl Entry {
+	pub fn from_post(post: &Post) -> Self {
+		let permalink = to_absolute_url(&post.permalink);
+		let content = post.body.clone();
+
+		// Only the first image of a gallery fits in an RSS enclosure, so mention the rest
+		let (summary, content) = if post.post_type == "gallery" && post.gallery.len() > 1 {
+			let summary = format!("<a href=\"{permalink}\">Gallery with {} images</a>", post.gallery.len());
+			let content = format!("{summary}<br/>{content}");
+			(summary, content)
+		} else {
+			(format!("<a href=\"{permalink}\">Comments</a>"), content)
+		};
+
+		let image = match post.post_type.as_str() {
+			"image" => Some(&post.media.url),
+			"gallery" => post.gallery.first().map(|media| &media.url),
+			"gif" | "video" => Some(&post.media.poster),
+			_ => None,
+		};
+
+		// Comments in user listings have no title of their own
+		let title = if post.title.is_empty() && !post.link_title.is_empty() {
+			format!("Comment on \"{}\"", post.link_title)
+		} else {
+			post.title.clone()
+		};
+
+		Self {
+			id: permalink,
+			title,
+			link: format_url(&get_post_url(post)),
+			author: post.author.name.clone(),
+			content,
+			summary: Some(summary),
+			image: image.filter(|url| !url.is_empty()).cloned(),
+			published: post.created_ts as i64,
+			enclosures: enclosures(post),
+		}
+	}
+}
+
+impl Entry {
+	pub fn from_comment(comment: &Comment, post_title: &str) -> Self {
+		let permalink = format!("{}{}/", comment.post_link, comment.id);
+
+		Self {
+			id: to_absolute_url(&permalink),
+			title: format!("u/{} on \"{post_title}\"", comment.author.name),
+			link: permalink,
+			author: comment.author.name.clone(),
+			content: comment.body.clone(),
+			summary: None,
+			image: None,
+			published: comment.created_ts,
+			enclosures: Vec::new(),
+		}
+	}
+}
+
+impl Feed {
+	/// Render the feed in the format picked by the request's path, with
+	/// outbound links rewritten according to the preferences sent with it
+	pub fn respond(mut self, req: &Request<Body>) -> Response<Body> {
+		let prefs = Preferences::new(req);
+		for entry in &mut self.entries {
+			entry.link = outbound::rewrite_url(&entry.link, &prefs);
+			entry.content = outbound::rewrite_html(&entry.content, &prefs);
+		}
+
+		let format = Format::from_path(req.uri().path());
+		let self_url = to_absolute_url(req.uri().path_and_query().map_or("/", |path| path.as_str()));
+
+		Response::builder()
+			.header(CONTENT_TYPE, format.content_type())
+			.body(self.render(format, &self_url).into())
+			.unwrap_or_default()
+	}
+
+	pub fn render(&self, format: Format, self_url: &str) -> String {
+		match format {
+			Format::Rss => self.to_rss(),
+			Format::Atom => self.to_atom(self_url),
+			Format::Json => self.to_json(self_url),
+		}
+	}
+
+	fn to_rss(&self) -> String {
+		rss::ChannelBuilder::default()
+			.title(&self.title)
+			.description(&self.description)
+			.link(absolute(&self.link))
+			.items(
+				self
+					.entries
+					.iter()
+					.map(|entry| rss::Item {
+						title: Some(entry.title.clone()),
+						link: Some(absolute(&entry.link)),
+						guid: Some(rss::Guid {
+							value: entry.id.clone(),
+							permalink: entry.id.starts_with("http"),
+						}),
+						author: Some(entry.author.clone()),
+						description: entry.summary.as_deref().map(absolute_urls),
+						content: Some(absolute_urls(&entry.content)),
+						pub_date: Some(timestamp(entry.published).to_rfc2822()),
+						// RSS only allows one enclosure per item
+						enclosure: entry.enclosures.first().map(|enclosure| rss::Enclosure {
+							url: absolute(&enclosure.url),
+							length: "0".to_string(),
+							mime_type: enclosure.mime_type.clone(),
+						}),
+						..Default::default()
+					})
+					.collect::<Vec<_>>(),
+			)
+			.build()
+			.to_string()
+	}
+
+	fn to_atom(&self, self_url: &str) -> String {
+		use atom_syndication::{Content, Link, Person, Text};
+
+		let link = |href: &str, rel: &str, mime_type: &str| Link {
+			href: href.to_string(),
+			rel: rel.to_string(),
+			mime_type: Some(mime_type.to_string()),
+			..Default::default()
+		};
+
+		let entries = self
+			.entries
+			.iter()
+			.map(|entry| {
+				let mut links = vec![link(&absolute(&entry.link), "alternate", "text/html")];
+				links.extend(entry.enclosures.iter().map(|enclosure| link(&absolute(&enclosure.url), "enclosure", &enclosure.mime_type)));
+
+				atom_syndication::Entry {
+					title: Text::plain(entry.title.clone()),
+					id: entry.id.clone(),
+					updated: timestamp(entry.published),
+					published: Some(timestamp(entry.published)),
+					authors: vec![Person {
+						name: entry.author.clone(),
+						..Default::default()
+					}],
+					links,
+					summary: entry.summary.as_deref().map(|summary| Text::html(absolute_urls(summary))),
+					content: Some(Content {
+						value: Some(absolute_urls(&entry.content)),
+						content_type: Some("html".to_string()),
+						..Default::default()
+					}),
+					..Default::default()
+				}
+			})
+			.collect();
+
+		atom_syndication::Feed {
+			title: Text::plain(self.title.clone()),
+			id: self_url.to_string(),
+			updated: timestamp(self.updated()),
+			subtitle: Some(Text::plain(self.description.clone())).filter(|subtitle| !subtitle.value.is_empty()),
+			links: vec![link(&absolute(&self.link), "alternate", "text/html"), link(self_url, "self", Format::Atom.content_type())],
+			entries,
+			..Default::default()
+		}
+		.to_string()
+	}
+
+	fn to_json(&self, self_url: &str) -> String {
+		#[derive(Serialize)]
+		struct JsonFeed<'a> {
+			version: &'static str,
+			title: &'a str,
+			home_page_url: String,
+			feed_url: &'a str,
+			#[serde(skip_serializing_if = "str::is_empty")]
+			description: &'a str,
+			items: Vec<JsonFeedItem<'a>>,
+		}
+
+		#[derive(Serialize)]
+		struct JsonFeedItem<'a> {
+			id: &'a str,
+			url: String,
+			title: &'a str,
+			content_html: String,
+			#[serde(skip_serializing_if = "Option::is_none")]
+			image: Option<String>,
+			date_published: String,
+			authors: [JsonFeedAuthor<'a>; 1],
+			#[serde(skip_serializing_if = "Vec::is_empty")]
+			attachments: Vec<Enclosure>,
+		}
+
+		#[derive(Serialize)]
+		struct JsonFeedAuthor<'a> {
+			name: &'a str,
+		}
+
+		let feed = JsonFeed {
+			version: "https://jsonfeed.org/version/1.1",
+			title: &self.title,
+			home_page_url: absolute(&self.link),
+			feed_url: self_url,
+			description: &self.description,
+			items: self
+				.entries
+				.iter()
+				.map(|entry| JsonFeedItem {
+					id: &entry.id,
+					url: absolute(&entry.link),
+					title: &entry.title,
+					content_html: absolute_urls(&entry.content),
+					image: entry.image.as_deref().map(absolute),
+					date_published: timestamp(entry.published).to_rfc3339(),
+					authors: [JsonFeedAuthor { name: &entry.author }],
+					attachments: entry
+						.enclosures
+						.iter()
+						.map(|enclosure| Enclosure {
+							url: absolute(&enclosure.url),
+							mime_type: enclosure.mime_type.clone(),
+						})
+						.collect(),
+				})
+				.collect(),
+		};
+
+		serde_json::to_string(&feed).unwrap_or_default()
+	}
+
+	/// When the newest entry was published, or now for an empty feed
+	fn updated(&self) -> i64 {
+		self
+			.entries
+			.iter()
+			.map(|entry| entry.published)
+			.max()
+			.unwrap_or_else(|| time::OffsetDateTime::now_utc().unix_timestamp())
+	}
+}
+
+/// Filters for a feed. Feed readers don't send cookies, so on top of the
+/// user's (or the instance's default) filters more can be passed in the
+/// query, like `?filters=pics+u_spez`.
+pub fn filters(req: &Request<Body>) -> HashSet<String> {
+	let mut filters = get_filters(req);
+	if let Some(extra) = param(&format!("?{}", req.uri().query().unwrap_or_default()), "filters") {
+		// An unescaped `+` in a query string decodes to a space
+		filters.extend(extra.split([' ', '+', ',']).filter(|filter| !filter.is_empty()).map(String::from));
+	}
+	filters
+}
+
+/// Drop posts from filtered subreddits and users, and NSFW posts on SFW-only instances
+pub fn retain_visible(req: &Request<Body>, posts: &mut Vec<Post>) {
+	filter_posts(posts, &filters(req));
+	if sfw_only() {
+		posts.retain(|post| !post.flags.nsfw);
+	}
+}
+
+/// Images for image posts and galleries, and the video itself for gifs and videos
+fn enclosures(post: &Post) -> Vec<Enclosure> {
+	let enclosure = |url: &str, mime_type: &str| Enclosure {
+		url: url.to_string(),
+		mime_type: mime_type.to_string(),
+	};
+
+	let enclosures = match post.post_type.as_str() {
+		"image" => vec![enclosure(&post.media.url, get_mime_type(&post.media.url))],
+		"gallery" => post.gallery.iter().map(|media| enclosure(&media.url, get_mime_type(&media.url))).collect(),
+		// Reddit serves both as MP4, even when the URL ends in .gif
+		"gif" | "video" => vec![enclosure(&post.media.url, "video/mp4")],
+		_ => Vec::new(),
+	};
+
+	enclosures.into_iter().filter(|enclosure| !enclosure.url.is_empty()).collect()
+}
+
+/// Determines the MIME type based on file extension in a URL.
+/// Handles both absolute and relative URLs with query parameters.
+fn get_mime_type(url: &str) -> &'static str {
+	// Extract the path component, removing query parameters
+	let path = url.split('?').next().unwrap_or(url);
+
+	// Get the file extension (everything after the last dot)
+	let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+
+	match extension.as_str() {
+		"jpg" | "jpeg" => "image/jpeg",
+		"png" => "image/png",
+		"gif" => "image/gif",
+		"webp" => "image/webp",
+		"svg" => "image/svg+xml",
+		"mp4" => "video/mp4",
+		_ => "application/octet-stream",
+	}
+}
+
+/// Make a path on this instance absolute using `REDLIB_FULL_URL`
+fn absolute(url: &str) -> String {
+	if url.starts_with('/') && !url.starts_with("//") {
+		to_absolute_url(url)
+	} else {
+		url.to_string()
+	}
+}
+
+/// Make links and images in HTML content absolute using `REDLIB_FULL_URL`
+fn absolute_urls(html: &str) -> String {
+	match get_setting("REDLIB_FULL_URL").filter(|base| !base.is_empty()) {
+		Some(base) => RELATIVE_URL_REGEX.replace_all(html, format!("$1=\"{base}/$2").as_str()).to_string(),
+		None => html.to_string(),
+	}
+}
+
+fn timestamp(ts: i64) -> DateTime<chrono::FixedOffset> {
+	DateTime::from_timestamp(ts, 0).unwrap_or_default().fixed_offset()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::parse_post;
+	use serde_json::json;
+
+	fn feed(entries: Vec<Entry>) -> Feed {
+		Feed {
+			title: "r/pics".to_string(),
+			description: "Pictures".to_string(),
+			link: "/r/pics".to_string(),
+			entries,
+		}
+	}
+
+	#[test]
+	fn test_query_filters() {
+		let req = Request::builder().uri("/r/popular.rss?filters=pics+u_spez,funny").body(Body::empty()).unwrap();
+		let filters = filters(&req);
+		assert!(filters.contains("pics"));
+		assert!(filters.contains("u_spez"));
+		assert!(filters.contains("funny"));
+		assert_eq!(filters.len(), 3);
+	}
+
+	#[test]
+	fn test_format_from_path() {
+		assert_eq!(Format::from_path("/r/rust.rss"), Format::Rss);
+		assert_eq!(Format::from_path("/r/rust.atom"), Format::Atom);
+		assert_eq!(Format::from_path("/user/spez.json"), Format::Json);
+		assert_eq!(Format::from_path("/live/abc"), Format::Rss);
+	}
+
+	#[tokio::test]
+	async fn test_post_enclosures() {
+		let gallery = parse_post(&json!({
+			"data": {
+				"id": "abc",
+				"title": "Two pictures",
+				"author": "someone",
+				"permalink": "/r/pics/comments/abc/two_pictures/",
+				"created_utc": 1700000000.0,
+				"is_gallery": true,
+				"gallery_data": { "items": [{ "media_id": "one" }, { "media_id": "two" }] },
+				"media_metadata": {
+					"one": { "m": "image/jpg", "s": { "u": "https://i.redd.it/one.jpg", "x": 10, "y": 10 } },
+					"two": { "m": "image/gif", "s": { "gif": "https://i.redd.it/two.gif", "x": 10, "y": 10 } }
+				}
+			}
+		}))
+		.await;
+		let entry = Entry::from_post(&gallery);
+		assert_eq!(entry.enclosures.len(), 2);
+		assert_eq!(entry.enclosures[0].mime_type, "image/jpeg");
+		assert_eq!(entry.enclosures[1].mime_type, "image/gif");
+		assert_eq!(entry.image.as_deref(), Some("/img/one.jpg"));
+		assert!(entry.summary.unwrap().contains("Gallery with 2 images"));
+
+		let video = parse_post(&json!({
+			"data": {
+				"id": "def",
+				"title": "A video",
+				"author": "someone",
+				"permalink": "/r/videos/comments/def/a_video/",
+				"created_utc": 1700000000.0,
+				"secure_media": { "reddit_video": { "fallback_url": "https://v.redd.it/foo/DASH_720.mp4?source=fallback", "is_gif": false } },
+				"preview": { "images": [{ "source": { "url": "https://preview.redd.it/poster.jpg", "width": 10, "height": 10 } }] }
+			}
+		}))
+		.await;
+		let entry = Entry::from_post(&video);
+		assert_eq!(entry.enclosures.len(), 1);
+		assert_eq!(entry.enclosures[0].url, "/vid/foo/720.mp4");
+		assert_eq!(entry.enclosures[0].mime_type, "video/mp4");
+		assert_eq!(entry.image.as_deref(), Some("/preview/pre/poster.jpg"));
+	}
+
+	#[test]
+	fn test_render_formats() {
+		let feed = feed(vec![Entry {
+			id: "/r/pics/comments/abc/two_pictures/".to_string(),
+			title: "Two pictures".to_string(),
+			link: "/r/pics/comments/abc/two_pictures/".to_string(),
+			author: "someone".to_string(),
+			content: "<p>Hello</p>".to_string(),
+			summary: None,
+			image: None,
+			published: 1700000000,
+			enclosures: vec![
+				Enclosure {
+					url: "/img/one.jpg".to_string(),
+					mime_type: "image/jpeg".to_string(),
+				},
+				Enclosure {
+					url: "/img/two.png".to_string(),
+					mime_type: "image/png".to_string(),
+				},
+			],
+		}]);
+
+		let rss = feed.render(Format::Rss, "/r/pics.rss");
+		assert_eq!(rss.matches("<enclosure ").count(), 1);
+
+		let atom = feed.render(Format::Atom, "/r/pics.atom");
+		let parsed = atom.parse::<atom_syndication::Feed>().unwrap();
+		assert_eq!(parsed.entries[0].links.iter().filter(|link| link.rel == "enclosure").count(), 2);
+		assert_eq!(parsed.updated.timestamp(), 1700000000);
+
+		let json: serde_json::Value = serde_json::from_str(&feed.render(Format::Json, "/r/pics.json")).unwrap();
+		assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+		assert_eq!(json["items"][0]["attachments"][1]["mime_type"], "image/png");
+		assert_eq!(json["items"][0]["date_published"], "2023-11-14T22:13:20+00:00");
+	}
+}
@@ -0,0 +1,695 @@
+//! A Gemini front-end, serving the front page, subreddits, posts with their
+//! comments, user pages and search as gemtext. It runs next to the HTTP server
+//! when `REDLIB_GEMINI_CERT` and `REDLIB_GEMINI_KEY` are set, and gets its data
+//! the same way the HTML pages do, with the instance's default settings since
+//! Gemini has no cookies. Media is linked through the HTTP proxy.
+
+use crate::blocks::{self, Block, Marker};
+use crate::client::json;
+use crate::config::get_setting;
+use crate::outbound;
+use crate::post::parse_comments;
+use crate::subreddit::subreddit;
+use crate::user::user;
+use crate::utils::{filter_posts, get_filters, parse_post, setting, sfw_only, should_be_nsfw_gated, Comment, Post, Preferences};
+
+use boring2::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod};
+use hyper::{Body, Request};
+use log::{error, info};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_boring2::SslStream;
+use url::Url;
+
+const DEFAULT_ADDRESS: &str = "[::]:1965";
+const POST_SORTS: [&str; 5] = ["hot", "new", "top", "rising", "controversial"];
+const USER_LISTINGS: [&str; 3] = ["overview", "submitted", "comments"];
+
+/// Requests are a URL of at most 1024 bytes and a CRLF
+const MAX_REQUEST: usize = 1026;
+
+/// A Gemini response: a two-digit status, its meta line and, on success, a gemtext body
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+	pub status: u8,
+	pub meta: String,
+	pub body: String,
+}
+
+impl Response {
+	fn page(body: String) -> Self {
+		Self {
+			status: 20,
+			meta: "text/gemini; charset=utf-8".to_string(),
+			body,
+		}
+	}
+
+	fn status(status: u8, meta: &str) -> Self {
+		Self {
+			status,
+			meta: meta.to_string(),
+			body: String::new(),
+		}
+	}
+
+	fn to_bytes(&self) -> Vec<u8> {
+		format!("{} {}\r\n{}", self.status, self.meta, self.body).into_bytes()
+	}
+}
+
+/// The pages the front-end serves, parsed from a request's path
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+	Front,
+	Subreddit { sub: String, sort: String },
+	Post { id: String, comment: String },
+	User { name: String, listing: String },
+	Search { sub: String },
+}
+
+impl Route {
+	/// Parse a path, and the `after` of a later page if it ends in `/after/:id`
+	fn parse(path: &str) -> Option<(Self, String)> {
+		let mut segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+		let mut after = String::new();
+		if segments.len() >= 2 && segments[segments.len() - 2] == "after" {
+			after = segments.pop()?.to_string();
+			segments.pop();
+		}
+
+		let route = match segments.as_slice() {
+			[] => Self::Front,
+			["r", sub] => Self::Subreddit {
+				sub: (*sub).to_string(),
+				sort: String::new(),
+			},
+			["r", sub, sort] if POST_SORTS.contains(sort) => Self::Subreddit {
+				sub: (*sub).to_string(),
+				sort: (*sort).to_string(),
+			},
+			["r", sub, "search"] => Self::Search { sub: (*sub).to_string() },
+			["search"] => Self::Search { sub: String::new() },
+			["r", _, "comments", id, rest @ ..] | ["comments", id, rest @ ..] => Self::Post {
+				id: (*id).to_string(),
+				comment: rest.get(1).map(|comment| (*comment).to_string()).unwrap_or_default(),
+			},
+			["u" | "user", name] => Self::User {
+				name: (*name).to_string(),
+				listing: "overview".to_string(),
+			},
+			["u" | "user", name, listing] if USER_LISTINGS.contains(listing) => Self::User {
+				name: (*name).to_string(),
+				listing: (*listing).to_string(),
+			},
+			_ => return None,
+		};
+		Some((route, after))
+	}
+}
+
+/// Listen for Gemini requests if the instance has a certificate for it
+pub async fn listen() {
+	let (Some(cert), Some(key)) = (get_setting("REDLIB_GEMINI_CERT"), get_setting("REDLIB_GEMINI_KEY")) else {
+		return;
+	};
+	let address = get_setting("REDLIB_GEMINI_ADDRESS").unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+
+	let acceptor = match acceptor(&cert, &key) {
+		Ok(acceptor) => Arc::new(acceptor),
+		Err(e) => {
+			error!("[Gemini] Couldn't load the certificate: {e}");
+			return;
+		}
+	};
+	let listener = match TcpListener::bind(&address).await {
+		Ok(listener) => listener,
+		Err(e) => {
+			error!("[Gemini] Couldn't listen on {address}: {e}");
+			return;
+		}
+	};
+	info!("[Gemini] Listening on {address}");
+
+	loop {
+		let Ok((tcp, _)) = listener.accept().await else {
+			continue;
+		};
+		let acceptor = acceptor.clone();
+		tokio::spawn(async move {
+			let result = async {
+				let ssl = Ssl::new(acceptor.context()).map_err(|e| e.to_string())?;
+				let mut stream = SslStream::new(ssl, tcp).map_err(|e| e.to_string())?;
+				Pin::new(&mut stream).accept().await.map_err(|e| e.to_string())?;
+				handle(&mut stream).await
+			};
+			if let Err(e) = tokio::time::timeout(Duration::from_secs(30), result).await.unwrap_or_else(|_| Err("timed out".to_string())) {
+				info!("[Gemini] Connection failed: {e}");
+			}
+		});
+	}
+}
+
+fn acceptor(cert: &str, key: &str) -> Result<SslAcceptor, String> {
+	let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).map_err(|e| e.to_string())?;
+	builder.set_certificate_chain_file(cert).map_err(|e| e.to_string())?;
+	builder.set_private_key_file(key, SslFiletype::PEM).map_err(|e| e.to_string())?;
+	builder.check_private_key().map_err(|e| e.to_string())?;
+	Ok(builder.build())
+}
+
+async fn handle<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<(), String> {
+	let mut request = Vec::new();
+	let mut buf = [0; MAX_REQUEST];
+	while !request.ends_with(b"\r\n") {
+		let read = stream.read(&mut buf).await.map_err(|e| e.to_string())?;
+		if read == 0 {
+			break;
+		}
+		request.extend_from_slice(&buf[..read]);
+		if request.len() > MAX_REQUEST {
+			break;
+		}
+	}
+
+	let response = match std::str::from_utf8(&request) {
+		Ok(request) if request.len() <= MAX_REQUEST && request.ends_with("\r\n") => respond(request.trim_end()).await,
+		_ => Response::status(59, "Bad request"),
+	};
+	stream.write_all(&response.to_bytes()).await.map_err(|e| e.to_string())?;
+	stream.shutdown().await.map_err(|e| e.to_string())
+}
+
+/// Answer a request for a `gemini://` URL
+pub async fn respond(request: &str) -> Response {
+	let Ok(url) = Url::parse(request) else {
+		return Response::status(59, "Bad request");
+	};
+	if url.scheme() != "gemini" {
+		return Response::status(53, "Only gemini:// URLs are served here");
+	}
+	let Some((route, after)) = Route::parse(url.path()) else {
+		return Response::status(51, "Not found");
+	};
+
+	let page = Page {
+		req: Request::builder().uri("/").body(Body::empty()).unwrap_or_default(),
+		http: http_base(url.host_str().unwrap_or_default()),
+	};
+	let query = url.query().map(|query| percent_decode_str(query).decode_utf8_lossy().to_string()).unwrap_or_default();
+
+	let result = match route {
+		Route::Front => page.front(&after).await,
+		Route::Subreddit { sub, sort } => page.subreddit(&sub, &sort, &after).await,
+		Route::Post { id, comment } => page.post(&id, &comment).await,
+		Route::User { name, listing } => page.user(&name, &listing, &after).await,
+		Route::Search { .. } if query.is_empty() => return Response::status(10, "Search Reddit"),
+		Route::Search { sub } => page.search(&sub, &query, &after).await,
+	};
+	result.unwrap_or_else(|e| e)
+}
+
+/// Where the HTTP front-end is, for media and pages Gemini doesn't serve
+fn http_base(host: &str) -> String {
+	get_setting("REDLIB_FULL_URL").map_or_else(|| format!("https://{host}"), |url| url.trim_end_matches('/').to_string())
+}
+
+/// Translate errors from Reddit like the HTML pages do
+fn upstream_error(msg: &str, name: &str) -> Response {
+	match msg {
+		"quarantined" | "gated" => Response::status(50, &format!("{name} is {msg}. Opt in from the web interface first")),
+		"private" => Response::status(50, &format!("{name} is a private community")),
+		"banned" => Response::status(51, &format!("{name} has been banned from Reddit")),
+		_ => Response::status(40, msg),
+	}
+}
+
+fn nsfw_error() -> Response {
+	if sfw_only() {
+		Response::status(50, "This instance only shows SFW content")
+	} else {
+		Response::status(50, "This content is NSFW, which this instance hides by default")
+	}
+}
+
+struct Page {
+	/// Stands in for the HTTP request the shared code reads settings from, so the instance defaults apply
+	req: Request<Body>,
+	http: String,
+}
+
+impl Page {
+	async fn front(&self, after: &str) -> Result<Response, Response> {
+		let front_page = setting(&self.req, "front_page");
+		let subscriptions = setting(&self.req, "subscriptions");
+		// A subscription group chosen as the front page, or the subscriptions if it's gone
+		let group = front_page
+			.strip_prefix("g/")
+			.and_then(|name| Preferences::new(&self.req).group(name).map(|group| group.joined()));
+		let sub = if let Some(group) = group {
+			group
+		} else if front_page == "default" || front_page.is_empty() || front_page.starts_with("g/") {
+			if subscriptions.is_empty() {
+				"popular".to_string()
+			} else {
+				subscriptions
+			}
+		} else {
+			front_page
+		};
+		self.subreddit(&sub, "", after).await
+	}
+
+	async fn subreddit(&self, sub: &str, sort: &str, after: &str) -> Result<Response, Response> {
+		let sort = if sort.is_empty() {
+			Some(setting(&self.req, "post_sort")).filter(|sort| !sort.is_empty()).unwrap_or_else(|| "hot".to_string())
+		} else {
+			sort.to_string()
+		};
+		let mut out = format!("# r/{sub}\n\n");
+
+		if !(sub.contains('+') || sub == "popular" || sub == "all") {
+			let about = subreddit(sub, false).await.map_err(|msg| upstream_error(&msg, &format!("r/{sub}")))?;
+			if about.nsfw && should_be_nsfw_gated(&self.req, "") {
+				return Err(nsfw_error());
+			}
+			if !about.title.is_empty() {
+				out.push_str(&format!("{}\n", about.title));
+			}
+			if !about.description.is_empty() {
+				out.push_str(&format!("{}\n", about.description));
+			}
+			out.push_str(&format!("{} members\n\n", about.members.0));
+		}
+
+		for other in POST_SORTS {
+			if other != sort {
+				out.push_str(&format!("=> /r/{sub}/{other} Sort by {other}\n"));
+			}
+		}
+		out.push_str(&format!("=> /r/{sub}/search Search r/{sub}\n\n"));
+
+		let filters = get_filters(&self.req);
+		if sub.split('+').all(|s| filters.contains(s)) {
+			out.push_str("(Content from this subreddit has been filtered)\n");
+			return Ok(Response::page(out));
+		}
+
+		let path = format!("/r/{}/{sort}.json?after={after}&raw_json=1", sub.replace('+', "%2B"));
+		out.push_str(
+			&self
+				.listing(&path, &format!("/r/{sub}/{sort}"), "", sort == "new")
+				.await
+				.map_err(|msg| upstream_error(&msg, &format!("r/{sub}")))?,
+		);
+		Ok(Response::page(out))
+	}
+
+	async fn post(&self, id: &str, comment: &str) -> Result<Response, Response> {
+		let thread = if comment.is_empty() { String::new() } else { format!("/_/{comment}") };
+		let sort = setting(&self.req, "comment_sort");
+		let sort = if sort.is_empty() { String::new() } else { format!("&sort={sort}") };
+		let response = json(format!("/comments/{id}{thread}.json?raw_json=1{sort}"), false)
+			.await
+			.map_err(|msg| upstream_error(&msg, "This post's subreddit"))?;
+
+		let post = parse_post(&response[0]["data"]["children"][0]).await;
+		if post.nsfw && should_be_nsfw_gated(&self.req, "") {
+			return Err(nsfw_error());
+		}
+		let comments = parse_comments(&response[1], &post.permalink, &post.author.name, comment, &get_filters(&self.req), &self.req);
+
+		let mut out = format!("# {}\n\n", post.title);
+		out.push_str(&format!(
+			"{} points · {}% upvoted · u/{} · {}{}\n",
+			post.score.0,
+			post.upvote_ratio,
+			post.author.name,
+			post.rel_time,
+			if post.flags.nsfw { " · NSFW" } else { "" }
+		));
+		out.push_str(&format!("=> /r/{0} r/{0}\n", post.community));
+		out.push_str(&self.media(&post));
+		let body = self.gemtext(&post.body);
+		if !body.is_empty() {
+			out.push('\n');
+			out.push_str(&body);
+		}
+
+		out.push_str(&format!("\n## {} comments\n\n", post.comments.0));
+		if !comment.is_empty() {
+			out.push_str(&format!("=> {} View all comments\n\n", post.permalink));
+		}
+		for comment in &comments {
+			self.comment(&mut out, comment, 0);
+		}
+		Ok(Response::page(out))
+	}
+
+	async fn user(&self, name: &str, listing: &str, after: &str) -> Result<Response, Response> {
+		let about = user(name).await.map_err(|msg| upstream_error(&msg, &format!("u/{name}")))?;
+		if about.nsfw && should_be_nsfw_gated(&self.req, "") {
+			return Err(nsfw_error());
+		}
+
+		let mut out = format!("# u/{name}\n\n");
+		if !about.title.is_empty() && about.title != name {
+			out.push_str(&format!("{}\n", about.title));
+		}
+		if !about.description.is_empty() {
+			out.push_str(&format!("{}\n", about.description));
+		}
+		out.push_str(&format!("{} karma · joined {}\n\n", about.karma, about.created));
+		for other in USER_LISTINGS {
+			if other != listing {
+				out.push_str(&format!("=> /u/{name}/{other} {}{}\n", other[..1].to_uppercase(), &other[1..]));
+			}
+		}
+		out.push('\n');
+
+		if get_filters(&self.req).contains(&["u_", name].concat()) {
+			out.push_str("(Content from this user has been filtered)\n");
+			return Ok(Response::page(out));
+		}
+
+		let path = format!("/user/{name}/{listing}.json?after={after}&raw_json=1");
+		out.push_str(
+			&self
+				.listing(&path, &format!("/u/{name}/{listing}"), "", false)
+				.await
+				.map_err(|msg| upstream_error(&msg, &format!("u/{name}")))?,
+		);
+		Ok(Response::page(out))
+	}
+
+	async fn search(&self, sub: &str, query: &str, after: &str) -> Result<Response, Response> {
+		let (title, base, restrict) = if sub.is_empty() {
+			("Reddit".to_string(), String::new(), "")
+		} else {
+			(format!("r/{sub}"), format!("/r/{}", sub.replace('+', "%2B")), "&restrict_sr=on")
+		};
+		let nsfw_results = if should_be_nsfw_gated(&self.req, "") { "" } else { "&include_over_18=on" };
+		let path = format!(
+			"{base}/search.json?q={}{restrict}&after={after}{nsfw_results}&raw_json=1",
+			utf8_percent_encode(query, NON_ALPHANUMERIC)
+		);
+
+		let mut out = format!("# Searching {title} for \"{query}\"\n\n");
+		out.push_str(&format!("=> {base}/search New search\n\n"));
+		// Later pages keep the query
+		let query = format!("?{}", utf8_percent_encode(query, NON_ALPHANUMERIC));
+		out.push_str(
+			&self
+				.listing(&path, &format!("{base}/search"), &query, false)
+				.await
+				.map_err(|msg| upstream_error(&msg, &title))?,
+		);
+		Ok(Response::page(out))
+	}
+
+	/// A page of posts, with a link to the next one under `base` that keeps `query`
+	async fn listing(&self, path: &str, base: &str, query: &str, newest_first: bool) -> Result<String, String> {
+		let (mut posts, after) = Post::fetch(path, false).await?;
+		filter_posts(&mut posts, &get_filters(&self.req));
+		if should_be_nsfw_gated(&self.req, "") {
+			posts.retain(|post| !post.flags.nsfw);
+		}
+		if newest_first {
+			posts.sort_by_key(|p| std::cmp::Reverse(p.created_ts));
+			posts.sort_by_key(|p| std::cmp::Reverse(p.flags.stickied));
+		}
+
+		let mut out = String::new();
+		if posts.is_empty() {
+			out.push_str("No posts were found.\n");
+		}
+		for post in &posts {
+			out.push_str(&self.summary(post));
+		}
+		if !after.is_empty() {
+			out.push_str(&format!("=> {base}/after/{after}{query} Next page\n"));
+		}
+		Ok(out)
+	}
+
+	/// A post in a listing. Comments in user listings are posts without a title.
+	fn summary(&self, post: &Post) -> String {
+		if post.title.is_empty() {
+			let mut out = format!("=> {} Comment in r/{}\n", post.permalink, post.community);
+			out.push_str(&format!("{} points · {}\n", post.score.0, post.rel_time));
+			out.push_str(&self.gemtext(&post.body));
+			out.push('\n');
+			return out;
+		}
+
+		let mut details = vec![format!("{} points", post.score.0), format!("{} comments", post.comments.0), format!("r/{}", post.community)];
+		details.push(format!("u/{}", post.author.name));
+		details.push(post.rel_time.clone());
+		if post.flags.stickied {
+			details.push("pinned".to_string());
+		}
+		if post.flags.nsfw {
+			details.push("NSFW".to_string());
+		}
+		let kind = if post.post_type == "link" { post.domain.clone() } else { post.post_type.clone() };
+		format!("=> {} {} [{kind}]\n{}\n\n", post.permalink, post.title, details.join(" · "))
+	}
+
+	/// Links to a post's media, through the HTTP proxy
+	fn media(&self, post: &Post) -> String {
+		match post.post_type.as_str() {
+			"image" => format!("=> {} Image\n", self.link(&post.media.url)),
+			"gif" | "video" => format!("=> {} Video\n", self.link(&post.media.url)),
+			"gallery" => post
+				.gallery
+				.iter()
+				.enumerate()
+				.map(|(i, image)| {
+					let caption = if image.caption.is_empty() { String::new() } else { format!(": {}", image.caption) };
+					format!("=> {} Image {} of {}{caption}\n", self.link(&image.url), i + 1, post.gallery.len())
+				})
+				.collect(),
+			"link" => format!("=> {} {}\n", self.link(&post.media.url), post.domain),
+			_ => String::new(),
+		}
+	}
+
+	fn comment(&self, out: &mut String, comment: &Comment, depth: usize) {
+		let indent = "│ ".repeat(depth);
+		if comment.kind == "more" {
+			if comment.parent_kind == "t1" {
+				out.push_str(&format!("=> {}{} More replies ({})\n\n", comment.post_link, comment.parent_id, comment.more_count));
+			}
+			return;
+		}
+
+		let op = if comment.author.name == comment.post_author { " (OP)" } else { "" };
+		out.push_str(&format!("{indent}u/{}{op} · {} points · {}\n", comment.author.name, comment.score.0, comment.rel_time));
+		let body = if comment.is_filtered {
+			"(Filtered content)\n".to_string()
+		} else {
+			self.gemtext(&comment.body)
+		};
+		out.push_str(&indent_lines(&body, &indent));
+		out.push('\n');
+
+		for reply in &comment.replies {
+			self.comment(out, reply, depth + 1);
+		}
+	}
+
+	/// Where a link from rendered content should point: pages Gemini serves stay
+	/// here, other paths go to the HTTP front-end and outbound links are cleaned
+	fn link(&self, url: &str) -> String {
+		if url.starts_with('/') {
+			let path = url.split(['?', '#']).next().unwrap_or_default();
+			if Route::parse(path).is_some() {
+				url.to_string()
+			} else {
+				format!("{}{url}", self.http)
+			}
+		} else {
+			outbound::rewrite_url(url, &Preferences::new(&self.req))
+		}
+	}
+
+	fn gemtext(&self, html: &str) -> String {
+		gemtext(html, |url| self.link(url))
+	}
+}
+
+/// Prefix text lines for a comment's depth. Links and preformatted lines must start at the margin.
+fn indent_lines(text: &str, indent: &str) -> String {
+	let mut preformatted = false;
+	text
+		.lines()
+		.map(|line| {
+			if line.starts_with("```") {
+				preformatted = !preformatted;
+				format!("{line}\n")
+			} else if preformatted || line.starts_with("=>") || indent.is_empty() {
+				format!("{line}\n")
+			} else {
+				format!("{indent}{line}\n")
+			}
+		})
+		.collect()
+}
+
+/// Turn rendered, sanitised HTML into gemtext. Links are collected and listed
+/// after the block they're in, since gemtext only has links on their own lines.
+fn gemtext(html: &str, link: impl Fn(&str) -> String) -> String {
+	let mut lines: Vec<String> = Vec::new();
+	let mut links: Vec<(String, String)> = Vec::new();
+
+	for block in blocks::parse(html) {
+		match block {
+			Block::Line(line) => {
+				let prefix = if line.heading > 0 {
+					format!("{} ", "#".repeat(line.heading.min(3)))
+				} else if line.lists > 0 {
+					"* ".to_string()
+				} else if line.quotes > 0 {
+					"> ".to_string()
+				} else {
+					String::new()
+				};
+				let number = match line.marker {
+					Some(Marker::Number(number)) => format!("{number}. "),
+					_ => String::new(),
+				};
+				if !line.text.is_empty() {
+					lines.push(format!("{prefix}{number}{}", line.text));
+				}
+				links.extend(line.links.iter().map(|(range, href)| (link(href), line.text[range.clone()].trim().to_string())));
+			}
+			Block::Pre(code) => {
+				lines.push("```".to_string());
+				lines.extend(code);
+				lines.push("```".to_string());
+			}
+			Block::Rule => lines.push("-----".to_string()),
+			Block::Break => {
+				for (href, text) in std::mem::take(&mut links) {
+					if text.is_empty() || text == href {
+						lines.push(format!("=> {href}"));
+					} else {
+						lines.push(format!("=> {href} {text}"));
+					}
+				}
+				if lines.last().is_some_and(|line| !line.is_empty()) {
+					lines.push(String::new());
+				}
+			}
+		}
+	}
+
+	while lines.last().is_some_and(String::is_empty) {
+		lines.pop();
+	}
+	let mut out = lines.join("\n");
+	if !out.is_empty() {
+		out.push('\n');
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_routes() {
+		assert_eq!(Route::parse("/"), Some((Route::Front, String::new())));
+		assert_eq!(
+			Route::parse("/r/rust/top/after/t3_abc"),
+			Some((
+				Route::Subreddit {
+					sub: "rust".to_string(),
+					sort: "top".to_string()
+				},
+				"t3_abc".to_string()
+			))
+		);
+		assert_eq!(
+			Route::parse("/r/rust/comments/abc/title/def/"),
+			Some((
+				Route::Post {
+					id: "abc".to_string(),
+					comment: "def".to_string()
+				},
+				String::new()
+			))
+		);
+		assert_eq!(
+			Route::parse("/user/spez/submitted"),
+			Some((
+				Route::User {
+					name: "spez".to_string(),
+					listing: "submitted".to_string()
+				},
+				String::new()
+			))
+		);
+		assert_eq!(Route::parse("/r/rust/wiki/index"), None);
+		assert_eq!(Route::parse("/img/abc.jpg"), None);
+	}
+
+	#[test]
+	fn test_gemtext() {
+		let page = Page {
+			req: Request::builder().uri("/").body(Body::empty()).unwrap(),
+			http: "https://redlib.example.com".to_string(),
+		};
+		let html = r#"<div class="md"><h2>Heading</h2><p>Some <strong>bold</strong> text with <a href="/r/rust">a link</a> and <a href="/img/abc.png">an image</a>.</p>
+<ul>
+<li>one</li>
+<li>two &amp; three</li>
+</ul>
+<blockquote>
+<p>quoted</p>
+</blockquote>
+<pre><code class="language-rust"><span class="hl-keyword">fn</span> main() {}
+</code></pre>
+<ol><li>first</li><li>second</li></ol><p>Emote <img src="/emote/a.png" alt=":joy:"> here</p></div>"#;
+		assert_eq!(
+			page.gemtext(html),
+			"## Heading
+
+Some bold text with a link and an image.
+=> /r/rust a link
+=> https://redlib.example.com/img/abc.png an image
+
+* one
+* two & three
+
+> quoted
+
+```
+fn main() {}
+```
+
+* 1. first
+* 2. second
+
+Emote :joy: here
+"
+		);
+	}
+
+	#[test]
+	fn test_comment_indent() {
+		assert_eq!(indent_lines("text\n=> /r/rust link\n```\ncode\n```\n", "│ "), "│ text\n=> /r/rust link\n```\ncode\n```\n");
+	}
+
+	#[tokio::test]
+	async fn test_bad_requests() {
+		assert_eq!(respond("https://example.com/").await.status, 53);
+		assert_eq!(respond("gemini://example.com/r/rust/wiki").await.status, 51);
+		assert_eq!(respond("gemini://example.com/search").await, Response::status(10, "Search Reddit"));
+	}
+}
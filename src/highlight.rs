@@ -0,0 +1,728 @@
+//! Syntax highlighting for code blocks in posts, comments and wikis. Code is
+//! split into tokens with a small lexer per language and wrapped in `hl-*`
+//! classes, which `style.css` colours from the theme's variables so no script
+//! is needed. Blocks without a language tag get a guess from telltale syntax.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+struct Language {
+	/// Names used in fenced code blocks, the first being the one shown in classes
+	names: &'static [&'static str],
+	keywords: &'static [&'static str],
+	literals: &'static [&'static str],
+	line_comments: &'static [&'static str],
+	block_comment: Option<(&'static str, &'static str)>,
+	quotes: &'static [char],
+	/// Whether `'` can also start a lifetime or label, as in Rust
+	lifetimes: bool,
+	case_insensitive: bool,
+	/// Patterns that give away untagged code in this language
+	hints: &'static [&'static str],
+}
+
+const LANGUAGES: &[Language] = &[
+	Language {
+		names: &["rust", "rs"],
+		keywords: &[
+			"as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+			"mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use", "where", "while",
+		],
+		literals: &["true", "false", "None", "Some", "Ok", "Err"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\''],
+		lifetimes: true,
+		case_insensitive: false,
+		hints: &[r"\bfn \w+", r"\blet mut\b", r"\bimpl\b", r"\w::\w", r"\w!\(|#\[derive", r"&mut |\) -> "],
+	},
+	Language {
+		names: &["python", "py", "python3"],
+		keywords: &[
+			"and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+			"is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+		],
+		literals: &["True", "False", "None", "self"],
+		line_comments: &["#"],
+		block_comment: None,
+		quotes: &['"', '\''],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[
+			r"\bdef \w+\(.*\):",
+			r"(?m)^\s*(from [\w.]+ )?import \w+",
+			r"\bself\.",
+			r"\belif\b",
+			r"(?m)^\s*print\(",
+			r"__\w+__",
+		],
+	},
+	Language {
+		names: &["javascript", "js", "jsx", "node"],
+		keywords: &[
+			"async",
+			"await",
+			"break",
+			"case",
+			"catch",
+			"class",
+			"const",
+			"continue",
+			"default",
+			"delete",
+			"do",
+			"else",
+			"enum",
+			"export",
+			"extends",
+			"finally",
+			"for",
+			"function",
+			"if",
+			"implements",
+			"import",
+			"in",
+			"instanceof",
+			"interface",
+			"let",
+			"new",
+			"of",
+			"return",
+			"static",
+			"super",
+			"switch",
+			"this",
+			"throw",
+			"try",
+			"typeof",
+			"var",
+			"void",
+			"while",
+			"yield",
+		],
+		literals: &["true", "false", "null", "undefined", "NaN"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\'', '`'],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[
+			r"\b(const|let) \w+ =",
+			r"\bfunction\s*\w*\(",
+			r"=>",
+			r"console\.log",
+			r"\b(document|window)\.",
+			r"require\(|module\.exports|export default",
+		],
+	},
+	Language {
+		names: &["typescript", "ts", "tsx"],
+		keywords: &[
+			"abstract",
+			"as",
+			"async",
+			"await",
+			"break",
+			"case",
+			"catch",
+			"class",
+			"const",
+			"continue",
+			"declare",
+			"default",
+			"delete",
+			"do",
+			"else",
+			"enum",
+			"export",
+			"extends",
+			"finally",
+			"for",
+			"function",
+			"if",
+			"implements",
+			"import",
+			"in",
+			"instanceof",
+			"interface",
+			"keyof",
+			"let",
+			"namespace",
+			"new",
+			"of",
+			"private",
+			"protected",
+			"public",
+			"readonly",
+			"return",
+			"satisfies",
+			"static",
+			"super",
+			"switch",
+			"this",
+			"throw",
+			"try",
+			"type",
+			"typeof",
+			"var",
+			"void",
+			"while",
+			"yield",
+		],
+		literals: &["true", "false", "null", "undefined", "NaN"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\'', '`'],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[
+			r"\b(interface|type) \w+(<[^>]*>)? (=|\{)",
+			r"\w\??: (string|number|boolean|any|unknown|void)\b",
+			r"\bas (const|unknown|any)\b",
+			r"\b(private|public|readonly) \w+[:;]",
+		],
+	},
+	Language {
+		names: &["cpp", "c", "c++", "h", "hpp", "cc", "cxx"],
+		keywords: &[
+			"auto",
+			"bool",
+			"break",
+			"case",
+			"char",
+			"class",
+			"const",
+			"continue",
+			"default",
+			"delete",
+			"do",
+			"double",
+			"else",
+			"enum",
+			"extern",
+			"float",
+			"for",
+			"goto",
+			"if",
+			"inline",
+			"int",
+			"long",
+			"namespace",
+			"new",
+			"private",
+			"protected",
+			"public",
+			"return",
+			"short",
+			"signed",
+			"sizeof",
+			"static",
+			"struct",
+			"switch",
+			"template",
+			"this",
+			"throw",
+			"try",
+			"typedef",
+			"typename",
+			"union",
+			"unsigned",
+			"using",
+			"virtual",
+			"void",
+			"volatile",
+			"while",
+		],
+		literals: &["true", "false", "NULL", "nullptr"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\''],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[
+			r#"(?m)^#include\s*[<"]"#,
+			r"\bstd::",
+			r"\bint main\s*\(",
+			r"printf\(|cout\s*<<",
+			r"\bnullptr\b|\w->\w",
+			r"\b(unsigned|char|int) \*?\w+\s*[=;\[]",
+		],
+	},
+	Language {
+		names: &["java"],
+		keywords: &[
+			"abstract",
+			"boolean",
+			"break",
+			"case",
+			"catch",
+			"char",
+			"class",
+			"continue",
+			"default",
+			"do",
+			"double",
+			"else",
+			"enum",
+			"extends",
+			"final",
+			"finally",
+			"float",
+			"for",
+			"if",
+			"implements",
+			"import",
+			"instanceof",
+			"int",
+			"interface",
+			"long",
+			"new",
+			"package",
+			"private",
+			"protected",
+			"public",
+			"return",
+			"static",
+			"super",
+			"switch",
+			"this",
+			"throw",
+			"throws",
+			"try",
+			"void",
+			"while",
+		],
+		literals: &["true", "false", "null"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\''],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[
+			r"\bpublic (static )?(final )?(class|void|int|String)\b",
+			r"System\.out\.print",
+			r"\bimport java\.",
+			r"@Override",
+			r"\bnew \w+<",
+		],
+	},
+	Language {
+		names: &["kotlin", "kt", "kts"],
+		keywords: &[
+			"as",
+			"break",
+			"by",
+			"class",
+			"companion",
+			"constructor",
+			"continue",
+			"data",
+			"do",
+			"else",
+			"enum",
+			"for",
+			"fun",
+			"if",
+			"import",
+			"in",
+			"init",
+			"inline",
+			"interface",
+			"internal",
+			"is",
+			"lateinit",
+			"object",
+			"open",
+			"override",
+			"package",
+			"private",
+			"protected",
+			"public",
+			"return",
+			"sealed",
+			"super",
+			"suspend",
+			"this",
+			"throw",
+			"try",
+			"typealias",
+			"val",
+			"var",
+			"when",
+			"while",
+		],
+		literals: &["true", "false", "null"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\''],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[
+			r"\bfun \w+\(",
+			r"\b(val|var) \w+(: \w+)? =",
+			r"(?m)^\s*println\(",
+			r"\bdata class\b",
+			r"\bwhen \(",
+			r"\w\?\.\w",
+		],
+	},
+	Language {
+		names: &["csharp", "cs", "c#"],
+		keywords: &[
+			"abstract",
+			"as",
+			"async",
+			"await",
+			"bool",
+			"break",
+			"case",
+			"catch",
+			"class",
+			"const",
+			"continue",
+			"default",
+			"do",
+			"double",
+			"else",
+			"enum",
+			"foreach",
+			"for",
+			"get",
+			"if",
+			"in",
+			"int",
+			"interface",
+			"internal",
+			"is",
+			"namespace",
+			"new",
+			"out",
+			"override",
+			"private",
+			"protected",
+			"public",
+			"readonly",
+			"return",
+			"set",
+			"static",
+			"string",
+			"struct",
+			"switch",
+			"this",
+			"throw",
+			"try",
+			"using",
+			"var",
+			"virtual",
+			"void",
+			"while",
+		],
+		literals: &["true", "false", "null"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\''],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[r"\busing System", r"Console\.Write", r"\bnamespace \w+", r"\{ get; set; \}", r"\bforeach \(var "],
+	},
+	Language {
+		names: &["go", "golang"],
+		keywords: &[
+			"break",
+			"case",
+			"chan",
+			"const",
+			"continue",
+			"default",
+			"defer",
+			"else",
+			"fallthrough",
+			"for",
+			"func",
+			"go",
+			"goto",
+			"if",
+			"import",
+			"interface",
+			"map",
+			"package",
+			"range",
+			"return",
+			"select",
+			"struct",
+			"switch",
+			"type",
+			"var",
+		],
+		literals: &["true", "false", "nil", "iota"],
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['"', '\'', '`'],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[r"(?m)^package \w+", r"\bfunc (\(\w+ \*?\w+\) )?\w+\(", r"\w :=", r"fmt\.Print", r"\bif err != nil"],
+	},
+	Language {
+		names: &["bash", "sh", "shell", "zsh", "console"],
+		keywords: &[
+			"if", "then", "else", "elif", "fi", "for", "while", "do", "done", "case", "esac", "function", "in", "return", "export", "local", "echo", "sudo", "cd",
+		],
+		literals: &["true", "false"],
+		line_comments: &["#"],
+		block_comment: None,
+		quotes: &['"', '\''],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[
+			r"(?m)^#!/(usr/)?bin/(env )?(ba|z)?sh",
+			r"(?m)^\$ ",
+			r"\bsudo ",
+			r"(?m)^\s*(apt|apt-get|pacman|dnf|brew|git|ls|chmod|systemctl) ",
+			r"\|\s*(grep|xargs)\b",
+			r"\$\{?\w+\}?",
+		],
+	},
+	Language {
+		names: &["sql", "mysql", "postgresql", "sqlite"],
+		keywords: &[
+			"select", "from", "where", "insert", "into", "values", "update", "set", "delete", "create", "table", "drop", "alter", "join", "left", "right", "inner", "outer", "on",
+			"group", "by", "order", "having", "limit", "as", "and", "or", "not", "is", "in", "distinct", "union", "primary", "key", "index",
+		],
+		literals: &["true", "false", "null"],
+		line_comments: &["--"],
+		block_comment: Some(("/*", "*/")),
+		quotes: &['\'', '"'],
+		lifetimes: false,
+		case_insensitive: true,
+		hints: &[
+			r"(?i)\bselect\b[\s\S]+\bfrom\b",
+			r"(?i)\binsert into\b",
+			r"(?i)\bcreate table\b",
+			r"(?i)\b(where|and) \w+ (=|like|in) ",
+			r"(?i)\b(left |inner )?join \w+ on\b",
+		],
+	},
+	Language {
+		names: &["json"],
+		keywords: &[],
+		literals: &["true", "false", "null"],
+		line_comments: &[],
+		block_comment: None,
+		quotes: &['"'],
+		lifetimes: false,
+		case_insensitive: false,
+		hints: &[],
+	},
+];
+
+static CHAR_LITERAL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^'(\\[^'\n]{1,8}|[^'\\\n])'").unwrap());
+static HINTS: LazyLock<Vec<Vec<Regex>>> = LazyLock::new(|| {
+	LANGUAGES
+		.iter()
+		.map(|language| language.hints.iter().map(|hint| Regex::new(hint).unwrap()).collect())
+		.collect()
+});
+
+/// Highlight `code` as the language named by a code block's info string, or
+/// as a guess when there isn't one. Returns the language's name and the HTML
+/// for the inside of `<code>`, or `None` if the language isn't known.
+pub fn highlight(code: &str, info: &str) -> Option<(&'static str, String)> {
+	let name = info.split_whitespace().next().unwrap_or_default().to_ascii_lowercase();
+	let language = if name.is_empty() {
+		guess(code)?
+	} else {
+		LANGUAGES.iter().find(|language| language.names.contains(&name.as_str()))?
+	};
+	Some((language.names[0], tokens(code, language)))
+}
+
+/// Pick the language whose hints match the most, if it's a clear winner
+fn guess(code: &str) -> Option<&'static Language> {
+	let trimmed = code.trim_start();
+	if (trimmed.starts_with('{') || trimmed.starts_with('[')) && serde_json::from_str::<serde_json::Value>(code).is_ok() {
+		return LANGUAGES.iter().find(|language| language.names[0] == "json");
+	}
+
+	let mut scores: Vec<(usize, &Language)> = LANGUAGES
+		.iter()
+		.zip(HINTS.iter())
+		.map(|(language, hints)| (hints.iter().filter(|hint| hint.is_match(code)).count(), language))
+		.collect();
+	scores.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+	match scores.as_slice() {
+		[(best, language), (second, _), ..] if *best >= 2 && best > second => Some(language),
+		_ => None,
+	}
+}
+
+fn tokens(code: &str, language: &Language) -> String {
+	let mut output = String::with_capacity(code.len() * 2);
+	let mut rest = code;
+	let mut prev: Option<char> = None;
+
+	let span = |output: &mut String, class: &str, text: &str| {
+		output.push_str(&format!("<span class=\"hl-{class}\">{}</span>", htmlescape::encode_minimal(text)));
+	};
+
+	while let Some(c) = rest.chars().next() {
+		let at_word_start = prev.map_or(true, |prev| !prev.is_alphanumeric() && prev != '_');
+
+		// `#` only starts a comment at the start of a word, so `$#` and `a#b` in shell aren't comments
+		if language
+			.line_comments
+			.iter()
+			.any(|comment| rest.starts_with(comment) && (*comment != "#" || prev.map_or(true, char::is_whitespace)))
+		{
+			let end = rest.find('\n').unwrap_or(rest.len());
+			span(&mut output, "comment", &rest[..end]);
+			rest = &rest[end..];
+			prev = Some(' ');
+			continue;
+		}
+
+		if let Some((open, close)) = language.block_comment.filter(|(open, _)| rest.starts_with(open)) {
+			let end = rest[open.len()..].find(close).map_or(rest.len(), |i| open.len() + i + close.len());
+			span(&mut output, "comment", &rest[..end]);
+			rest = &rest[end..];
+			prev = Some(' ');
+			continue;
+		}
+
+		if language.quotes.contains(&c) {
+			if let Some(end) = string_end(rest, c, language) {
+				span(&mut output, "string", &rest[..end]);
+				rest = &rest[end..];
+				prev = Some(c);
+				continue;
+			}
+		}
+
+		if c.is_ascii_digit() && at_word_start {
+			let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.').unwrap_or(rest.len());
+			span(&mut output, "number", &rest[..end]);
+			rest = &rest[end..];
+			prev = Some('0');
+			continue;
+		}
+
+		if (c.is_alphabetic() || c == '_') && at_word_start {
+			let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+			let word = &rest[..end];
+			let matches = |words: &[&str]| words.iter().any(|w| if language.case_insensitive { w.eq_ignore_ascii_case(word) } else { *w == word });
+			let next = rest[end..].trim_start_matches(' ').chars().next();
+
+			if matches(language.keywords) {
+				span(&mut output, "keyword", word);
+			} else if matches(language.literals) {
+				span(&mut output, "literal", word);
+			} else if next == Some('(') || (language.lifetimes && rest[end..].starts_with("!(")) {
+				span(&mut output, "function", word);
+			} else if !language.case_insensitive && word.starts_with(|c: char| c.is_uppercase()) && word.chars().any(char::is_lowercase) {
+				span(&mut output, "type", word);
+			} else {
+				output.push_str(&htmlescape::encode_minimal(word));
+			}
+			rest = &rest[end..];
+			prev = word.chars().last();
+			continue;
+		}
+
+		output.push_str(&htmlescape::encode_minimal(&c.to_string()));
+		rest = &rest[c.len_utf8()..];
+		prev = Some(c);
+	}
+
+	output
+}
+
+/// Find where a string starting with `quote` ends, including the closing quote
+fn string_end(text: &str, quote: char, language: &Language) -> Option<usize> {
+	// In Rust, 'a is a lifetime unless it's closed right after one character or escape
+	if language.lifetimes && quote == '\'' {
+		return CHAR_LITERAL.find(text).map(|literal| literal.end());
+	}
+
+	// Python's triple-quoted strings run until the next three quotes
+	let triple = quote.to_string().repeat(3);
+	if language.names[0] == "python" && text.starts_with(&triple) {
+		return Some(text[3..].find(&triple).map_or(text.len(), |i| i + 6));
+	}
+
+	// Go's raw strings are the only ones without escapes
+	let raw = quote == '`' && language.names[0] == "go";
+	let mut escaped = false;
+	for (i, c) in text.char_indices().skip(1) {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' if !raw => escaped = true,
+			c if c == quote => return Some(i + c.len_utf8()),
+			// Only backtick strings span lines
+			'\n' if quote != '`' => return None,
+			_ => {}
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_highlight() {
+		assert_eq!(
+			highlight("fn main() {\n\tlet s = \"hi\"; // greet\n\tprintln!(\"{s}\");\n}", "rust"),
+			Some((
+				"rust",
+				"<span class=\"hl-keyword\">fn</span> <span class=\"hl-function\">main</span>() {\n\t<span class=\"hl-keyword\">let</span> s = <span class=\"hl-string\">&quot;hi&quot;</span>; <span class=\"hl-comment\">// greet</span>\n\t<span class=\"hl-function\">println</span>!(<span class=\"hl-string\">&quot;{s}&quot;</span>);\n}"
+					.to_string()
+			))
+		);
+		// Lifetimes aren't strings, but characters are
+		assert_eq!(
+			highlight("fn f<'a>(c: &'a str) -> char { 'x' }", "rs").map(|(_, html)| html),
+			Some("<span class=\"hl-keyword\">fn</span> f&lt;&#x27;a&gt;(c: &amp;&#x27;a str) -&gt; char { <span class=\"hl-string\">&#x27;x&#x27;</span> }".to_string())
+		);
+		assert_eq!(
+			highlight("SELECT name FROM users WHERE id = 1 -- one", "sql").map(|(_, html)| html),
+			Some("<span class=\"hl-keyword\">SELECT</span> name <span class=\"hl-keyword\">FROM</span> users <span class=\"hl-keyword\">WHERE</span> id = <span class=\"hl-number\">1</span> <span class=\"hl-comment\">-- one</span>".to_string())
+		);
+		assert_eq!(
+			highlight("echo $# items # count", "bash").map(|(_, html)| html),
+			Some("<span class=\"hl-keyword\">echo</span> $# items <span class=\"hl-comment\"># count</span>".to_string())
+		);
+		// TypeScript and Kotlin have keywords of their own
+		assert_eq!(
+			highlight("type id = string", "ts").map(|(_, html)| html),
+			Some("<span class=\"hl-keyword\">type</span> id = string".to_string())
+		);
+		assert_eq!(
+			highlight("type = 1", "js").map(|(_, html)| html),
+			Some("type = <span class=\"hl-number\">1</span>".to_string())
+		);
+		assert_eq!(highlight("val x = when (y) { else -> 1 }", "kotlin").map(|(name, _)| name), Some("kotlin"));
+		assert!(highlight("val x = 1", "kt").is_some_and(|(_, html)| html.starts_with("<span class=\"hl-keyword\">val</span>")));
+		assert_eq!(highlight("<b>", "brainfuck"), None);
+	}
+
+	#[test]
+	fn test_guess_language() {
+		let guessed = |code| highlight(code, "").map(|(name, _)| name);
+		assert_eq!(
+			guessed("def greet(name):\n    print(f\"hi {name}\")\n\nif __name__ == '__main__':\n    greet('x')"),
+			Some("python")
+		);
+		assert_eq!(guessed("#include <stdio.h>\nint main(void) {\n\tprintf(\"hi\");\n}"), Some("cpp"));
+		assert_eq!(guessed("const add = (a, b) => a + b;\nconsole.log(add(1, 2));"), Some("javascript"));
+		assert_eq!(guessed("interface User {\n  name: string;\n  age?: number;\n}"), Some("typescript"));
+		assert_eq!(
+			guessed("data class User(val name: String)\nfun main() {\n    println(User(\"x\")?.name)\n}"),
+			Some("kotlin")
+		);
+		assert_eq!(guessed("$ sudo apt install redlib\n$ systemctl start redlib"), Some("bash"));
+		assert_eq!(guessed("{\"a\": [1, 2, null]}"), Some("json"));
+		assert_eq!(guessed("Just some text that was indented by four spaces."), None);
+	}
+}
@@ -126,9 +126,13 @@ impl InstanceInfo {
 				["Compile mode", &self.compile_mode],
 				["SFW only", &convert(&self.config.sfw_only)],
 				["Pushshift frontend", &convert(&self.config.pushshift)],
+				["Alternative front-ends", &convert(&self.config.frontends)],
 				["RSS enabled", &convert(&self.config.enable_rss)],
 				["Full URL", &convert(&self.config.full_url)],
 				["Remove default feeds", &convert(&self.config.default_remove_default_feeds)],
+				["Preference sync enabled", &convert(&self.config.enable_sync)],
+				["Sync storage cap (MB)", &convert(&self.config.sync_max_storage_mb)],
+				["Gemini address", &convert(&self.config.gemini_address)],
 				//TODO: fallback to crate::config::DEFAULT_PUSHSHIFT_FRONTEND
 			])
 			.with_header_row(["Settings"]),
@@ -171,6 +175,9 @@ impl InstanceInfo {
 				RSS enabled: {:?}\n
 				Full URL: {:?}\n
 				Remove default feeds: {:?}\n
+				Preference sync enabled: {:?}\n
+				Sync storage cap (MB): {:?}\n
+				Gemini address: {:?}\n
                 Config:\n
                     Banner: {:?}\n
                     Hide awards: {:?}\n
@@ -198,6 +205,9 @@ impl InstanceInfo {
 					self.config.enable_rss,
 					self.config.full_url,
 					self.config.default_remove_default_feeds,
+					self.config.enable_sync,
+					self.config.sync_max_storage_mb,
+					self.config.gemini_address,
 					self.config.pushshift,
 					self.config.banner,
 					self.config.default_hide_awards,
@@ -1,13 +1,28 @@
+pub mod api;
+pub mod backup;
+pub mod blocks;
 pub mod client;
 pub mod config;
 pub mod duplicates;
+pub mod embed;
+pub mod feed;
+pub mod gemini;
+pub mod highlight;
 pub mod instance_info;
+pub mod links;
+pub mod live;
+pub mod markdown;
 pub mod oauth;
 pub mod oauth_resources;
+pub mod outbound;
 pub mod post;
+pub mod resolvers;
+pub mod saved;
 pub mod search;
 pub mod server;
 pub mod settings;
 pub mod subreddit;
+pub mod sync;
+pub mod text;
 pub mod user;
 pub mod utils;
@@ -0,0 +1,224 @@
+//! Reddit links come in more shapes than `/r/:sub/comments/:id`. These
+//! handlers resolve the other ones people paste into Redlib to the right post
+//! or media view.
+
+use std::sync::LazyLock;
+
+use crate::client::{canonical_path, json};
+use crate::config::get_setting;
+use crate::server::RequestExt;
+use crate::utils::{error, format_url, param, redirect, template, Preferences};
+
+use askama::Template;
+use hyper::{Body, Request, Response};
+use log::error;
+use redb::backends::InMemoryBackend;
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition, TableError};
+use url::form_urlencoded;
+
+/// Posts that share links resolved to, keyed by share ID
+const SHARE_LINKS: TableDefinition<&str, &str> = TableDefinition::new("share_links");
+
+/// Share IDs in the order they were cached, so the oldest can be dropped first
+const SHARE_ORDER: TableDefinition<u64, &str> = TableDefinition::new("share_order");
+
+const DEFAULT_SHARE_CACHE_PATH: &str = "redlib-shares.redb";
+
+/// Share links remembered at most, a few MB on disk
+const MAX_SHARE_LINKS: u64 = 50_000;
+
+/// Query parameters Reddit adds to shared links to tie visits to the sharer
+const SHARE_PARAMS: [&str; 4] = ["share_id", "rdt", "$deep_link", "_branch_match_id"];
+
+/// Kept on disk across restarts, or in memory if the file can't be opened
+static SHARE_CACHE: LazyLock<Option<Database>> = LazyLock::new(|| {
+	let path = get_setting("REDLIB_SHARE_CACHE_PATH").unwrap_or_else(|| DEFAULT_SHARE_CACHE_PATH.to_string());
+	let db = Database::create(&path).or_else(|e| {
+		error!("Failed to open share link cache at {path}, keeping it in memory: {e}");
+		Database::builder().create_with_backend(InMemoryBackend::new())
+	});
+	db.inspect_err(|e| error!("Failed to open share link cache: {e}")).ok()
+});
+
+/// `/gallery/:id` and `/poll/:id` are other names for a post
+pub async fn post_alias(req: Request<Body>) -> Result<Response<Body>, String> {
+	let id = req.param("id").unwrap_or_default();
+	Ok(redirect(&format!("/comments/{id}")))
+}
+
+/// `/video/:id` takes the ID of the video on v.redd.it, so look up the post it belongs to
+pub async fn video(req: Request<Body>) -> Result<Response<Body>, String> {
+	let id = req.param("id").unwrap_or_default();
+	if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return error(req, "Invalid video ID").await;
+	}
+
+	let listing = json(format!("/api/info.json?url=https://v.redd.it/{id}&raw_json=1"), false).await;
+	match listing.as_ref().map(|listing| listing["data"]["children"][0]["data"]["permalink"].as_str()) {
+		Ok(Some(permalink)) => Ok(redirect(permalink)),
+		Ok(None) => error(req, "Couldn't find the post this video belongs to").await,
+		Err(msg) => error(req, msg).await,
+	}
+}
+
+/// `/media?url=` is Reddit's image viewer, so show the image itself
+pub async fn media(req: Request<Body>) -> Result<Response<Body>, String> {
+	let url = param(&format!("?{}", req.uri().query().unwrap_or_default()), "url").unwrap_or_default();
+
+	// Only Reddit's own media is proxied; anything else would be an open redirect
+	match format_url(&url) {
+		media if media.starts_with('/') => Ok(redirect(&media)),
+		_ => error(req, "Only media hosted by Reddit can be viewed").await,
+	}
+}
+
+/// `/r/:sub/comments/:id/comment/:comment_id` is how new Reddit links to a comment
+pub async fn comment(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_default();
+	let id = req.param("id").unwrap_or_default();
+	let comment_id = req.param("comment_id").unwrap_or_default();
+	let query = req.uri().query().map_or(String::new(), |query| format!("?{query}"));
+
+	Ok(redirect(&format!("/r/{sub}/comments/{id}/_/{comment_id}{query}")))
+}
+
+/// Shown before following a share link, which means asking Reddit about it
+#[derive(Template)]
+#[template(path = "share.html")]
+struct ShareTemplate {
+	sub: String,
+	id: String,
+	prefs: Preferences,
+	url: String,
+}
+
+fn cached_share_link(db: &Database, id: &str) -> Result<Option<String>, String> {
+	let txn = db.begin_read().map_err(|e| e.to_string())?;
+	let table = match txn.open_table(SHARE_LINKS) {
+		Ok(table) => table,
+		Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+		Err(e) => return Err(e.to_string()),
+	};
+	let value = table.get(id).map_err(|e| e.to_string())?;
+	Ok(value.map(|value| value.value().to_string()))
+}
+
+/// Remember where a share link points, forgetting the oldest links beyond `max`
+fn cache_share_link(db: &Database, id: &str, path: &str, max: u64) -> Result<(), String> {
+	let txn = db.begin_write().map_err(|e| e.to_string())?;
+	{
+		let mut links = txn.open_table(SHARE_LINKS).map_err(|e| e.to_string())?;
+		let mut order = txn.open_table(SHARE_ORDER).map_err(|e| e.to_string())?;
+
+		if links.insert(id, path).map_err(|e| e.to_string())?.is_none() {
+			let next = order.last().map_err(|e| e.to_string())?.map_or(0, |(seq, _)| seq.value() + 1);
+			order.insert(next, id).map_err(|e| e.to_string())?;
+		}
+
+		while links.len().map_err(|e| e.to_string())? > max {
+			let Some(oldest) = order.pop_first().map_err(|e| e.to_string())?.map(|(_, id)| id.value().to_string()) else {
+				break;
+			};
+			links.remove(oldest.as_str()).map_err(|e| e.to_string())?;
+		}
+	}
+	txn.commit().map_err(|e| e.to_string())
+}
+
+/// Drop the parameters identifying who shared a link, keeping the rest of the query
+fn strip_share_params(path: &str) -> String {
+	let Some((path, query)) = path.split_once('?') else {
+		return path.to_string();
+	};
+
+	let kept: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+		.filter(|(key, _)| !key.starts_with("utm_") && !SHARE_PARAMS.contains(&key.as_ref()))
+		.map(|(key, value)| (key.into_owned(), value.into_owned()))
+		.collect();
+
+	if kept.is_empty() {
+		path.to_string()
+	} else {
+		format!("{path}?{}", form_urlencoded::Serializer::new(String::new()).extend_pairs(kept).finish())
+	}
+}
+
+/// `/r/:sub/s/:id` is an obfuscated share link. Finding the post means asking
+/// Reddit to follow it, so unless this instance has already done that, explain
+/// what will happen and wait for the user to go ahead with a POST.
+pub async fn share(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_default();
+	let id = req.param("id").unwrap_or_default();
+	if !(8..12).contains(&id.len()) || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return error(req, "Nothing here").await;
+	}
+
+	let cache = SHARE_CACHE.as_ref();
+	let cached = cache.map(|db| cached_share_link(db, &id)).transpose().unwrap_or_else(|e| {
+		error!("Failed to read share link cache: {e}");
+		None
+	});
+	if let Some(path) = cached.flatten() {
+		return Ok(redirect(&path));
+	}
+
+	if req.method() != hyper::Method::POST {
+		return Ok(template(&ShareTemplate {
+			sub,
+			id,
+			prefs: Preferences::new(&req),
+			url: req.uri().to_string(),
+		}));
+	}
+
+	// Only the share link itself is sent to Reddit, never anything from the client
+	match canonical_path(format!("/r/{sub}/s/{id}"), 3).await {
+		Ok(Some(path)) => {
+			let path = strip_share_params(&path);
+			if let Err(e) = cache.map(|db| cache_share_link(db, &id, &path, MAX_SHARE_LINKS)).transpose() {
+				error!("Failed to cache share link: {e}");
+			}
+			Ok(redirect(&path))
+		}
+		Ok(None) => error(req, "Post ID is invalid. It may point to a post on a community that has been banned.").await,
+		Err(e) => error(req, &e).await,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_strip_share_params() {
+		assert_eq!(strip_share_params("/r/rust/comments/abc/title/"), "/r/rust/comments/abc/title/");
+		assert_eq!(
+			strip_share_params("/r/rust/comments/abc/title/?share_id=xyz&utm_source=share&utm_medium=web"),
+			"/r/rust/comments/abc/title/"
+		);
+		assert_eq!(
+			strip_share_params("/r/rust/comments/abc/title/?context=3&share_id=xyz"),
+			"/r/rust/comments/abc/title/?context=3"
+		);
+	}
+
+	#[test]
+	fn test_share_link_cache() {
+		let db = Database::builder().create_with_backend(InMemoryBackend::new()).unwrap();
+		assert_eq!(cached_share_link(&db, "AbCdEfGh12").unwrap(), None);
+		cache_share_link(&db, "AbCdEfGh12", "/r/rust/comments/abc/title/", 2).unwrap();
+		assert_eq!(cached_share_link(&db, "AbCdEfGh12").unwrap().as_deref(), Some("/r/rust/comments/abc/title/"));
+	}
+
+	#[test]
+	fn test_share_link_cache_cap() {
+		let db = Database::builder().create_with_backend(InMemoryBackend::new()).unwrap();
+		cache_share_link(&db, "AbCdEfGh12", "/r/rust/comments/abc/", 2).unwrap();
+		cache_share_link(&db, "IjKlMnOp34", "/r/rust/comments/def/", 2).unwrap();
+		cache_share_link(&db, "AbCdEfGh12", "/r/rust/comments/abc/", 2).unwrap();
+		cache_share_link(&db, "QrStUvWx56", "/r/rust/comments/ghi/", 2).unwrap();
+		assert_eq!(cached_share_link(&db, "AbCdEfGh12").unwrap(), None);
+		assert_eq!(cached_share_link(&db, "IjKlMnOp34").unwrap().as_deref(), Some("/r/rust/comments/def/"));
+		assert_eq!(cached_share_link(&db, "QrStUvWx56").unwrap().as_deref(), Some("/r/rust/comments/ghi/"));
+	}
+}
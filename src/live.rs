@@ -0,0 +1,238 @@
+//! Handler for Reddit live threads.
+
+use crate::client::json;
+use crate::config;
+use crate::feed::{Entry, Feed};
+use crate::server::RequestExt;
+use crate::utils::{error, format_url, nsfw_landing, param, rewrite_urls, template, time, Preferences};
+
+use askama::Template;
+use hyper::{Body, Request, Response};
+use serde_json::Value;
+
+/// `LiveThread` holds the metadata of a live thread, taken from
+/// `/live/:id/about.json`.
+struct LiveThread {
+	id: String,
+	title: String,
+	description: String,
+	resources: String,
+	/// state is either "live" or "complete".
+	state: String,
+	viewers: i64,
+	nsfw: bool,
+	rel_time: String,
+	created: String,
+}
+
+impl LiveThread {
+	fn parse(data: &Value) -> Self {
+		let (rel_time, created) = time(data["created_utc"].as_f64().unwrap_or_default());
+
+		Self {
+			id: data["id"].as_str().unwrap_or_default().to_string(),
+			title: data["title"].as_str().unwrap_or_default().to_string(),
+			description: rewrite_urls(data["description_html"].as_str().unwrap_or_default()),
+			resources: rewrite_urls(data["resources_html"].as_str().unwrap_or_default()),
+			state: data["state"].as_str().unwrap_or_default().to_string(),
+			viewers: data["viewer_count"].as_i64().unwrap_or_default(),
+			nsfw: data["nsfw"].as_bool().unwrap_or_default(),
+			rel_time,
+			created,
+		}
+	}
+}
+
+/// `LiveUpdate` is a single update posted to a live thread.
+struct LiveUpdate {
+	/// name is the fullname of the update (`LiveUpdate_<uuid>`), which is
+	/// used for pagination.
+	name: String,
+	author: String,
+	body: String,
+	/// stricken updates have been retracted by the thread's contributors.
+	stricken: bool,
+	embeds: Vec<LiveEmbed>,
+	created_ts: i64,
+	rel_time: String,
+	created: String,
+}
+
+/// `LiveEmbed` is a link to external media attached to an update.
+struct LiveEmbed {
+	url: String,
+	title: String,
+	provider: String,
+	/// thumbnail is only set when it can be served through the media proxy.
+	thumbnail: String,
+}
+
+impl LiveUpdate {
+	fn parse(update: &Value) -> Self {
+		let data = &update["data"];
+		let created_ts = data["created_utc"].as_f64().unwrap_or_default();
+		let (rel_time, created) = time(created_ts);
+
+		let embeds = data["mobile_embeds"]
+			.as_array()
+			.map(|embeds| embeds.iter().map(LiveEmbed::parse).filter(|embed| !embed.url.is_empty()).collect())
+			.unwrap_or_default();
+
+		Self {
+			name: data["name"].as_str().unwrap_or_default().to_string(),
+			author: data["author"].as_str().unwrap_or("[deleted]").to_string(),
+			body: rewrite_urls(data["body_html"].as_str().unwrap_or_default()),
+			stricken: data["stricken"].as_bool().unwrap_or_default(),
+			embeds,
+			created_ts: created_ts as i64,
+			rel_time,
+			created,
+		}
+	}
+}
+
+impl LiveEmbed {
+	fn parse(embed: &Value) -> Self {
+		let url = embed["original_url"].as_str().or_else(|| embed["url"].as_str()).unwrap_or_default();
+		let thumbnail = format_url(embed["thumbnail_url"].as_str().unwrap_or_default());
+
+		Self {
+			url: format_url(url),
+			title: embed["title"].as_str().unwrap_or(url).to_string(),
+			provider: embed["provider_name"].as_str().unwrap_or_default().to_string(),
+			// Only keep thumbnails we can proxy; anything else would be blocked by the CSP
+			thumbnail: if thumbnail.starts_with('/') { thumbnail } else { String::new() },
+		}
+	}
+}
+
+/// `LiveTemplate` defines an Askama template for rendering a live thread.
+#[derive(Template)]
+#[template(path = "live.html")]
+struct LiveTemplate {
+	thread: LiveThread,
+	updates: Vec<LiveUpdate>,
+	ends: (String, String),
+	prefs: Preferences,
+	url: String,
+}
+
+/// Fetch a live thread's metadata and a page of its updates, along with the
+/// "after" value for the next (older) page.
+async fn fetch(id: &str, query: &str) -> Result<(LiveThread, Vec<LiveUpdate>, String), String> {
+	let about = json(format!("/live/{id}/about.json?raw_json=1"), false).await?;
+	let listing = json(format!("/live/{id}.json?{query}&raw_json=1"), false).await?;
+
+	let updates = listing["data"]["children"]
+		.as_array()
+		.map(|list| list.iter().map(LiveUpdate::parse).collect())
+		.unwrap_or_default();
+
+	Ok((
+		LiveThread::parse(&about["data"]),
+		updates,
+		listing["data"]["after"].as_str().unwrap_or_default().to_string(),
+	))
+}
+
+pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
+	let id = req.param("id").unwrap_or_default();
+	let query = req.uri().query().unwrap_or_default().to_string();
+	let url = req.uri().to_string();
+
+	match fetch(&id, &query).await {
+		Ok((thread, updates, after)) => {
+			// Return landing page if this thread is NSFW but the user has
+			// disabled the display of NSFW content or the instance is SFW-only
+			if thread.nsfw && crate::utils::should_be_nsfw_gated(&req, &url) {
+				return Ok(nsfw_landing(req, url).await.unwrap_or_default());
+			}
+
+			Ok(template(&LiveTemplate {
+				thread,
+				updates,
+				ends: (param(&format!("?{query}"), "after").unwrap_or_default(), after),
+				prefs: Preferences::new(&req),
+				url,
+			}))
+		}
+		Err(msg) => error(req, &msg).await,
+	}
+}
+
+pub async fn rss(req: Request<Body>) -> Result<Response<Body>, String> {
+	if config::get_setting("REDLIB_ENABLE_RSS").is_none() {
+		return Ok(error(req, "RSS is disabled on this instance.").await.unwrap_or_default());
+	}
+
+	let id = req.param("id").unwrap_or_default();
+	let (thread, updates, _) = fetch(&id, "").await?;
+	let thread_link = format!("/live/{id}");
+
+	let feed = Feed {
+		title: thread.title,
+		description: thread.description,
+		link: thread_link.clone(),
+		entries: updates
+			.into_iter()
+			.filter(|update| !update.stricken)
+			.map(|update| Entry {
+				id: update.name,
+				title: format!("Update by u/{}", update.author),
+				link: thread_link.clone(),
+				author: update.author,
+				content: update.body,
+				summary: None,
+				image: None,
+				published: update.created_ts,
+				enclosures: Vec::new(),
+			})
+			.collect(),
+	};
+
+	Ok(feed.respond(&req))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn test_parse_live_update() {
+		let update = LiveUpdate::parse(&json!({
+			"kind": "LiveUpdate",
+			"data": {
+				"name": "LiveUpdate_1234",
+				"author": "reporter",
+				"body_html": "<div class=\"md\"><p>See <a href=\"https://www.reddit.com/r/news\">r/news</a></p></div>",
+				"stricken": false,
+				"created_utc": 1700000000.0,
+				"mobile_embeds": [
+					{
+						"original_url": "https://example.com/article",
+						"provider_name": "Example",
+						"title": "Article",
+						"thumbnail_url": "https://tracker.example.com/thumb.jpg"
+					},
+					{
+						"original_url": "https://i.redd.it/abc.jpg",
+						"thumbnail_url": "https://i.redd.it/abc.jpg"
+					}
+				]
+			}
+		}));
+
+		assert_eq!(update.name, "LiveUpdate_1234");
+		assert_eq!(update.author, "reporter");
+		assert!(update.body.contains(r#"href="/r/news""#));
+		assert_eq!(update.created_ts, 1700000000);
+		assert_eq!(update.embeds.len(), 2);
+		assert_eq!(update.embeds[0].url, "https://example.com/article");
+		assert_eq!(update.embeds[0].provider, "Example");
+		assert!(update.embeds[0].thumbnail.is_empty());
+		assert_eq!(update.embeds[1].url, "/img/abc.jpg");
+		assert_eq!(update.embeds[1].title, "https://i.redd.it/abc.jpg");
+		assert_eq!(update.embeds[1].thumbnail, "/img/abc.jpg");
+	}
+}
@@ -12,7 +12,7 @@ use log::{info, warn};
 use redlib::client::{canonical_path, proxy, rate_limit_check, CLIENT};
 use redlib::server::{self, RequestExt};
 use redlib::utils::{error, redirect, ThemeAssets};
-use redlib::{config, duplicates, headers, instance_info, post, search, settings, subreddit, user};
+use redlib::{api, backup, config, duplicates, gemini, headers, instance_info, links, live, post, resolvers, saved, search, settings, subreddit, sync, user};
 
 use redlib::client::OAUTH_CLIENT;
 
@@ -209,7 +209,8 @@ async fn main() {
 		"Referrer-Policy" => "no-referrer",
 		"X-Content-Type-Options" => "nosniff",
 		"X-Frame-Options" => "DENY",
-		"Content-Security-Policy" => "default-src 'none'; font-src 'self'; script-src 'self' blob:; manifest-src 'self'; media-src 'self' data: blob: about:; style-src 'self' 'unsafe-inline'; base-uri 'none'; img-src 'self' data:; form-action 'self'; frame-ancestors 'none'; connect-src 'self'; worker-src blob:;"
+		"Content-Security-Policy" => server::CONTENT_SECURITY_POLICY,
+		"Vary" => "Accept"
 	};
 
 	if let Some(expire_time) = hsts {
@@ -220,6 +221,7 @@ async fn main() {
 
 	// Read static files
 	app.at("/style.css").get(|_| style().boxed());
+	app.at("/lite.css").get(|_| resource(include_str!("../static/lite.css"), "text/css", true).boxed());
 	app
 		.at("/manifest.json")
 		.get(|_| resource(include_str!("../static/manifest.json"), "application/json", false).boxed());
@@ -256,6 +258,10 @@ async fn main() {
 	app
 		.at("/check_update.js")
 		.get(|_| resource(include_str!("../static/check_update.js"), "text/javascript", false).boxed());
+	app.at("/live.js").get(|_| resource(include_str!("../static/live.js"), "text/javascript", false).boxed());
+	app
+		.at("/settings_import.js")
+		.get(|_| resource(include_str!("../static/settings_import.js"), "text/javascript", false).boxed());
 	app.at("/copy.js").get(|_| resource(include_str!("../static/copy.js"), "text/javascript", false).boxed());
 
 	app.at("/commits.atom").get(|_| async move { proxy_commit_info().await }.boxed());
@@ -276,30 +282,79 @@ async fn main() {
 	app.at("/preview/:loc/:id").get(|r| proxy(r, "https://{loc}view.redd.it/{id}").boxed());
 	app.at("/style/*path").get(|r| proxy(r, "https://styles.redditmedia.com/{path}").boxed());
 	app.at("/static/*path").get(|r| proxy(r, "https://www.redditstatic.com/{path}").boxed());
+	app.at("/imgur/*path").get(|r| resolvers::proxy_media(r, "imgur").boxed());
+	app.at("/redgifs/*path").get(|r| resolvers::proxy_media(r, "redgifs").boxed());
+	app.at("/streamable/:host/*path").get(|r| resolvers::proxy_media(r, "streamable").boxed());
 
 	// Browse user profile
 	app
 		.at("/u/:name")
 		.get(|r| async move { Ok(redirect(&format!("/user/{}", r.param("name").unwrap_or_default()))) }.boxed());
+	app.at("/u/:name/m/:multi").get(|r| {
+		async move {
+			Ok(redirect(&format!(
+				"/user/{}/m/{}",
+				r.param("name").unwrap_or_default(),
+				r.param("multi").unwrap_or_default()
+			)))
+		}
+		.boxed()
+	});
 	app.at("/u/:name/comments/:id/:title").get(|r| post::item(r).boxed());
 	app.at("/u/:name/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed());
 
 	app.at("/user/[deleted]").get(|req| error(req, "User has deleted their account").boxed());
-	app.at("/user/:name.rss").get(|r| user::rss(r).boxed());
 	app.at("/user/:name").get(|r| user::profile(r).boxed());
 	app.at("/user/:name/:listing").get(|r| user::profile(r).boxed());
+	app.at("/user/:name/m/:multi").get(|r| subreddit::multireddit(r).boxed());
+	app.at("/user/:name/m/:multi/:sort").get(|r| subreddit::multireddit(r).boxed());
 	app.at("/user/:name/comments/:id").get(|r| post::item(r).boxed());
 	app.at("/user/:name/comments/:id/:title").get(|r| post::item(r).boxed());
 	app.at("/user/:name/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed());
 
+	// JSON API
+	app.at("/api/v1/r/:sub").get(|r| api::subreddit_posts(r).boxed());
+	app.at("/api/v1/r/:sub/about").get(|r| api::subreddit_about(r).boxed());
+	app.at("/api/v1/r/:sub/search").get(|r| api::search(r).boxed());
+	app.at("/api/v1/r/:sub/comments/:id").get(|r| api::comments(r).boxed());
+	app.at("/api/v1/r/:sub/comments/:id/:comment_id").get(|r| api::comments(r).boxed());
+	app.at("/api/v1/r/:sub/:sort").get(|r| api::subreddit_posts(r).boxed());
+	app.at("/api/v1/comments/:id").get(|r| api::comments(r).boxed());
+	app.at("/api/v1/comments/:id/:comment_id").get(|r| api::comments(r).boxed());
+	app.at("/api/v1/user/:name").get(|r| api::user_posts(r).boxed());
+	app.at("/api/v1/user/:name/:listing").get(|r| api::user_posts(r).boxed());
+	app.at("/api/v1/search").get(|r| api::search(r).boxed());
+	app.at("/api/v1/*path").get(|r| api::not_found(r).boxed());
+
 	// Configure settings
 	app.at("/settings").get(|r| settings::get(r).boxed()).post(|r| settings::set(r).boxed());
 	app.at("/settings/restore").get(|r| settings::restore(r).boxed());
 	app.at("/settings/encoded-restore").post(|r| settings::encoded_restore(r).boxed());
 	app.at("/settings/update").get(|r| settings::update(r).boxed());
-
-	// RSS Subscriptions
-	app.at("/r/:sub.rss").get(|r| subreddit::rss(r).boxed());
+	app.at("/settings/groups").post(|r| settings::group(r).boxed());
+	app.at("/settings/groups/:name/delete").post(|r| settings::delete_group(r).boxed());
+	app.at("/settings/export.json").get(|r| backup::export(r).boxed());
+	app.at("/settings/export.toml").get(|r| backup::export(r).boxed());
+	app.at("/settings/subscriptions.opml").get(|r| backup::opml(r).boxed());
+	app.at("/settings/import").post(|r| backup::import(r).boxed());
+	app.at("/settings/import/apply").post(|r| backup::apply(r).boxed());
+	app.at("/settings/sync/create").post(|r| sync::create(r).boxed());
+	app.at("/settings/sync/push").post(|r| sync::push(r).boxed());
+	app.at("/settings/sync/pull").post(|r| sync::pull(r).boxed());
+	app.at("/settings/sync/disconnect").post(|r| sync::disconnect(r).boxed());
+	app.at("/settings/sync/delete").post(|r| sync::delete(r).boxed());
+
+	// RSS, Atom and JSON Feed subscriptions
+	for format in ["rss", "atom", "json"] {
+		app.at(&format!("/r/:sub.{format}")).get(|r| subreddit::rss(r).boxed());
+		app.at(&format!("/r/:sub/search.{format}")).get(|r| search::rss(r).boxed());
+		app.at(&format!("/search.{format}")).get(|r| search::rss(r).boxed());
+		app.at(&format!("/r/:sub/comments/:id.{format}")).get(|r| post::rss(r).boxed());
+		app.at(&format!("/comments/:id.{format}")).get(|r| post::rss(r).boxed());
+		app.at(&format!("/user/:name.{format}")).get(|r| user::rss(r).boxed());
+		app.at(&format!("/user/:name/:listing.{format}")).get(|r| user::rss(r).boxed());
+		app.at(&format!("/live/:id.{format}")).get(|r| live::rss(r).boxed());
+	}
 
 	// Subreddit services
 	app
@@ -319,6 +374,7 @@ async fn main() {
 	app.at("/r/:sub/comments/:id").get(|r| post::item(r).boxed());
 	app.at("/r/:sub/comments/:id/:title").get(|r| post::item(r).boxed());
 	app.at("/r/:sub/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed());
+	app.at("/r/:sub/comments/:id/comment/:comment_id").get(|r| links::comment(r).boxed());
 	app.at("/comments/:id").get(|r| post::item(r).boxed());
 	app.at("/comments/:id/comments").get(|r| post::item(r).boxed());
 	app.at("/comments/:id/comments/:comment_id").get(|r| post::item(r).boxed());
@@ -332,13 +388,29 @@ async fn main() {
 
 	app.at("/r/:sub/search").get(|r| search::find(r).boxed());
 
+	// Saved posts and comments
+	app.at("/saved").get(|r| saved::get(r).boxed());
+	app.at("/saved/export").get(|r| saved::export(r).boxed());
+	app.at("/saved/import").post(|r| saved::import(r).boxed());
+	app.at("/saved/:id/add").post(|r| saved::bookmark(r).boxed());
+	app.at("/saved/:id/remove").post(|r| saved::bookmark(r).boxed());
+
+	// Browse subscription groups
+	app.at("/g/:group").get(|r| subreddit::group(r).boxed());
+	app.at("/g/:group/:sort").get(|r| subreddit::group(r).boxed());
+
+	// View live threads
+	app.at("/live/:id").get(|r| live::item(r).boxed());
+
 	app
 		.at("/r/:sub/w")
 		.get(|r| async move { Ok(redirect(&format!("/r/{}/wiki", r.param("sub").unwrap_or_default()))) }.boxed());
 	app
 		.at("/r/:sub/w/*page")
-		.get(|r| async move { Ok(redirect(&format!("/r/{}/wiki/{}", r.param("sub").unwrap_or_default(), r.param("wiki").unwrap_or_default()))) }.boxed());
+		.get(|r| async move { Ok(redirect(&format!("/r/{}/wiki/{}", r.param("sub").unwrap_or_default(), r.param("page").unwrap_or_default()))) }.boxed());
 	app.at("/r/:sub/wiki").get(|r| subreddit::wiki(r).boxed());
+	app.at("/r/:sub/wiki/pages").get(|r| subreddit::wiki_pages(r).boxed());
+	app.at("/r/:sub/wiki/revisions/*page").get(|r| subreddit::wiki_revisions(r).boxed());
 	app.at("/r/:sub/wiki/*page").get(|r| subreddit::wiki(r).boxed());
 
 	app.at("/r/:sub/about/sidebar").get(|r| subreddit::sidebar(r).boxed());
@@ -356,6 +428,12 @@ async fn main() {
 	app.at("/wiki").get(|r| subreddit::wiki(r).boxed());
 	app.at("/wiki/*page").get(|r| subreddit::wiki(r).boxed());
 
+	// Other shapes of Reddit links
+	app.at("/gallery/:id").get(|r| links::post_alias(r).boxed());
+	app.at("/poll/:id").get(|r| links::post_alias(r).boxed());
+	app.at("/video/:id").get(|r| links::video(r).boxed());
+	app.at("/media").get(|r| links::media(r).boxed());
+
 	// Search all of Reddit
 	app.at("/search").get(|r| search::find(r).boxed());
 
@@ -366,24 +444,9 @@ async fn main() {
 	app.at("/info").get(|r| instance_info::instance_info(r).boxed());
 	app.at("/info.:extension").get(|r| instance_info::instance_info(r).boxed());
 
-	// Handle obfuscated share links.
-	// Note that this still forces the server to follow the share link to get to the post, so maybe this wants to be updated with a warning before it follow it
-	app.at("/r/:sub/s/:id").get(|req: Request<Body>| {
-		Box::pin(async move {
-			let sub = req.param("sub").unwrap_or_default();
-			match req.param("id").as_deref() {
-				// Share link
-				Some(id) if (8..12).contains(&id.len()) => match canonical_path(format!("/r/{sub}/s/{id}"), 3).await {
-					Ok(Some(path)) => Ok(redirect(&path)),
-					Ok(None) => error(req, "Post ID is invalid. It may point to a post on a community that has been banned.").await,
-					Err(e) => error(req, &e).await,
-				},
-
-				// Error message for unknown pages
-				_ => error(req, "Nothing here").await,
-			}
-		})
-	});
+	// Handle obfuscated share links. Following one means asking Reddit, so
+	// users are told about it first unless the link was resolved before.
+	app.at("/r/:sub/s/:id").get(|r| links::share(r).boxed()).post(|r| links::share(r).boxed());
 
 	app.at("/:id").get(|req: Request<Body>| {
 		Box::pin(async move {
@@ -411,6 +474,9 @@ async fn main() {
 
 	println!("Running Redlib v{} on {listener}!", env!("CARGO_PKG_VERSION"));
 
+	// Serve Gemini alongside, if the instance has a certificate for it
+	tokio::spawn(gemini::listen());
+
 	let server = app.listen(&listener);
 
 	// Run this server for... forever!
@@ -0,0 +1,480 @@
+//! Reddit-flavoured Markdown, rendered from the source Reddit sends alongside
+//! its own HTML. On top of CommonMark with tables and strikethrough, this
+//! handles spoilers (`>!text!<`), superscript (`^word` and `^(a few words)`),
+//! `r/` and `u/` links, bare URLs and media embedded in comments. Code blocks
+//! are highlighted, and the HTML is passed through an allowlist before it
+//! reaches a page.
+
+use std::sync::LazyLock;
+
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag, TagEnd, TextMergeStream};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::highlight::highlight;
+
+static SUBREDDIT_OR_USER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^/?(r|u)/([A-Za-z0-9_-]{2,21})").unwrap());
+static BARE_URL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^https?://[^\s<>"]+"#).unwrap());
+static LINE_SPOILER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^( {0,3})>!").unwrap());
+static HEADING_WITHOUT_SPACE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(#{1,6})([^#\s])").unwrap());
+
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^<(/?)([a-zA-Z][a-zA-Z0-9]*)((?:\s+[a-zA-Z-]+(?:\s*=\s*"[^"<]*")?)*)\s*/?>"#).unwrap());
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"([a-zA-Z-]+)(?:\s*=\s*"([^"<]*)")?"#).unwrap());
+static TEXT_ALIGN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^text-align: ?(left|center|right);?$").unwrap());
+
+/// Tags allowed through [`sanitize`], with the attributes each may keep
+const ALLOWED_TAGS: &[(&str, &[&str])] = &[
+	("a", &["href", "title"]),
+	("blockquote", &[]),
+	("br", &[]),
+	("code", &["class"]),
+	("del", &[]),
+	("div", &["class"]),
+	("em", &[]),
+	("h1", &[]),
+	("h2", &[]),
+	("h3", &[]),
+	("h4", &[]),
+	("h5", &[]),
+	("h6", &[]),
+	("hr", &[]),
+	("li", &[]),
+	("ol", &["start"]),
+	("p", &[]),
+	("pre", &[]),
+	("span", &["class"]),
+	("strong", &[]),
+	("sup", &[]),
+	("table", &[]),
+	("tbody", &[]),
+	("td", &["style"]),
+	("th", &["style"]),
+	("thead", &[]),
+	("tr", &[]),
+	("ul", &[]),
+];
+
+const VOID_TAGS: &[&str] = &["br", "hr"];
+
+const SPOILER_OPEN: &str = "<span class=\"md-spoiler-text\">";
+
+/// Render Reddit-flavoured Markdown to sanitised HTML wrapped in `<div class="md">`,
+/// like Reddit's own `*_html` fields. `media_metadata` resolves images and GIFs
+/// embedded in comments.
+pub fn render(md: &str, media_metadata: &Value) -> String {
+	if md.trim().is_empty() {
+		return String::new();
+	}
+
+	let source = preprocess(md);
+	let parser = TextMergeStream::new(Parser::new_ext(&source, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH));
+
+	let mut events = Vec::new();
+	let mut state = InlineState::default();
+	let mut code_block: Option<(CodeBlockKind, String)> = None;
+	let mut in_image = false;
+
+	for event in parser {
+		match event {
+			Event::Start(Tag::Image { dest_url, .. }) => {
+				in_image = true;
+				events.extend(embedded_media(&dest_url, media_metadata));
+			}
+			Event::End(TagEnd::Image) => in_image = false,
+			// The image's alt text is replaced with the media itself
+			_ if in_image => {}
+			Event::Start(Tag::CodeBlock(kind)) => code_block = Some((kind, String::new())),
+			Event::Text(text) if code_block.is_some() => {
+				if let Some((_, code)) = code_block.as_mut() {
+					code.push_str(&text);
+				}
+			}
+			Event::End(TagEnd::CodeBlock) => {
+				let Some((kind, code)) = code_block.take() else {
+					continue;
+				};
+				let info = match &kind {
+					CodeBlockKind::Fenced(info) => info.to_string(),
+					CodeBlockKind::Indented => String::new(),
+				};
+				match highlight(&code, &info) {
+					Some((language, html)) => events.push(Event::Html(format!("<pre><code class=\"language-{language}\">{html}</code></pre>\n").into())),
+					None => events.extend([Event::Start(Tag::CodeBlock(kind)), Event::Text(code.into()), Event::End(TagEnd::CodeBlock)]),
+				}
+			}
+			Event::Start(Tag::Link { .. }) => {
+				state.links += 1;
+				events.push(event);
+			}
+			Event::End(TagEnd::Link) => {
+				state.links = state.links.saturating_sub(1);
+				events.push(event);
+			}
+			Event::Text(text) => reddit_inline(&text, &mut state, &mut events),
+			// Reddit doesn't allow HTML in Markdown, so show it as written
+			Event::Html(html) | Event::InlineHtml(html) => events.push(Event::Text(html)),
+			Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::TableCell | TagEnd::Item) => {
+				// Spoilers don't continue past the end of a block
+				for _ in 0..std::mem::take(&mut state.spoilers) {
+					events.push(Event::InlineHtml("</span>".into()));
+				}
+				events.push(event);
+			}
+			_ => events.push(event),
+		}
+	}
+
+	let mut output = String::new();
+	html::push_html(&mut output, events.into_iter());
+	format!("<div class=\"md\">{}</div>", sanitize(&output))
+}
+
+/// Adjust the source where Reddit's Markdown parts ways with CommonMark, outside code blocks
+fn preprocess(md: &str) -> String {
+	let mut in_fence = false;
+	md.lines()
+		.map(|line| {
+			let trimmed = line.trim_start();
+			if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+				in_fence = !in_fence;
+			}
+			if in_fence {
+				return line.to_string();
+			}
+			// A spoiler at the start of a line isn't a quote
+			let line = LINE_SPOILER.replace(line, r"$1\>!");
+			// Headings don't need a space after the #
+			HEADING_WITHOUT_SPACE.replace(&line, "$1 $2").into_owned()
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+#[derive(Default)]
+struct InlineState {
+	/// Spoilers opened in the current block
+	spoilers: usize,
+	/// How many links the text is in, since links can't be nested
+	links: usize,
+}
+
+/// Apply Reddit's inline syntax to a run of text
+fn reddit_inline<'a>(text: &str, state: &mut InlineState, events: &mut Vec<Event<'a>>) {
+	let mut plain = String::new();
+	let mut rest = text;
+	let mut prev: Option<char> = None;
+
+	let flush = |plain: &mut String, events: &mut Vec<Event<'a>>| {
+		if !plain.is_empty() {
+			events.push(Event::Text(CowStr::from(std::mem::take(plain))));
+		}
+	};
+
+	while let Some(c) = rest.chars().next() {
+		if rest.starts_with(">!") {
+			flush(&mut plain, events);
+			events.push(Event::InlineHtml(SPOILER_OPEN.into()));
+			state.spoilers += 1;
+			rest = &rest[2..];
+			prev = Some('!');
+			continue;
+		}
+
+		if rest.starts_with("!<") && state.spoilers > 0 {
+			flush(&mut plain, events);
+			events.push(Event::InlineHtml("</span>".into()));
+			state.spoilers -= 1;
+			rest = &rest[2..];
+			prev = Some('<');
+			continue;
+		}
+
+		if c == '^' {
+			let (inner, after) = superscript(&rest[1..]);
+			if !inner.is_empty() {
+				flush(&mut plain, events);
+				events.push(Event::InlineHtml("<sup>".into()));
+				reddit_inline(inner, state, events);
+				events.push(Event::InlineHtml("</sup>".into()));
+				rest = after;
+				prev = Some(')');
+				continue;
+			}
+		}
+
+		// Links only start at a word boundary and never inside another link
+		if state.links == 0 && prev.map_or(true, |prev| !prev.is_alphanumeric() && prev != '/') {
+			if let Some(url) = BARE_URL.find(rest).map(|url| trim_url(url.as_str())) {
+				flush(&mut plain, events);
+				push_link(events, url.to_string(), url.to_string());
+				rest = &rest[url.len()..];
+				prev = url.chars().last();
+				continue;
+			}
+
+			if let Some(caps) = SUBREDDIT_OR_USER.captures(rest) {
+				let written = caps.get(0).map_or("", |m| m.as_str());
+				flush(&mut plain, events);
+				push_link(events, format!("/{}/{}", &caps[1], &caps[2]), written.to_string());
+				rest = &rest[written.len()..];
+				prev = written.chars().last();
+				continue;
+			}
+		}
+
+		plain.push(c);
+		rest = &rest[c.len_utf8()..];
+		prev = Some(c);
+	}
+
+	flush(&mut plain, events);
+}
+
+/// Split `text` after a `^` into the superscripted part and what follows it
+fn superscript(text: &str) -> (&str, &str) {
+	if let Some(grouped) = text.strip_prefix('(') {
+		let mut depth = 1;
+		for (i, c) in grouped.char_indices() {
+			match c {
+				'(' => depth += 1,
+				')' => depth -= 1,
+				_ => {}
+			}
+			if depth == 0 {
+				return (&grouped[..i], &grouped[i + 1..]);
+			}
+		}
+	}
+
+	let end = text.find(char::is_whitespace).unwrap_or(text.len());
+	text.split_at(end)
+}
+
+/// Leave trailing punctuation out of a bare URL, keeping closing parentheses that have a match
+fn trim_url(url: &str) -> &str {
+	let mut url = url;
+	while let Some(last) = url.chars().last() {
+		let unmatched_paren = last == ')' && url.matches('(').count() < url.matches(')').count();
+		if matches!(last, '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '*' | '_' | '~') || unmatched_paren {
+			url = &url[..url.len() - 1];
+		} else {
+			break;
+		}
+	}
+	url
+}
+
+fn push_link<'a>(events: &mut Vec<Event<'a>>, href: String, text: String) {
+	events.push(Event::Start(Tag::Link {
+		link_type: LinkType::Inline,
+		dest_url: href.into(),
+		title: CowStr::Borrowed(""),
+		id: CowStr::Borrowed(""),
+	}));
+	events.push(Event::Text(text.into()));
+	events.push(Event::End(TagEnd::Link));
+}
+
+/// Media embedded with image syntax, like `![img](abc123)` or `![gif](giphy|abc123)`.
+/// Images and GIFs become links, which `rewrite_urls` turns into previews, and
+/// emotes become the `:id:` placeholders `rewrite_emotes` looks for.
+fn embedded_media<'a>(dest: &str, media_metadata: &Value) -> Vec<Event<'a>> {
+	if dest.starts_with("emote|") {
+		let id = dest.rsplit('|').next().unwrap_or_default();
+		return vec![Event::Text(format!(":{id}:").into())];
+	}
+
+	let media = [Some(dest), dest.rsplit_once('|').map(|(key, _)| key)]
+		.into_iter()
+		.flatten()
+		.map(|key| &media_metadata[key]["s"])
+		.find(|media| media.is_object());
+	let url = match media {
+		Some(media) => media["u"].as_str().or_else(|| media["gif"].as_str()).unwrap_or_default().to_string(),
+		None if dest.starts_with("https://") || dest.starts_with("http://") => dest.to_string(),
+		None => String::new(),
+	};
+
+	if url.is_empty() {
+		return Vec::new();
+	}
+	let mut events = Vec::new();
+	push_link(&mut events, url.clone(), url);
+	events
+}
+
+/// Keep only allowlisted tags and attributes, escaping anything that isn't a tag
+/// and closing tags left open.
+pub fn sanitize(html: &str) -> String {
+	let mut output = String::with_capacity(html.len());
+	let mut open: Vec<&str> = Vec::new();
+	let mut rest = html;
+
+	while let Some(i) = rest.find('<') {
+		output.push_str(&rest[..i]);
+		rest = &rest[i..];
+
+		let Some(caps) = TAG.captures(rest) else {
+			output.push_str("&lt;");
+			rest = &rest[1..];
+			continue;
+		};
+		let len = caps[0].len();
+		let name = caps[2].to_ascii_lowercase();
+
+		if let Some((tag, attributes)) = ALLOWED_TAGS.iter().find(|(tag, _)| *tag == name) {
+			if caps[1].is_empty() {
+				output.push('<');
+				output.push_str(tag);
+				for attribute in ATTRIBUTE.captures_iter(&caps[3]) {
+					let key = attribute[1].to_ascii_lowercase();
+					let value = attribute.get(2).map_or("", |value| value.as_str());
+					if attributes.contains(&key.as_str()) && allowed_value(tag, &key, value) {
+						output.push_str(&format!(" {key}=\"{value}\""));
+					}
+				}
+				output.push('>');
+				if !VOID_TAGS.contains(tag) {
+					open.push(tag);
+				}
+			} else if let Some(position) = open.iter().rposition(|open| open == tag) {
+				for tag in open.drain(position..).rev() {
+					output.push_str(&format!("</{tag}>"));
+				}
+			}
+		}
+
+		rest = &rest[len..];
+	}
+
+	output.push_str(rest);
+	for tag in open.into_iter().rev() {
+		output.push_str(&format!("</{tag}>"));
+	}
+	output
+}
+
+fn allowed_value(tag: &str, key: &str, value: &str) -> bool {
+	match key {
+		"href" => {
+			// Browsers decode entities and skip whitespace before reading the scheme
+			let Ok(href) = htmlescape::decode_html(value) else {
+				return false;
+			};
+			let href: String = href.chars().filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control()).collect();
+			match href.find(':') {
+				// A colon after the path, query or fragment starts isn't a scheme
+				Some(colon) if !href[..colon].contains(['/', '?', '#']) => matches!(href[..colon].to_ascii_lowercase().as_str(), "http" | "https" | "mailto"),
+				_ => true,
+			}
+		}
+		"class" => match tag {
+			"div" => value == "md",
+			"span" => value == "md-spoiler-text" || value.strip_prefix("hl-").is_some_and(|class| class.chars().all(|c| c.is_ascii_lowercase())),
+			"code" => value.strip_prefix("language-").is_some_and(|language| language.chars().all(|c| c.is_ascii_lowercase())),
+			_ => false,
+		},
+		"style" => TEXT_ALIGN.is_match(value),
+		"start" => value.chars().all(|c| c.is_ascii_digit()),
+		_ => true,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn md(source: &str) -> String {
+		render(source, &Value::Null)
+	}
+
+	#[test]
+	fn test_reddit_syntax() {
+		assert_eq!(
+			md("Snape >!kills **Dumbledore**!< oops"),
+			"<div class=\"md\"><p>Snape <span class=\"md-spoiler-text\">kills <strong>Dumbledore</strong></span> oops</p>\n</div>"
+		);
+		assert_eq!(md(">!whole line!<"), "<div class=\"md\"><p><span class=\"md-spoiler-text\">whole line</span></p>\n</div>");
+		assert_eq!(md(">!never closed"), "<div class=\"md\"><p><span class=\"md-spoiler-text\">never closed</span></p>\n</div>");
+		assert_eq!(md("> !important"), "<div class=\"md\"><blockquote>\n<p>!important</p>\n</blockquote>\n</div>");
+
+		assert_eq!(
+			md("E = mc^2 and ^(a few words) and ^^nested"),
+			"<div class=\"md\"><p>E = mc<sup>2</sup> and <sup>a few words</sup> and <sup><sup>nested</sup></sup></p>\n</div>"
+		);
+
+		assert_eq!(
+			md("See r/rust, /u/spez and https://example.com/a_(b). Not foo/r/bar or `r/code`."),
+			"<div class=\"md\"><p>See <a href=\"/r/rust\">r/rust</a>, <a href=\"/u/spez\">/u/spez</a> and <a href=\"https://example.com/a_(b)\">https://example.com/a_(b)</a>. Not foo/r/bar or <code>r/code</code>.</p>\n</div>"
+		);
+		assert_eq!(
+			md("[r/rust](https://reddit.com/r/rust)"),
+			"<div class=\"md\"><p><a href=\"https://reddit.com/r/rust\">r/rust</a></p>\n</div>"
+		);
+
+		assert_eq!(md("#Heading\n~~gone~~"), "<div class=\"md\"><h1>Heading</h1>\n<p><del>gone</del></p>\n</div>");
+		assert_eq!(
+			md("```\n>!not a spoiler\n#not a heading\n```"),
+			"<div class=\"md\"><pre><code>&gt;!not a spoiler\n#not a heading\n</code></pre>\n</div>"
+		);
+		assert_eq!(
+			md("a | b\n:-|:-:\n1 | 2"),
+			"<div class=\"md\"><table><thead><tr><th style=\"text-align: left\">a</th><th style=\"text-align: center\">b</th></tr></thead><tbody>\n<tr><td style=\"text-align: left\">1</td><td style=\"text-align: center\">2</td></tr>\n</tbody></table>\n</div>"
+		);
+		assert_eq!(
+			md("```rust\nlet x = 1;\n```"),
+			"<div class=\"md\"><pre><code class=\"language-rust\"><span class=\"hl-keyword\">let</span> x = <span class=\"hl-number\">1</span>;\n</code></pre>\n</div>"
+		);
+		// Lists can start right after a line of text
+		assert_eq!(
+			md("Settings:\n- one\n- two"),
+			"<div class=\"md\"><p>Settings:</p>\n<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n</div>"
+		);
+		assert_eq!(md("  "), "");
+	}
+
+	#[test]
+	fn test_unsafe_markdown() {
+		assert_eq!(md("<script>alert(1)</script>"), "<div class=\"md\">&lt;script&gt;alert(1)&lt;/script&gt;</div>");
+		assert_eq!(
+			md("hi <b onclick=\"x\">there</b>"),
+			"<div class=\"md\"><p>hi &lt;b onclick=\"x\"&gt;there&lt;/b&gt;</p>\n</div>"
+		);
+		assert_eq!(md("[click](javascript:alert(1))"), "<div class=\"md\"><p><a>click</a></p>\n</div>");
+		assert_eq!(
+			md("[mail](mailto:a@b.c) [ok](/r/rust?q=a:b)"),
+			"<div class=\"md\"><p><a href=\"mailto:a@b.c\">mail</a> <a href=\"/r/rust?q=a:b\">ok</a></p>\n</div>"
+		);
+	}
+
+	#[test]
+	fn test_embedded_media() {
+		let metadata: Value = serde_json::from_str(
+			r#"{"abc123":{"status":"valid","e":"Image","m":"image/png","s":{"y":480,"x":640,"u":"https://preview.redd.it/abc123.png?width=640&format=png&s=xyz"},"id":"abc123"},
+			"giphy|xyz":{"status":"valid","e":"AnimatedImage","m":"image/gif","s":{"y":200,"gif":"https://i.giphy.com/media/xyz/giphy.gif","x":200}}}"#,
+		)
+		.unwrap();
+		assert_eq!(
+			render("![img](abc123)", &metadata),
+			"<div class=\"md\"><p><a href=\"https://preview.redd.it/abc123.png?width=640&amp;format=png&amp;s=xyz\">https://preview.redd.it/abc123.png?width=640&amp;format=png&amp;s=xyz</a></p>\n</div>"
+		);
+		assert_eq!(
+			render("![gif](giphy|xyz|downsized)", &metadata),
+			"<div class=\"md\"><p><a href=\"https://i.giphy.com/media/xyz/giphy.gif\">https://i.giphy.com/media/xyz/giphy.gif</a></p>\n</div>"
+		);
+		assert_eq!(render("nice ![img](emote|t5_31hpy|2028)", &metadata), "<div class=\"md\"><p>nice :2028:</p>\n</div>");
+		assert_eq!(render("![img](missing)", &metadata), "<div class=\"md\"><p></p>\n</div>");
+	}
+
+	#[test]
+	fn test_sanitize() {
+		assert_eq!(sanitize("<p onclick=\"x\">a<script>b</script></p>"), "<p>ab</p>");
+		assert_eq!(sanitize("<div class=\"evil md\"><em>open"), "<div><em>open</em></div>");
+		assert_eq!(sanitize("a < b <!-- c -->"), "a &lt; b &lt;!-- c -->");
+		assert_eq!(
+			sanitize("<a href=\"JaVa&#x09;script:x\">x</a><a href=\"//example.com\">y</a>"),
+			"<a>x</a><a href=\"//example.com\">y</a>"
+		);
+		assert_eq!(sanitize("</span><span class=\"md-spoiler-text\">s</em></span>"), "<span class=\"md-spoiler-text\">s</span>");
+	}
+}
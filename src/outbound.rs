@@ -0,0 +1,443 @@
+//! Rewriting of links that leave Redlib. Tracking parameters are removed using
+//! rules in the [ClearURLs](https://docs.clearurls.xyz/latest/specs/rules/)
+//! format: the built-in rules below, plus any the operator points
+//! `REDLIB_TRACKING_RULES_FILE` at (like ClearURLs' own `data.min.json`).
+//! Links to sites with privacy-respecting front-ends can then be pointed at
+//! the ones the instance (`REDLIB_FRONTENDS`) or the user has picked.
+
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use crate::config::get_setting;
+use crate::utils::{lite_images, Preferences};
+
+use log::{error, warn};
+use percent_encoding::percent_decode_str;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use url::{Position, Url};
+
+/// Absolute links in rendered HTML
+static OUTBOUND_LINK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"href="(https?://[^"]+)""#).unwrap());
+
+const BUILTIN_RULES: &str = r#"{
+	"providers": {
+		"global": {
+			"urlPattern": ".*",
+			"rules": [
+				"utm_[a-z_]*", "fbclid", "gclid", "gclsrc", "dclid", "msclkid", "yclid", "twclid", "ttclid", "igshid", "igsh",
+				"mc_cid", "mc_eid", "_hsenc", "_hsmi", "mkt_tok", "oly_anon_id", "oly_enc_id", "vero_id", "wickedid", "_openstat",
+				"ref_src", "ref_url"
+			]
+		},
+		"youtube": {
+			"urlPattern": "^https?://(?:[a-z0-9-]+\\.)*(?:youtube\\.com|youtu\\.be)",
+			"rules": ["si", "feature", "pp", "kw"]
+		},
+		"spotify": {
+			"urlPattern": "^https?://(?:[a-z0-9-]+\\.)*spotify\\.com",
+			"rules": ["si", "nd", "context"]
+		},
+		"twitter": {
+			"urlPattern": "^https?://(?:[a-z0-9-]+\\.)*(?:twitter\\.com|x\\.com)",
+			"rules": ["s", "t"]
+		},
+		"amazon": {
+			"urlPattern": "^https?://(?:[a-z0-9-]+\\.)*amazon(?:\\.[a-z]{2,3}){1,2}",
+			"rules": ["pd_rd_[a-z]*", "pf_rd_[a-z]*", "qid", "sr", "srs", "crid", "sprefix", "ref_?", "_encoding", "content-id", "psc"],
+			"referralMarketing": ["tag", "ascsubtag"],
+			"rawRules": ["/ref=[^/?]*"]
+		},
+		"tiktok": {
+			"urlPattern": "^https?://(?:[a-z0-9-]+\\.)*tiktok\\.com",
+			"rules": ["is_from_webapp", "is_copy_url", "sender_device", "sender_web_id", "share_app_id", "share_item_id", "share_link_id", "_r", "_t"]
+		},
+		"google": {
+			"urlPattern": "^https?://(?:[a-z0-9-]+\\.)*google(?:\\.[a-z]{2,3}){1,2}",
+			"rules": ["ved", "ei", "sei", "gs_lcp", "gs_lp", "sclient", "oq", "aqs", "sourceid", "bih", "biw"],
+			"exceptions": ["^https?://(?:[a-z0-9-]+\\.)*google(?:\\.[a-z]{2,3}){1,2}/maps"],
+			"redirections": ["^https?://(?:www\\.)?google(?:\\.[a-z]{2,3}){1,2}/url\\?(?:.*&)?(?:q|url)=([^&]+)"]
+		}
+	}
+}"#;
+
+/// Sites with alternative front-ends, by the name used to configure them, and the domains they're reached on
+const SERVICES: [(&str, &[&str]); 6] = [
+	("youtube", &["youtube.com", "youtu.be", "youtube-nocookie.com"]),
+	("twitter", &["twitter.com", "x.com"]),
+	("imgur", &["imgur.com"]),
+	("medium", &["medium.com"]),
+	("tiktok", &["tiktok.com"]),
+	("wikipedia", &["wikipedia.org"]),
+];
+
+static INSTANCE_FRONTENDS: LazyLock<Frontends> = LazyLock::new(|| {
+	Frontends::parse(&get_setting("REDLIB_FRONTENDS").unwrap_or_default()).unwrap_or_else(|e| {
+		error!("Ignoring REDLIB_FRONTENDS: {e}");
+		Frontends::default()
+	})
+});
+
+static RULES: LazyLock<Rules> = LazyLock::new(|| {
+	let mut rules = Rules::parse(BUILTIN_RULES).unwrap_or_default();
+	if let Some(path) = get_setting("REDLIB_TRACKING_RULES_FILE") {
+		match std::fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|json| Rules::parse(&json)) {
+			Ok(extra) => rules.0.extend(extra.0),
+			Err(e) => error!("Failed to load tracking rules from {path}: {e}"),
+		}
+	}
+	rules
+});
+
+/// A rules file, with providers keyed by name
+#[derive(Deserialize)]
+struct RulesFile {
+	providers: BTreeMap<String, ProviderRules>,
+}
+
+/// A provider as written in a rules file. Fields Redlib doesn't use, like
+/// `completeProvider` and `forceRedirection`, are ignored.
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct ProviderRules {
+	url_pattern: String,
+	rules: Vec<String>,
+	referral_marketing: Vec<String>,
+	raw_rules: Vec<String>,
+	exceptions: Vec<String>,
+	redirections: Vec<String>,
+}
+
+/// The rules for URLs matching `url_pattern`, compiled
+struct Provider {
+	url_pattern: Regex,
+	/// Matched against whole parameter names
+	params: Vec<Regex>,
+	/// Removed from the URL wherever they match
+	raw_rules: Vec<Regex>,
+	/// URLs the provider doesn't apply to
+	exceptions: Vec<Regex>,
+	/// URLs that only forward to another one, captured in the first group
+	redirections: Vec<Regex>,
+}
+
+impl Provider {
+	fn compile(name: &str, rules: ProviderRules) -> Result<Self, String> {
+		let url_pattern = compile(&rules.url_pattern).map_err(|e| format!("{name}: {e}"))?;
+
+		// Rules are written for JavaScript, so skip the ones Rust's regex engine can't handle
+		let compile_all = |patterns: Vec<String>, anchor: bool| -> Vec<Regex> {
+			patterns
+				.into_iter()
+				.filter_map(|pattern| {
+					let pattern = if anchor { format!("^(?:{pattern})$") } else { pattern };
+					compile(&pattern).inspect_err(|e| warn!("Skipping tracking rule in {name}: {e}")).ok()
+				})
+				.collect()
+		};
+
+		Ok(Self {
+			url_pattern,
+			params: compile_all([rules.rules, rules.referral_marketing].concat(), true),
+			raw_rules: compile_all(rules.raw_rules, false),
+			exceptions: compile_all(rules.exceptions, false),
+			redirections: compile_all(rules.redirections, false),
+		})
+	}
+
+	fn applies_to(&self, url: &str) -> bool {
+		self.url_pattern.is_match(url) && !self.exceptions.iter().any(|exception| exception.is_match(url))
+	}
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+	RegexBuilder::new(pattern).case_insensitive(true).build().map_err(|e| e.to_string())
+}
+
+#[derive(Default)]
+struct Rules(Vec<Provider>);
+
+impl Rules {
+	fn parse(json: &str) -> Result<Self, String> {
+		let file: RulesFile = serde_json::from_str(json).map_err(|e| e.to_string())?;
+		let mut providers = Vec::new();
+		for (name, rules) in file.providers {
+			match Provider::compile(&name, rules) {
+				Ok(provider) => providers.push(provider),
+				Err(e) => warn!("Skipping tracking rules for {e}"),
+			}
+		}
+		Ok(Self(providers))
+	}
+
+	fn clean(&self, url: &str) -> String {
+		let mut url = url.to_string();
+
+		// Follow redirectors first, since the URL they forward to may need cleaning too.
+		// The target is re-serialised so characters it decoded to are escaped again.
+		let target = self
+			.0
+			.iter()
+			.filter(|provider| provider.applies_to(&url))
+			.flat_map(|provider| &provider.redirections)
+			.find_map(|redirection| Url::parse(&percent_decode_str(redirection.captures(&url)?.get(1)?.as_str()).decode_utf8_lossy()).ok());
+		if let Some(target) = target.filter(|target| target.scheme() == "http" || target.scheme() == "https") {
+			url = target.to_string();
+		}
+
+		for provider in &self.0 {
+			if !provider.applies_to(&url) {
+				continue;
+			}
+			for raw_rule in &provider.raw_rules {
+				url = raw_rule.replace_all(&url, "").to_string();
+			}
+			url = remove_params(&url, &provider.params);
+		}
+		url
+	}
+}
+
+/// Drop query parameters whose names match one of `params`, leaving the rest
+/// of the URL exactly as it was
+fn remove_params(url: &str, params: &[Regex]) -> String {
+	if params.is_empty() {
+		return url.to_string();
+	}
+	let (without_fragment, fragment) = url.split_once('#').map_or((url, None), |(url, fragment)| (url, Some(fragment)));
+	let Some((base, query)) = without_fragment.split_once('?') else {
+		return url.to_string();
+	};
+
+	let kept: Vec<&str> = query
+		.split('&')
+		.filter(|pair| {
+			let name = pair.split('=').next().unwrap_or_default();
+			!pair.is_empty() && !params.iter().any(|param| param.is_match(&percent_decode_str(name).decode_utf8_lossy()))
+		})
+		.collect();
+
+	let mut cleaned = base.to_string();
+	if !kept.is_empty() {
+		cleaned = format!("{cleaned}?{}", kept.join("&"));
+	}
+	if let Some(fragment) = fragment {
+		cleaned = format!("{cleaned}#{fragment}");
+	}
+	cleaned
+}
+
+/// Remove tracking parameters from a URL
+pub fn clean_url(url: &str) -> String {
+	RULES.clean(url)
+}
+
+/// Alternative front-ends keyed by a service name from `SERVICES` or a domain.
+///
+/// Written as `site:front-end` pairs joined with `+`, like
+/// `youtube:yewtu.be+twitter:nitter.net`. A site can list several front-ends
+/// separated with `|` to pick one at random for each link, or none to keep
+/// linking to the site itself.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Frontends(Vec<(String, Vec<String>)>);
+
+impl Frontends {
+	pub fn parse(value: &str) -> Result<Self, String> {
+		let mut frontends = Vec::new();
+		for pair in value.split(['+', ',', ' ']).filter(|pair| !pair.is_empty()) {
+			let Some((site, hosts)) = pair.split_once(':') else {
+				return Err(format!("\"{pair}\" should look like site:front-end"));
+			};
+
+			let site = site.trim().to_lowercase();
+			let is_domain = site.contains('.') && site.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+			if !is_domain && !SERVICES.iter().any(|(name, _)| *name == site) {
+				return Err(format!("\"{site}\" isn't a known site or a domain"));
+			}
+
+			let hosts = hosts
+				.split('|')
+				.filter(|host| !host.is_empty() && *host != "off")
+				.map(|host| {
+					let host = if host.contains("://") { host.to_string() } else { format!("https://{host}") };
+					match Url::parse(&host) {
+						Ok(url) if url.has_host() && (url.scheme() == "https" || url.scheme() == "http") => Ok(url[..Position::AfterPath].trim_end_matches('/').to_string()),
+						_ => Err(format!("\"{host}\" isn't a valid front-end for {site}")),
+					}
+				})
+				.collect::<Result<Vec<String>, String>>()?;
+			frontends.push((site, hosts));
+		}
+		Ok(Self(frontends))
+	}
+
+	/// The front-ends for a host, if the site is configured. The list is empty
+	/// if links to it should be kept as they are.
+	fn lookup(&self, host: &str) -> Option<&[String]> {
+		let host = host.to_lowercase();
+		let on_domain = |domain: &str| host == domain || host.ends_with(&format!(".{domain}"));
+		self
+			.0
+			.iter()
+			.find(|(site, _)| match SERVICES.iter().find(|(name, _)| name == site) {
+				Some((_, domains)) => domains.iter().any(|domain| on_domain(domain)),
+				None => on_domain(site),
+			})
+			.map(|(_, hosts)| hosts.as_slice())
+	}
+}
+
+/// Point a URL at a front-end for its site, keeping its path, query and fragment
+pub(crate) fn to_frontend(url: &str, frontends: &Frontends) -> String {
+	let Ok(parsed) = Url::parse(url) else {
+		return url.to_string();
+	};
+	let Some(hosts) = parsed.host_str().and_then(|host| frontends.lookup(host).or_else(|| INSTANCE_FRONTENDS.lookup(host))) else {
+		return url.to_string();
+	};
+	if hosts.is_empty() {
+		return url.to_string();
+	}
+
+	let host = &hosts[fastrand::usize(..hosts.len())];
+	format!("{host}{}", &parsed[Position::BeforePath..])
+}
+
+fn rewrite(url: &str, prefs: &Preferences, frontends: &Frontends) -> String {
+	let url = if prefs.keep_tracking_params == "on" { url.to_string() } else { clean_url(url) };
+	to_frontend(&url, frontends)
+}
+
+/// Rewrite an outbound URL according to the user's preferences
+pub fn rewrite_url(url: &str, prefs: &Preferences) -> String {
+	if !(url.starts_with("http://") || url.starts_with("https://")) {
+		return url.to_string();
+	}
+	rewrite(url, prefs, &Frontends::parse(&prefs.frontends).unwrap_or_default())
+}
+
+/// Rewrite the outbound links in rendered HTML according to the user's
+/// preferences, dropping inline images in lite mode
+pub fn rewrite_html(html: &str, prefs: &Preferences) -> String {
+	let frontends = Frontends::parse(&prefs.frontends).unwrap_or_default();
+	let html = if prefs.lite == "on" { lite_images(html) } else { html.to_string() };
+	OUTBOUND_LINK_REGEX
+		.replace_all(&html, |caps: &regex::Captures| {
+			let url = htmlescape::decode_html(&caps[1]).unwrap_or_else(|_| caps[1].replace("&amp;", "&"));
+			format!(r#"href="{}""#, htmlescape::encode_attribute(&rewrite(&url, prefs, &frontends)))
+		})
+		.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_builtin_rules() {
+		assert_eq!(
+			clean_url("https://example.com/article?id=5&utm_source=reddit&utm_medium=social&fbclid=abc#comments"),
+			"https://example.com/article?id=5#comments"
+		);
+		assert_eq!(clean_url("https://example.com/?gclid=abc"), "https://example.com/");
+		assert_eq!(
+			clean_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ&si=abc123"),
+			"https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+		);
+		assert_eq!(clean_url("https://youtu.be/dQw4w9WgXcQ?si=abc123&t=42"), "https://youtu.be/dQw4w9WgXcQ?t=42");
+		assert_eq!(clean_url("https://open.spotify.com/track/abc?si=xyz"), "https://open.spotify.com/track/abc");
+		assert_eq!(clean_url("https://x.com/user/status/1?s=20&t=abc"), "https://x.com/user/status/1");
+		assert_eq!(
+			clean_url("https://www.amazon.com/dp/B000/ref=sr_1_1?tag=someone-20&keywords=rust"),
+			"https://www.amazon.com/dp/B000?keywords=rust"
+		);
+
+		// Parameters only trackers on some sites are left alone elsewhere
+		assert_eq!(clean_url("https://example.com/search?si=1&t=2"), "https://example.com/search?si=1&t=2");
+		assert_eq!(clean_url("https://www.google.com/maps/place?ved=1"), "https://www.google.com/maps/place?ved=1");
+		assert_eq!(
+			clean_url("https://www.google.com/url?q=https%3A%2F%2Fexample.com%2F%3Futm_source%3Dgoogle&sa=D"),
+			"https://example.com/"
+		);
+	}
+
+	#[test]
+	fn test_custom_rules() {
+		let rules = Rules::parse(
+			r#"{"providers": {
+				"example": {"urlPattern": "^https?://example\\.org", "rules": ["session", "track_[0-9]+"], "completeProvider": false},
+				"broken": {"urlPattern": "(?<=lookbehind)"},
+				"partly": {"urlPattern": ".*", "rules": ["(?!lookahead)", "ok"]}
+			}}"#,
+		)
+		.unwrap();
+
+		assert_eq!(rules.0.len(), 2);
+		assert_eq!(rules.clean("https://example.org/a?session=1&track_42=2&page=3&ok=4"), "https://example.org/a?page=3");
+		assert_eq!(rules.clean("https://example.com/a?session=1&ok=4"), "https://example.com/a?session=1");
+		assert!(Rules::parse("not json").is_err());
+	}
+
+	#[test]
+	fn test_rewrite_html() {
+		let prefs = Preferences::default();
+		let html = r#"<a href="https://example.com/?a=1&amp;utm_source=x&amp;b=2">link</a> <a href="/r/rust">r/rust</a>"#;
+		assert_eq!(
+			rewrite_html(html, &prefs),
+			format!(
+				r#"<a href="{}">link</a> <a href="/r/rust">r/rust</a>"#,
+				htmlescape::encode_attribute("https://example.com/?a=1&b=2")
+			)
+		);
+		assert_eq!(rewrite_url("https://example.com/?fbclid=abc", &prefs), "https://example.com/");
+		assert_eq!(rewrite_url("/img/abc.jpg", &prefs), "/img/abc.jpg");
+
+		let keep = Preferences {
+			keep_tracking_params: "on".to_string(),
+			..Default::default()
+		};
+		assert_eq!(
+			rewrite_html(html, &keep),
+			format!(
+				r#"<a href="{}">link</a> <a href="/r/rust">r/rust</a>"#,
+				htmlescape::encode_attribute("https://example.com/?a=1&utm_source=x&b=2")
+			)
+		);
+	}
+
+	#[test]
+	fn test_redirect_injection() {
+		let redirect = "https://www.google.com/url?q=https%3A%2F%2Fe.com%2F%22%3E%3Cmeta%20http-equiv%3Drefresh%20content%3D0%3E";
+		assert_eq!(clean_url(redirect), "https://e.com/%22%3E%3Cmeta%20http-equiv=refresh%20content=0%3E");
+
+		let html = format!(r#"<a href="{redirect}">link</a>"#);
+		let rewritten = rewrite_html(&html, &Preferences::default());
+		assert!(!rewritten.contains("<meta"));
+		assert_eq!(rewritten.matches('"').count(), 2);
+	}
+
+	#[test]
+	fn test_frontends() {
+		let frontends = Frontends::parse("youtube:yewtu.be+twitter:https://nitter.net/+example.org:a.example|b.example+imgur:off").unwrap();
+		assert_eq!(
+			to_frontend("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42#top", &frontends),
+			"https://yewtu.be/watch?v=dQw4w9WgXcQ&t=42#top"
+		);
+		assert_eq!(to_frontend("https://youtu.be/dQw4w9WgXcQ", &frontends), "https://yewtu.be/dQw4w9WgXcQ");
+		assert_eq!(to_frontend("https://x.com/user/status/1", &frontends), "https://nitter.net/user/status/1");
+		assert_eq!(to_frontend("https://imgur.com/abc", &frontends), "https://imgur.com/abc");
+		assert_eq!(to_frontend("https://notyoutube.com/watch", &frontends), "https://notyoutube.com/watch");
+		assert!(["https://a.example/page?q=1", "https://b.example/page?q=1"].contains(&to_frontend("https://sub.example.org/page?q=1", &frontends).as_str()));
+
+		assert_eq!(Frontends::parse("").unwrap(), Frontends::default());
+		assert!(Frontends::parse("youtube").is_err());
+		assert!(Frontends::parse("myspace:example.com").is_err());
+		assert!(Frontends::parse("youtube:ftp://example.com").is_err());
+
+		let prefs = Preferences {
+			frontends: "youtube:yewtu.be".to_string(),
+			..Default::default()
+		};
+		assert_eq!(rewrite_url("https://www.youtube.com/watch?v=abc&si=xyz", &prefs), "https://yewtu.be/watch?v=abc");
+	}
+}
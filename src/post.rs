@@ -1,13 +1,17 @@
 #![allow(clippy::cmp_owned)]
 use crate::client::json;
 use crate::config::get_setting;
-use crate::server::RequestExt;
+use crate::feed::{self, Entry, Feed};
+use crate::markdown;
+use crate::server::{ContentSecurityPolicy, RequestExt, CONTENT_SECURITY_POLICY};
 use crate::subreddit::{can_access_quarantine, quarantine};
+use crate::text;
 use crate::utils::{
-	error, format_num, get_filters, nsfw_landing, param, parse_post, rewrite_emotes, setting, template, time, val, Author, Awards, Comment, Flair, FlairPart, Post, Preferences,
+	error, format_num, get_filters, nsfw_landing, param, parse_post, rewrite_emotes, setting, sfw_only, template, time, val, Author, Awards, Comment, Flair, FlairPart, Post,
+	Preferences,
 };
 use askama::Template;
-use hyper::{Body, Request, Response};
+use hyper::{header::HeaderValue, Body, Request, Response};
 use regex::Regex;
 use std::collections::{HashMap, HashSet};
 use std::sync::LazyLock;
@@ -61,7 +65,7 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 		// Otherwise, grab the JSON output from the request
 		Ok(response) => {
 			// Parse the JSON into Post and Comment structs
-			let post = parse_post(&response[0]["data"]["children"][0]).await;
+			let mut post = parse_post(&response[0]["data"]["children"][0]).await;
 
 			let req_url = req.uri().to_string();
 			// Return landing page if this post if this Reddit deems this post
@@ -85,17 +89,35 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 				_ => query_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &query, &req),
 			};
 
+			if text::wanted(&req) {
+				return Ok(text::post(&req, &post, &comments, &sort));
+			}
+
+			let prefs = Preferences::new(&req);
+			if let Some(embed) = post.embed.as_mut() {
+				embed.use_frontend(&prefs);
+				// Load an embedded player only once the user has asked for it
+				if param(&url, "embed").is_some_and(|embed| embed == "on") {
+					embed.load(req.headers().get("host").and_then(|host| host.to_str().ok()).unwrap_or_default());
+				}
+			}
+			let csp = post.embed.as_ref().filter(|embed| embed.loaded).map(|embed| embed.csp(CONTENT_SECURITY_POLICY));
+
 			// Use the Post and Comment structs to generate a website to show users
-			Ok(template(&PostTemplate {
+			let mut response = template(&PostTemplate {
 				comments,
 				post,
 				url_without_query: url.clone().trim_end_matches(&format!("?q={query}&type=comment")).to_string(),
 				sort,
-				prefs: Preferences::new(&req),
+				prefs,
 				single_thread,
 				url: req_url,
 				comment_query: query,
-			}))
+			});
+			if let Some(csp) = csp.and_then(|csp| HeaderValue::from_str(&csp).ok()) {
+				response.extensions_mut().insert(ContentSecurityPolicy(csp));
+			}
+			Ok(response)
 		}
 		// If the Reddit API returns an error, exit and send error page to user
 		Err(msg) => {
@@ -109,9 +131,63 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+/// Feed of the newest comments on a post, for following a discussion
+pub async fn rss(req: Request<Body>) -> Result<Response<Body>, String> {
+	if get_setting("REDLIB_ENABLE_RSS").is_none() {
+		return Ok(error(req, "RSS is disabled on this instance.").await.unwrap_or_default());
+	}
+
+	let id = req.param("id").unwrap_or_default();
+	let sub = req.param("sub").unwrap_or_default();
+	let query = req.uri().query().unwrap_or_default();
+	let sort = param(&format!("?{query}"), "sort").unwrap_or_else(|| "new".to_string());
+
+	let response = json(format!("/comments/{id}.json?{query}&sort={sort}&raw_json=1"), can_access_quarantine(&req, &sub)).await?;
+	let post = parse_post(&response[0]["data"]["children"][0]).await;
+	if post.nsfw && sfw_only() {
+		return Ok(error(req, "This instance only shows SFW content.").await.unwrap_or_default());
+	}
+
+	let mut comments = Vec::new();
+	flatten_comments(
+		parse_comments(&response[1], &post.permalink, &post.author.name, "", &feed::filters(&req), &req),
+		&mut comments,
+	);
+	if sort == "new" {
+		comments.sort_by_key(|comment| std::cmp::Reverse(comment.created_ts));
+	}
+
+	let feed = Feed {
+		title: format!("Comments on \"{}\"", post.title),
+		description: format!("r/{}", post.community),
+		link: post.permalink.clone(),
+		entries: comments.iter().map(|comment| Entry::from_comment(comment, &post.title)).collect(),
+	};
+
+	Ok(feed.respond(&req))
+}
+
+/// Flatten a comment tree, leaving out "more comments" stubs and filtered authors
+fn flatten_comments(comments: Vec<Comment>, flat: &mut Vec<Comment>) {
+	for mut comment in comments {
+		let replies = std::mem::take(&mut comment.replies);
+		if comment.kind == "t1" && !comment.is_filtered {
+			flat.push(comment);
+		}
+		flatten_comments(replies, flat);
+	}
+}
+
 // COMMENTS
 
-fn parse_comments(json: &serde_json::Value, post_link: &str, post_author: &str, highlighted_comment: &str, filters: &HashSet<String>, req: &Request<Body>) -> Vec<Comment> {
+pub(crate) fn parse_comments(
+	json: &serde_json::Value,
+	post_link: &str,
+	post_author: &str,
+	highlighted_comment: &str,
+	filters: &HashSet<String>,
+	req: &Request<Body>,
+) -> Vec<Comment> {
 	// Parse the comment JSON into a Vector of Comments
 	let comments = json["data"]["children"].as_array().map_or(Vec::new(), std::borrow::ToOwned::to_owned);
 
@@ -177,7 +253,7 @@ fn build_comment(
 			get_setting("REDLIB_PUSHSHIFT_FRONTEND").unwrap_or_else(|| String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
 		)
 	} else {
-		rewrite_emotes(&data["media_metadata"], val(comment, "body_html"))
+		rewrite_emotes(&data["media_metadata"], markdown::render(&val(comment, "body"), &data["media_metadata"]))
 	};
 	let kind = comment["kind"].as_str().unwrap_or_default().to_string();
 
@@ -242,6 +318,7 @@ fn build_comment(
 		},
 		rel_time,
 		created,
+		created_ts: unix_time as i64,
 		edited,
 		replies,
 		highlighted,
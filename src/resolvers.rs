@@ -0,0 +1,408 @@
+//! Resolvers for media hosted outside Reddit. Link posts to Imgur, Redgifs and
+//! Streamable are looked up with each host's API so the media can be shown
+//! inline, proxied through `/imgur`, `/redgifs` and `/streamable` like Reddit's
+//! own media. Each resolver is off unless the instance enables it, and Imgur's
+//! also needs the instance's own API client ID.
+
+use std::time::Duration;
+
+use crate::client::{proxy, CLIENT};
+use crate::config::get_setting;
+use crate::server::RequestExt;
+use crate::utils::{error, url_path_basename, GalleryMedia, Media};
+
+use cached::proc_macro::cached;
+use hyper::{Body, Request, Response};
+use log::warn;
+use serde_json::Value;
+use url::{Position, Url};
+
+/// How long to wait for a host before showing the post as a plain link
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A link to media on a host with a resolver
+#[derive(Debug, PartialEq, Eq)]
+enum Resolver {
+	/// An album (`imgur.com/a/:id`), gallery post (`imgur.com/gallery/:slug-:id`) or single image page (`imgur.com/:id`)
+	Imgur {
+		id: String,
+		album: bool,
+	},
+	/// A GIFV page on `i.imgur.com`, which is just an MP4
+	ImgurGifv(String),
+	Redgifs(String),
+	Streamable(String),
+}
+
+impl Resolver {
+	fn from_url(url: &str) -> Option<Self> {
+		let url = Url::parse(url).ok()?;
+		let host = url.host_str()?.trim_start_matches("www.").trim_start_matches("m.");
+		let segments: Vec<&str> = url.path_segments()?.filter(|segment| !segment.is_empty()).collect();
+		let valid = |id: &str| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric());
+
+		let resolver = match (host, segments.as_slice()) {
+			("imgur.com", ["a" | "gallery", slug]) => Self::Imgur {
+				id: slug.rsplit('-').next().unwrap_or_default().to_string(),
+				album: true,
+			},
+			("imgur.com", [id]) => Self::Imgur { id: id.to_string(), album: false },
+			("i.imgur.com", [file]) => Self::ImgurGifv(file.strip_suffix(".gifv")?.to_string()),
+			("redgifs.com" | "v3.redgifs.com", ["watch" | "ifr", id]) => Self::Redgifs(id.to_lowercase()),
+			("streamable.com", [id] | ["e" | "o", id]) => Self::Streamable(id.to_string()),
+			_ => return None,
+		};
+
+		match &resolver {
+			Self::Imgur { id, .. } | Self::ImgurGifv(id) | Self::Redgifs(id) | Self::Streamable(id) if valid(id) => Some(resolver),
+			_ => None,
+		}
+	}
+
+	/// The name of the resolver in `REDLIB_RESOLVE_{NAME}` and its proxy route
+	fn name(&self) -> &'static str {
+		match self {
+			Self::Imgur { .. } | Self::ImgurGifv(_) => "imgur",
+			Self::Redgifs(_) => "redgifs",
+			Self::Streamable(_) => "streamable",
+		}
+	}
+
+	async fn resolve(&self) -> Result<Option<Resolved>, String> {
+		match self {
+			Self::Imgur { id, album } => {
+				let client_id = get_setting("REDLIB_IMGUR_CLIENT_ID").unwrap_or_default();
+				let kind = if *album { "albums" } else { "media" };
+				let json = fetch_json(format!("https://api.imgur.com/post/v1/{kind}/{id}?client_id={client_id}&include=media"), None).await?;
+				Ok(parse_imgur(&json))
+			}
+			Self::ImgurGifv(id) => Ok(Some(Resolved {
+				post_type: "gif",
+				url: format!("/imgur/{id}.mp4"),
+				width: 0,
+				height: 0,
+				poster: format!("/imgur/{id}.jpg"),
+				gallery: Vec::new(),
+			})),
+			Self::Redgifs(id) => {
+				let token = redgifs_token().await?;
+				let json = fetch_json(format!("https://api.redgifs.com/v2/gifs/{id}"), Some(token)).await?;
+				Ok(parse_redgifs(&json))
+			}
+			Self::Streamable(id) => {
+				let json = fetch_json(format!("https://api.streamable.com/videos/{id}"), None).await?;
+				Ok(parse_streamable(&json))
+			}
+		}
+	}
+}
+
+/// Media found by a resolver, with URLs already pointing at the proxy routes
+struct Resolved {
+	post_type: &'static str,
+	url: String,
+	width: i64,
+	height: i64,
+	poster: String,
+	gallery: Vec<GalleryMedia>,
+}
+
+fn enabled(name: &str) -> bool {
+	let on = get_setting(&format!("REDLIB_RESOLVE_{}", name.to_uppercase())).is_some_and(|value| value == "on");
+	on && (name != "imgur" || get_setting("REDLIB_IMGUR_CLIENT_ID").is_some_and(|id| !id.is_empty()))
+}
+
+/// Turn a link post's URL into inline media, if a resolver for its host is
+/// enabled and finds something to show
+pub async fn resolve(url: &str, permalink: &str) -> Option<(String, Media, Vec<GalleryMedia>)> {
+	let resolver = Resolver::from_url(url).filter(|resolver| enabled(resolver.name()))?;
+
+	let resolved = match tokio::time::timeout(RESOLVE_TIMEOUT, resolver.resolve()).await {
+		Ok(Ok(resolved)) => resolved?,
+		Ok(Err(e)) => {
+			warn!("Failed to resolve {url}: {e}");
+			return None;
+		}
+		Err(_) => {
+			warn!("Timed out resolving {url}");
+			return None;
+		}
+	};
+
+	let download_name = if resolved.gallery.is_empty() {
+		format!(
+			"redlib_{}_{}",
+			url_path_basename(permalink),
+			url_path_basename(resolved.url.split('?').next().unwrap_or_default())
+		)
+	} else {
+		String::new()
+	};
+
+	Some((
+		resolved.post_type.to_string(),
+		Media {
+			url: resolved.url,
+			alt_url: String::new(),
+			width: resolved.width,
+			height: resolved.height,
+			poster: resolved.poster,
+			download_name,
+			variants: Vec::new(),
+		},
+		resolved.gallery,
+	))
+}
+
+#[cached(size = 500, time = 600, result = true)]
+async fn fetch_json(url: String, bearer: Option<String>) -> Result<Value, String> {
+	let mut builder = CLIENT.get(&url).header("Accept", "application/json");
+	if let Some(token) = bearer {
+		builder = builder.header("Authorization", format!("Bearer {token}"));
+	}
+
+	let res = builder.send().await.map_err(|e| e.to_string())?;
+	if !res.status().is_success() {
+		return Err(format!("{} responded with {}", url.split('?').next().unwrap_or_default(), res.status()));
+	}
+	res.json::<Value>().await.map_err(|e| e.to_string())
+}
+
+/// Redgifs only answers API requests with a token, which anyone can get and lasts a day
+#[cached(time = 3600, result = true)]
+async fn redgifs_token() -> Result<String, String> {
+	let json = fetch_json("https://api.redgifs.com/v2/auth/temporary".to_string(), None).await?;
+	json["token"].as_str().map(String::from).ok_or_else(|| "Redgifs didn't return a token".to_string())
+}
+
+/// Point media on a resolver's host at its proxy route
+fn proxied(url: &str) -> Option<String> {
+	let url = Url::parse(&if url.starts_with("//") { format!("https:{url}") } else { url.to_string() }).ok()?;
+	let path = &url[Position::BeforePath..];
+	match url.host_str()? {
+		"i.imgur.com" => Some(format!("/imgur{path}")),
+		"media.redgifs.com" => Some(format!("/redgifs{path}")),
+		host => host
+			.strip_suffix(".streamable.com")
+			.filter(|subdomain| valid_subdomain(subdomain))
+			.map(|subdomain| format!("/streamable/{subdomain}{path}")),
+	}
+}
+
+fn valid_subdomain(subdomain: &str) -> bool {
+	!subdomain.is_empty() && subdomain.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Albums become galleries of their images; a single image or video is shown on its own
+fn parse_imgur(json: &Value) -> Option<Resolved> {
+	let media = json["media"].as_array()?;
+	let item = |media: &Value| -> Option<(String, i64, i64)> {
+		Some((
+			proxied(media["url"].as_str()?)?,
+			media["width"].as_i64().unwrap_or_default(),
+			media["height"].as_i64().unwrap_or_default(),
+		))
+	};
+
+	if let [single] = media.as_slice() {
+		let (url, width, height) = item(single)?;
+		let video = single["type"] == "video";
+		return Some(Resolved {
+			post_type: if video { "video" } else { "image" },
+			poster: if video {
+				url.rsplit_once('.').map(|(base, _)| format!("{base}.jpg")).unwrap_or_default()
+			} else {
+				String::new()
+			},
+			url,
+			width,
+			height,
+			gallery: Vec::new(),
+		});
+	}
+
+	// Galleries only show images, so videos in mixed albums are left out
+	let gallery: Vec<GalleryMedia> = media
+		.iter()
+		.filter(|media| media["type"] == "image")
+		.filter_map(|media| {
+			let (url, width, height) = item(media)?;
+			Some(GalleryMedia {
+				url,
+				width,
+				height,
+				caption: media["metadata"]["description"].as_str().unwrap_or_default().to_string(),
+				outbound_url: String::new(),
+				variants: Vec::new(),
+			})
+		})
+		.collect();
+
+	let first = gallery.first()?;
+	Some(Resolved {
+		post_type: "gallery",
+		url: first.url.clone(),
+		width: first.width,
+		height: first.height,
+		poster: String::new(),
+		gallery,
+	})
+}
+
+fn parse_redgifs(json: &Value) -> Option<Resolved> {
+	let gif = &json["gif"];
+	let urls = &gif["urls"];
+	Some(Resolved {
+		post_type: "video",
+		url: proxied(urls["hd"].as_str().or_else(|| urls["sd"].as_str())?)?,
+		width: gif["width"].as_i64().unwrap_or_default(),
+		height: gif["height"].as_i64().unwrap_or_default(),
+		poster: urls["poster"].as_str().and_then(proxied).unwrap_or_default(),
+		gallery: Vec::new(),
+	})
+}
+
+fn parse_streamable(json: &Value) -> Option<Resolved> {
+	// Anything but 2 means the video is still processing or has failed
+	if json["status"] != 2 {
+		return None;
+	}
+	let file = ["mp4", "mp4-mobile"].iter().map(|name| &json["files"][name]).find(|file| file["url"].is_string())?;
+	Some(Resolved {
+		post_type: "video",
+		url: proxied(file["url"].as_str()?)?,
+		width: file["width"].as_i64().unwrap_or_default(),
+		height: file["height"].as_i64().unwrap_or_default(),
+		poster: json["thumbnail_url"].as_str().and_then(proxied).unwrap_or_default(),
+		gallery: Vec::new(),
+	})
+}
+
+/// Proxy media for a resolver, as long as the resolver is enabled
+pub async fn proxy_media(req: Request<Body>, name: &str) -> Result<Response<Body>, String> {
+	if !enabled(name) {
+		return error(req, "Nothing here").await;
+	}
+
+	match name {
+		"imgur" => proxy(req, "https://i.imgur.com/{path}").await,
+		"redgifs" => proxy(req, "https://media.redgifs.com/{path}").await,
+		"streamable" if valid_subdomain(&req.param("host").unwrap_or_default()) => proxy(req, "https://{host}.streamable.com/{path}").await,
+		_ => error(req, "Nothing here").await,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sealed_test::prelude::*;
+
+	#[test]
+	#[sealed_test(env = [("REDLIB_RESOLVE_IMGUR", "on"), ("REDLIB_RESOLVE_STREAMABLE", "on")])]
+	fn test_imgur_needs_client_id() {
+		assert!(!enabled("imgur"));
+		assert!(enabled("streamable"));
+		assert!(!enabled("redgifs"));
+	}
+
+	#[test]
+	#[sealed_test(env = [("REDLIB_RESOLVE_IMGUR", "on"), ("REDLIB_IMGUR_CLIENT_ID", "abc123")])]
+	fn test_imgur_with_client_id() {
+		assert!(enabled("imgur"));
+	}
+
+	#[test]
+	fn test_resolver_from_url() {
+		assert_eq!(
+			Resolver::from_url("https://imgur.com/a/AbC123"),
+			Some(Resolver::Imgur {
+				id: "AbC123".to_string(),
+				album: true
+			})
+		);
+		assert_eq!(
+			Resolver::from_url("https://imgur.com/gallery/my-cat-did-a-thing-AbC123"),
+			Some(Resolver::Imgur {
+				id: "AbC123".to_string(),
+				album: true
+			})
+		);
+		assert_eq!(
+			Resolver::from_url("https://m.imgur.com/XyZ789"),
+			Some(Resolver::Imgur {
+				id: "XyZ789".to_string(),
+				album: false
+			})
+		);
+		assert_eq!(Resolver::from_url("https://i.imgur.com/XyZ789.gifv"), Some(Resolver::ImgurGifv("XyZ789".to_string())));
+		assert_eq!(
+			Resolver::from_url("https://www.redgifs.com/watch/HappyLittleTree"),
+			Some(Resolver::Redgifs("happylittletree".to_string()))
+		);
+		assert_eq!(Resolver::from_url("https://streamable.com/e/ab12c"), Some(Resolver::Streamable("ab12c".to_string())));
+
+		assert_eq!(Resolver::from_url("https://i.imgur.com/XyZ789.jpg"), None);
+		assert_eq!(Resolver::from_url("https://imgur.com/a/bad.id"), None);
+		assert_eq!(Resolver::from_url("https://example.com/watch/abc"), None);
+	}
+
+	#[test]
+	fn test_parse_imgur() {
+		let album: Value = serde_json::from_str(
+			r#"{"id":"AbC123","title":"Cats","media_count":3,"media":[
+				{"id":"one","url":"https://i.imgur.com/one.jpeg","ext":"jpeg","type":"image","width":800,"height":600,"metadata":{"title":"","description":"First"}},
+				{"id":"two","url":"https://i.imgur.com/two.png","ext":"png","type":"image","width":640,"height":480,"metadata":{"title":"","description":""}},
+				{"id":"three","url":"https://i.imgur.com/three.mp4","ext":"mp4","type":"video","width":720,"height":1280,"metadata":{"title":"","description":""}}
+			]}"#,
+		)
+		.unwrap();
+		let resolved = parse_imgur(&album).unwrap();
+		assert_eq!(resolved.post_type, "gallery");
+		assert_eq!(resolved.gallery.len(), 2);
+		assert_eq!(resolved.gallery[0].url, "/imgur/one.jpeg");
+		assert_eq!(resolved.gallery[0].caption, "First");
+
+		let video: Value = serde_json::from_str(
+			r#"{"id":"XyZ789","media_count":1,"media":[{"id":"XyZ789","url":"https://i.imgur.com/XyZ789.mp4","ext":"mp4","type":"video","width":720,"height":1280,"metadata":{}}]}"#,
+		)
+		.unwrap();
+		let resolved = parse_imgur(&video).unwrap();
+		assert_eq!(resolved.post_type, "video");
+		assert_eq!(resolved.url, "/imgur/XyZ789.mp4");
+		assert_eq!(resolved.poster, "/imgur/XyZ789.jpg");
+
+		assert!(parse_imgur(&serde_json::from_str(r#"{"errors":[{"code":"404"}]}"#).unwrap()).is_none());
+	}
+
+	#[test]
+	fn test_parse_redgifs() {
+		let json: Value = serde_json::from_str(
+			r#"{"gif":{"id":"happylittletree","width":1080,"height":1920,"hasAudio":true,"urls":{
+				"sd":"https://media.redgifs.com/HappyLittleTree-mobile.mp4",
+				"hd":"https://media.redgifs.com/HappyLittleTree.mp4",
+				"poster":"https://media.redgifs.com/HappyLittleTree-poster.jpg"
+			}},"user":null}"#,
+		)
+		.unwrap();
+		let resolved = parse_redgifs(&json).unwrap();
+		assert_eq!(resolved.post_type, "video");
+		assert_eq!(resolved.url, "/redgifs/HappyLittleTree.mp4");
+		assert_eq!(resolved.poster, "/redgifs/HappyLittleTree-poster.jpg");
+		assert_eq!((resolved.width, resolved.height), (1080, 1920));
+	}
+
+	#[test]
+	fn test_parse_streamable() {
+		let json: Value = serde_json::from_str(
+			r#"{"status":2,"percent":100,"title":"","thumbnail_url":"//cdn-cf-east.streamable.com/image/ab12c.jpg?Expires=1700000000&Signature=abc",
+			"files":{"mp4":{"status":2,"url":"https://cdn-cf-east.streamable.com/video/mp4/ab12c.mp4?Expires=1700000000&Signature=abc","width":1280,"height":720}}}"#,
+		)
+		.unwrap();
+		let resolved = parse_streamable(&json).unwrap();
+		assert_eq!(resolved.url, "/streamable/cdn-cf-east/video/mp4/ab12c.mp4?Expires=1700000000&Signature=abc");
+		assert_eq!(resolved.poster, "/streamable/cdn-cf-east/image/ab12c.jpg?Expires=1700000000&Signature=abc");
+
+		assert!(parse_streamable(&serde_json::from_str(r#"{"status":1,"files":{}}"#).unwrap()).is_none());
+		assert_eq!(proxied("https://evil.com#.streamable.com/x"), None);
+	}
+}
@@ -0,0 +1,211 @@
+//! Handlers for locally saved (bookmarked) posts and comments.
+
+use crate::server::RequestExt;
+use crate::settings::set_list_cookies;
+use crate::utils::{error, param, redirect, template, Post, Preferences};
+
+use askama::Template;
+use hyper::{header::CONTENT_TYPE, Body, Request, Response};
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+
+/// Bookmarks are kept in cookies, so cap how many can be stored.
+pub const MAX_BOOKMARKS: usize = 1000;
+
+/// Number of bookmarks shown per page on `/saved`.
+const PAGE_SIZE: usize = 25;
+
+/// `SavedTemplate` defines an Askama template for rendering bookmarks.
+#[derive(Template)]
+#[template(path = "saved.html")]
+struct SavedTemplate {
+	/// posts contains both saved posts and saved comments, newest first.
+	/// Comments have an empty title, like in user listings.
+	posts: Vec<Post>,
+	/// Total number of bookmarks stored.
+	total: usize,
+	/// Number of bookmarks that Reddit no longer returns.
+	missing: usize,
+	page: usize,
+	pages: usize,
+	prefs: Preferences,
+	url: String,
+}
+
+/// The JSON document used to export and import bookmarks.
+#[derive(Serialize, Deserialize)]
+struct BookmarksExport {
+	bookmarks: Vec<String>,
+}
+
+/// Check that a bookmark is the fullname of a post (`t3_`) or comment (`t1_`)
+pub fn valid_bookmark(id: &str) -> bool {
+	id.strip_prefix("t3_")
+		.or_else(|| id.strip_prefix("t1_"))
+		.is_some_and(|id| !id.is_empty() && id.len() <= 16 && id.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Add bookmarks to a list, skipping invalid and duplicate entries and
+/// respecting `MAX_BOOKMARKS`
+fn merge_bookmarks(list: &mut Vec<String>, new: impl IntoIterator<Item = String>) {
+	for id in new {
+		let id = id.trim().to_lowercase();
+		if list.len() < MAX_BOOKMARKS && valid_bookmark(&id) && !list.contains(&id) {
+			list.push(id);
+		}
+	}
+}
+
+/// Build a redirect that stores the given bookmarks in cookies
+fn save_bookmarks(req_cookies: &str, path: &str, list: &[String]) -> Response<Body> {
+	let mut response = redirect(path);
+	set_list_cookies(&mut response, req_cookies, "bookmarks", (!list.is_empty()).then_some(list));
+	response
+}
+
+fn cookies_string(req: &Request<Body>) -> String {
+	req.headers().get("cookie").and_then(|hv| hv.to_str().ok()).unwrap_or_default().to_string()
+}
+
+pub async fn get(req: Request<Body>) -> Result<Response<Body>, String> {
+	let prefs = Preferences::new(&req);
+	let url = req.uri().to_string();
+	let query = format!("?{}", req.uri().query().unwrap_or_default());
+
+	// Show the most recently saved items first
+	let bookmarks: Vec<&String> = prefs.bookmarks.iter().rev().collect();
+	let total = bookmarks.len();
+	let pages = total.div_ceil(PAGE_SIZE).max(1);
+	let page = param(&query, "page").and_then(|p| p.parse::<usize>().ok()).unwrap_or(1).clamp(1, pages);
+	let ids: Vec<&String> = bookmarks.into_iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE).collect();
+
+	let mut posts = Vec::new();
+	if !ids.is_empty() {
+		let path = format!("/api/info.json?id={}&raw_json=1", ids.iter().map(|id| id.as_str()).collect::<Vec<_>>().join(","));
+		let (mut fetched, _) = match Post::fetch(&path, false).await {
+			Ok(fetched) => fetched,
+			Err(msg) => return error(req, &msg).await,
+		};
+
+		// Reddit doesn't return items in the requested order
+		for id in &ids {
+			let short_id = &id[3..];
+			if let Some(index) = fetched.iter().position(|post| post.id == short_id) {
+				posts.push(fetched.swap_remove(index));
+			}
+		}
+	}
+
+	Ok(template(&SavedTemplate {
+		missing: ids.len() - posts.len(),
+		posts,
+		total,
+		page,
+		pages,
+		prefs,
+		url,
+	}))
+}
+
+/// Add or remove a bookmark, depending on the last segment of the path
+pub async fn bookmark(req: Request<Body>) -> Result<Response<Body>, String> {
+	let id = req.param("id").unwrap_or_default().to_lowercase();
+	if !valid_bookmark(&id) {
+		return Err("Only posts and comments can be saved".to_string());
+	}
+
+	let remove = req.uri().path().ends_with("/remove");
+	let cookies = cookies_string(&req);
+	let mut list = Preferences::new(&req).bookmarks;
+
+	let body = hyper::body::to_bytes(req.into_body())
+		.await
+		.map_err(|e| format!("Failed to get bytes from request body: {e}"))?;
+	let path = form_urlencoded::parse(&body)
+		.find(|(key, _)| key == "redirect")
+		.map_or_else(|| "/saved".to_string(), |(_, value)| format!("/{}", value.trim_start_matches('/')));
+
+	if remove {
+		list.retain(|saved| saved != &id);
+	} else if list.len() >= MAX_BOOKMARKS {
+		return Err(format!("You can save at most {MAX_BOOKMARKS} items"));
+	} else {
+		merge_bookmarks(&mut list, [id]);
+	}
+
+	Ok(save_bookmarks(&cookies, &path, &list))
+}
+
+/// Download all bookmarks as a JSON file
+pub async fn export(req: Request<Body>) -> Result<Response<Body>, String> {
+	let export = BookmarksExport {
+		bookmarks: Preferences::new(&req).bookmarks,
+	};
+	let body = serde_json::to_string_pretty(&export).map_err(|e| e.to_string())?;
+
+	Ok(
+		Response::builder()
+			.status(200)
+			.header(CONTENT_TYPE, "application/json")
+			.header("content-disposition", "attachment; filename=\"redlib-saved.json\"")
+			.body(body.into())
+			.unwrap_or_default(),
+	)
+}
+
+/// Merge bookmarks from an exported JSON document into the current ones
+pub async fn import(req: Request<Body>) -> Result<Response<Body>, String> {
+	let cookies = cookies_string(&req);
+	let mut list = Preferences::new(&req).bookmarks;
+
+	let body = hyper::body::to_bytes(req.into_body())
+		.await
+		.map_err(|e| format!("Failed to get bytes from request body: {e}"))?;
+
+	if body.len() > 1024 * 1024 {
+		return Err("Request body too large".to_string());
+	}
+
+	let json = form_urlencoded::parse(&body)
+		.find(|(key, _)| key == "bookmarks")
+		.map(|(_, value)| value.into_owned())
+		.ok_or_else(|| "bookmarks parameter not found in request body".to_string())?;
+
+	merge_bookmarks(&mut list, parse_import(&json)?);
+
+	Ok(save_bookmarks(&cookies, "/saved", &list))
+}
+
+/// Accept either an exported document or a bare array of fullnames
+fn parse_import(json: &str) -> Result<Vec<String>, String> {
+	serde_json::from_str::<BookmarksExport>(json)
+		.map(|export| export.bookmarks)
+		.or_else(|_| serde_json::from_str::<Vec<String>>(json))
+		.map_err(|e| format!("Invalid bookmarks file: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_valid_bookmark() {
+		assert!(valid_bookmark("t3_1abcde"));
+		assert!(valid_bookmark("t1_k2xyz9"));
+		assert!(!valid_bookmark("t5_2qh1i"));
+		assert!(!valid_bookmark("t3_"));
+		assert!(!valid_bookmark("t3_abc+t3_def"));
+	}
+
+	#[test]
+	fn test_import_bookmarks() {
+		let mut list = vec!["t3_abc".to_string()];
+		merge_bookmarks(&mut list, parse_import(r#"{"bookmarks": ["t3_ABC", "t1_def", "bad"]}"#).unwrap());
+		assert_eq!(list, vec!["t3_abc", "t1_def"]);
+
+		merge_bookmarks(&mut list, parse_import(r#"["t3_ghi"]"#).unwrap());
+		assert_eq!(list.len(), 3);
+
+		assert!(parse_import("not json").is_err());
+	}
+}
@@ -1,4 +1,6 @@
 #![allow(clippy::cmp_owned)]
+use crate::config;
+use crate::feed::{self, Entry, Feed};
 use crate::utils::{self, catch_random, error, filter_posts, format_num, format_url, get_filters, param, redirect, setting, template, val, Post, Preferences};
 use crate::{
 	client::json,
@@ -8,6 +10,7 @@ use crate::{
 use askama::Template;
 use hyper::{Body, Request, Response};
 use regex::Regex;
+use serde::Serialize;
 use std::sync::LazyLock;
 
 // STRUCTS
@@ -22,8 +25,9 @@ struct SearchParams {
 }
 
 // STRUCTS
-struct Subreddit {
-	name: String,
+#[derive(Serialize)]
+pub(crate) struct Subreddit {
+	pub(crate) name: String,
 	url: String,
 	icon: String,
 	description: String,
@@ -165,7 +169,40 @@ pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
-async fn search_subreddits(q: &str, typed: &str) -> Vec<Subreddit> {
+/// Feed of search results, for following a saved search
+pub async fn rss(req: Request<Body>) -> Result<Response<Body>, String> {
+	if config::get_setting("REDLIB_ENABLE_RSS").is_none() {
+		return Ok(error(req, "RSS is disabled on this instance.").await.unwrap_or_default());
+	}
+
+	let sub = req.param("sub").unwrap_or_default();
+	let query_string = req.uri().query().unwrap_or_default();
+	let Some(query) = param(&format!("?{query_string}"), "q").filter(|q| !q.is_empty()) else {
+		return Ok(error(req, "Search feeds need a query, like ?q=redlib").await.unwrap_or_default());
+	};
+
+	let nsfw_results = if utils::sfw_only() { "" } else { "&include_over_18=on" };
+	let base = if sub.is_empty() { String::new() } else { format!("/r/{sub}") };
+	let path = format!("{}/search.json?{query_string}{nsfw_results}&raw_json=1", base.replace('+', "%2B"));
+
+	let (mut posts, _) = Post::fetch(&path, can_access_quarantine(&req, &sub)).await?;
+	feed::retain_visible(&req, &mut posts);
+
+	let feed = Feed {
+		title: if sub.is_empty() {
+			format!("Search results for \"{query}\"")
+		} else {
+			format!("Search results for \"{query}\" in r/{sub}")
+		},
+		description: String::new(),
+		link: format!("{base}/search?{query_string}"),
+		entries: posts.iter().map(Entry::from_post).collect(),
+	};
+
+	Ok(feed.respond(&req))
+}
+
+pub(crate) async fn search_subreddits(q: &str, typed: &str) -> Vec<Subreddit> {
 	let limit = if typed == "sr_user" { "50" } else { "3" };
 	let subreddit_search_path = format!("/subreddits/search.json?q={}&limit={limit}", q.replace(' ', "+"));
 
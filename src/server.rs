@@ -10,6 +10,7 @@ use hyper::{
 	body,
 	body::HttpBody,
 	header,
+	server::conn::AddrStream,
 	service::{make_service_fn, service_fn},
 	HeaderMap,
 };
@@ -20,10 +21,12 @@ use std::{
 	cmp::Ordering,
 	fmt::Display,
 	io,
+	net::IpAddr,
 	pin::Pin,
 	result::Result,
 	str::{from_utf8, Split},
 	string::ToString,
+	sync::LazyLock,
 };
 use time::OffsetDateTime;
 
@@ -173,6 +176,28 @@ pub struct Route<'a> {
 	path: String,
 }
 
+/// Policy for every page. Pages that need more, like an embedded player, replace
+/// it with a [`ContentSecurityPolicy`] extension on their response.
+pub const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; font-src 'self'; script-src 'self' blob:; manifest-src 'self'; media-src 'self' data: blob: about:; style-src 'self' 'unsafe-inline'; base-uri 'none'; img-src 'self' data:; form-action 'self'; frame-ancestors 'none'; connect-src 'self'; worker-src blob:;";
+
+/// A response extension replacing the default `Content-Security-Policy`. The
+/// other default headers always win over the ones a route (or a proxied
+/// upstream) sets.
+#[derive(Clone)]
+pub struct ContentSecurityPolicy(pub header::HeaderValue);
+
+/// The address a connection came from, added to each of its requests
+#[derive(Clone, Copy)]
+struct RemoteAddr(IpAddr);
+
+/// Reverse proxies whose `X-Forwarded-For` or `X-Real-IP` header is believed.
+/// None are unless the instance lists them, since anyone can send the headers.
+static TRUSTED_PROXIES: LazyLock<Vec<IpAddr>> = LazyLock::new(|| {
+	config::get_setting("REDLIB_TRUSTED_PROXIES")
+		.map(|list| list.split([',', ' ']).filter_map(|addr| addr.trim().parse().ok()).collect())
+		.unwrap_or_default()
+});
+
 pub struct Server {
 	pub default_headers: HeaderMap,
 	router: Router<fn(Request<Body>) -> BoxResponse>,
@@ -199,6 +224,7 @@ pub trait RequestExt {
 	fn set_params(&mut self, params: Params) -> Option<Params>;
 	fn cookies(&self) -> Vec<Cookie<'_>>;
 	fn cookie(&self, name: &str) -> Option<Cookie<'_>>;
+	fn client_ip(&self) -> Option<IpAddr>;
 }
 
 pub trait ResponseExt {
@@ -238,6 +264,25 @@ impl RequestExt for Request<Body> {
 	fn cookie(&self, name: &str) -> Option<Cookie<'_>> {
 		self.cookies().into_iter().find(|c| c.name() == name)
 	}
+
+	/// The client's address. Requests from a trusted reverse proxy are
+	/// attributed to the address it forwarded.
+	fn client_ip(&self) -> Option<IpAddr> {
+		client_ip(self, &TRUSTED_PROXIES)
+	}
+}
+
+fn client_ip(req: &Request<Body>, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+	// IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses
+	let remote = req.extensions().get::<RemoteAddr>()?.0.to_canonical();
+	if !trusted_proxies.contains(&remote) {
+		return Some(remote);
+	}
+
+	// The proxy appends the address it saw, so only the last one is trustworthy
+	let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+	let forwarded = header("x-forwarded-for").and_then(|list| list.split(',').next_back()).or_else(|| header("x-real-ip"));
+	Some(forwarded.and_then(|addr| addr.trim().parse().ok()).unwrap_or(remote))
 }
 
 impl ResponseExt for Response<Body> {
@@ -305,7 +350,8 @@ impl Server {
 	}
 
 	pub fn listen(self, addr: &str) -> Boxed<Result<(), hyper::Error>> {
-		let make_svc = make_service_fn(move |_conn| {
+		let make_svc = make_service_fn(move |conn: &AddrStream| {
+			let remote = RemoteAddr(conn.remote_addr().ip());
 			// For correct borrowing, these values need to be borrowed
 			let router = self.router.clone();
 			let default_headers = self.default_headers.clone();
@@ -315,7 +361,8 @@ impl Server {
 			// returns a Response into a `Service`.
 			// let shared_router = router.clone();
 			async move {
-				Ok::<_, String>(service_fn(move |req: Request<Body>| {
+				Ok::<_, String>(service_fn(move |mut req: Request<Body>| {
+					req.extensions_mut().insert(remote);
 					let req_headers = req.headers().clone();
 					let def_headers = default_headers.clone();
 
@@ -362,7 +409,7 @@ impl Server {
 							async move {
 								match func.await {
 									Ok(mut res) => {
-										res.headers_mut().extend(def_headers);
+										apply_default_headers(&mut res, def_headers);
 										if is_head {
 											*res.body_mut() = Body::empty();
 										} else {
@@ -407,6 +454,15 @@ impl Server {
 	}
 }
 
+/// Add the default headers to a route's response, replacing any it set itself
+/// except a page's own [`ContentSecurityPolicy`]
+fn apply_default_headers(res: &mut Response<Body>, default_headers: HeaderMap<header::HeaderValue>) {
+	res.headers_mut().extend(default_headers);
+	if let Some(ContentSecurityPolicy(csp)) = res.extensions_mut().remove::<ContentSecurityPolicy>() {
+		res.headers_mut().insert(header::CONTENT_SECURITY_POLICY, csp);
+	}
+}
+
 /// Create a boilerplate Response for error conditions. This response will be
 /// compressed if requested by client.
 async fn new_boilerplate(
@@ -774,6 +830,60 @@ mod tests {
 		assert_eq!(determine_compressor("gzip;q=NAN".to_string()), None);
 	}
 
+	#[test]
+	fn test_client_ip() {
+		let request = |remote: [u8; 4], forwarded: Option<&str>| {
+			let mut req = Request::builder();
+			if let Some(forwarded) = forwarded {
+				req = req.header("x-forwarded-for", forwarded);
+			}
+			let mut req = req.body(Body::empty()).unwrap();
+			req.extensions_mut().insert(RemoteAddr(IpAddr::from(remote)));
+			req
+		};
+
+		let trusted = [IpAddr::from([127, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])];
+
+		assert_eq!(client_ip(&request([203, 0, 113, 5], Some("198.51.100.1")), &trusted), Some(IpAddr::from([203, 0, 113, 5])));
+		assert_eq!(
+			client_ip(&request([127, 0, 0, 1], Some("192.0.2.9, 198.51.100.1")), &trusted),
+			Some(IpAddr::from([198, 51, 100, 1]))
+		);
+		assert_eq!(client_ip(&request([10, 0, 0, 2], None), &trusted), Some(IpAddr::from([10, 0, 0, 2])));
+		let mut mapped = request([0; 4], Some("198.51.100.1"));
+		mapped.extensions_mut().insert(RemoteAddr("::ffff:127.0.0.1".parse().unwrap()));
+		assert_eq!(client_ip(&mapped, &trusted), Some(IpAddr::from([198, 51, 100, 1])));
+		// Private addresses aren't trusted unless they're listed, like a Docker bridge gateway
+		assert_eq!(client_ip(&request([172, 17, 0, 1], Some("198.51.100.1")), &trusted), Some(IpAddr::from([172, 17, 0, 1])));
+		assert_eq!(request([127, 0, 0, 1], Some("198.51.100.1")).client_ip(), Some(IpAddr::from([127, 0, 0, 1])));
+		assert_eq!(Request::new(Body::empty()).client_ip(), None);
+	}
+
+	#[test]
+	fn test_default_headers() {
+		let defaults = headers! {
+			"Referrer-Policy" => "no-referrer",
+			"Content-Security-Policy" => CONTENT_SECURITY_POLICY
+		};
+
+		// A proxied upstream can't loosen the defaults
+		let mut proxied = Response::builder()
+			.header("Referrer-Policy", "unsafe-url")
+			.header("Content-Security-Policy", "default-src *")
+			.body(Body::empty())
+			.unwrap();
+		apply_default_headers(&mut proxied, defaults.clone());
+		assert_eq!(proxied.headers()["Referrer-Policy"], "no-referrer");
+		assert_eq!(proxied.headers()["Content-Security-Policy"], CONTENT_SECURITY_POLICY);
+
+		// A page can replace the policy through the extension
+		let mut page = Response::new(Body::empty());
+		page.extensions_mut().insert(ContentSecurityPolicy(header::HeaderValue::from_static("default-src 'self'")));
+		apply_default_headers(&mut page, defaults);
+		assert_eq!(page.headers()["Content-Security-Policy"], "default-src 'self'");
+		assert_eq!(page.headers()["Referrer-Policy"], "no-referrer");
+	}
+
 	#[test]
 	fn test_compress_response() {
 		// This macro generates an Accept-Encoding header value given any number of
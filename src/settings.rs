@@ -1,11 +1,14 @@
 #![allow(clippy::cmp_owned)]
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 // CRATES
-use crate::server::ResponseExt;
+use crate::backup::validate;
+use crate::server::{RequestExt, ResponseExt};
 use crate::subreddit::join_until_size_limit;
-use crate::utils::{deflate_decompress, redirect, template, Preferences};
+use crate::sync;
+use crate::utils::{deflate_decompress, enable_sync, redirect, template, valid_group_name, Preferences, SubscriptionGroup};
 use askama::Template;
 use cookie::Cookie;
 use futures_lite::StreamExt;
@@ -19,12 +22,14 @@ use url::form_urlencoded;
 #[template(path = "settings.html")]
 struct SettingsTemplate {
 	prefs: Preferences,
+	/// The sync key this browser is connected to, if sync is enabled
+	sync_key: Option<String>,
 	url: String,
 }
 
 // CONSTANTS
 
-const PREFS: [&str; 19] = [
+pub(crate) const PREFS: [&str; 23] = [
 	"theme",
 	"front_page",
 	"layout",
@@ -44,8 +49,15 @@ const PREFS: [&str; 19] = [
 	"disable_visit_reddit_confirmation",
 	"video_quality",
 	"remove_default_feeds",
+	"keep_tracking_params",
+	"frontends",
+	"disable_syntax_highlighting",
+	"lite",
 ];
 
+// Settings stored as '+'-separated lists, split across numbered cookies
+const LIST_PREFS: [&str; 3] = ["subscriptions", "filters", "subscription_groups"];
+
 // FUNCTIONS
 
 /// Retrieve cookies from request "Cookie" header
@@ -53,12 +65,15 @@ pub async fn get(req: Request<Body>) -> Result<Response<Body>, String> {
 	let url = req.uri().to_string();
 	Ok(template(&SettingsTemplate {
 		prefs: Preferences::new(&req),
+		sync_key: enable_sync().then(|| sync::cookie_key(&req)).flatten().map(|key| key.to_string()),
 		url,
 	}))
 }
 
 /// Set cookies using response "Set-Cookie" header
 pub async fn set(req: Request<Body>) -> Result<Response<Body>, String> {
+	let prefs = Preferences::new(&req);
+
 	// Split the body into parts
 	let (parts, mut body) = req.into_parts();
 
@@ -81,10 +96,11 @@ pub async fn set(req: Request<Body>) -> Result<Response<Body>, String> {
 		.map_err(|e| e.to_string())?;
 
 	let form = url::form_urlencoded::parse(&body_bytes).collect::<HashMap<_, _>>();
+	let skipped = invalid_prefs(&prefs, &form);
 
 	let mut response = redirect("/settings");
 
-	for &name in &PREFS {
+	for &name in PREFS.iter().filter(|name| !skipped.contains(name)) {
 		match form.get(name) {
 			Some(value) => response.insert_cookie(
 				Cookie::build((name.to_owned(), value.clone()))
@@ -101,6 +117,8 @@ pub async fn set(req: Request<Body>) -> Result<Response<Body>, String> {
 }
 
 fn set_cookies_method(req: Request<Body>, remove_cookies: bool) -> Response<Body> {
+	let prefs = Preferences::new(&req);
+
 	// Split the body into parts
 	let (parts, _) = req.into_parts();
 
@@ -115,6 +133,7 @@ fn set_cookies_method(req: Request<Body>, remove_cookies: bool) -> Response<Body
 	let query = parts.uri.query().unwrap_or_default().as_bytes();
 
 	let form = url::form_urlencoded::parse(query).collect::<HashMap<_, _>>();
+	let skipped = invalid_prefs(&prefs, &form);
 
 	let path = match form.get("redirect") {
 		Some(value) => {
@@ -130,7 +149,44 @@ fn set_cookies_method(req: Request<Body>, remove_cookies: bool) -> Response<Body
 
 	let mut response = redirect(&path);
 
-	for name in PREFS {
+	// We can't search through the cookies directly like in subreddit.rs, so instead we have to make a string out of the request's headers to search through
+	let cookies_string = parts
+		.headers
+		.get("cookie")
+		.map(|hv| hv.to_str().unwrap_or("").to_string()) // Return String
+		.unwrap_or_else(String::new); // Return an empty string if None
+
+	set_form_cookies(&mut response, &cookies_string, &form, &skipped, remove_cookies);
+
+	response
+}
+
+/// Preferences in a form that restoring a backup would skip. They're left as
+/// they are so one bad value doesn't stop the rest from being saved.
+fn invalid_prefs(prefs: &Preferences, form: &HashMap<Cow<'_, str>, Cow<'_, str>>) -> Vec<&'static str> {
+	// A restore link brings its own groups for the front page to point at
+	let groups: Vec<SubscriptionGroup> = match form.get("subscription_groups") {
+		Some(list) => list.split('+').filter_map(SubscriptionGroup::parse).collect(),
+		None => prefs.subscription_groups.clone(),
+	};
+
+	PREFS
+		.into_iter()
+		.filter(|&name| {
+			form.get(name).is_some_and(|value| match (name, value.strip_prefix("g/")) {
+				// Themes this instance doesn't have fall back to the default look
+				("theme", _) => false,
+				("front_page", Some(group)) => !groups.iter().any(|known| known.name.eq_ignore_ascii_case(group)),
+				_ => validate(prefs, name, value).is_err(),
+			})
+		})
+		.collect()
+}
+
+/// Set a cookie for every preference present in the form, except the skipped
+/// ones, removing the others if `remove_cookies` is set
+fn set_form_cookies(response: &mut Response<Body>, cookies_string: &str, form: &HashMap<Cow<'_, str>, Cow<'_, str>>, skipped: &[&str], remove_cookies: bool) {
+	for name in PREFS.into_iter().filter(|name| !skipped.contains(name)) {
 		match form.get(name) {
 			Some(value) => response.insert_cookie(
 				Cookie::build((name.to_owned(), value.clone()))
@@ -147,120 +203,122 @@ fn set_cookies_method(req: Request<Body>, remove_cookies: bool) -> Response<Body
 		};
 	}
 
-	// Get subscriptions/filters to restore from query string
-	let subscriptions = form.get("subscriptions");
-	let filters = form.get("filters");
-
-	// We can't search through the cookies directly like in subreddit.rs, so instead we have to make a string out of the request's headers to search through
-	let cookies_string = parts
-		.headers
-		.get("cookie")
-		.map(|hv| hv.to_str().unwrap_or("").to_string()) // Return String
-		.unwrap_or_else(String::new); // Return an empty string if None
+	// Restore subscriptions, filters and subscription groups from query string
+	for name in LIST_PREFS {
+		let list: Option<Vec<String>> = form.get(name).map(|value| value.split('+').map(str::to_string).collect());
+		if list.is_some() || remove_cookies {
+			set_list_cookies(response, cookies_string, name, list.as_deref());
+		}
+	}
+}
 
-	// If there are subscriptions to restore set them and delete any old subscriptions cookies, otherwise delete them all
-	if let Some(subscriptions) = subscriptions {
-		let sub_list: Vec<String> = subscriptions.split('+').map(str::to_string).collect();
+/// Replace every preference cookie, including bookmarks, with the given preferences
+pub(crate) fn set_preference_cookies(response: &mut Response<Body>, cookies_string: &str, prefs: &Preferences) -> Result<(), String> {
+	let encoded = prefs.to_urlencoded()?;
+	let form = form_urlencoded::parse(encoded.as_bytes()).collect::<HashMap<_, _>>();
+	set_form_cookies(response, cookies_string, &form, &[], true);
+	set_list_cookies(response, cookies_string, "bookmarks", (!prefs.bookmarks.is_empty()).then_some(&prefs.bookmarks[..]));
+	Ok(())
+}
 
-		// Start at 0 to keep track of what number we need to start deleting old subscription cookies from
-		let mut subscriptions_number_to_delete_from = 0;
+/// Store a list setting in as many numbered cookies as needed (`name`, `name1`,
+/// `name2`, ...) and delete any old ones. If there is no list, delete them all.
+pub(crate) fn set_list_cookies(response: &mut Response<Body>, cookies_string: &str, name: &str, list: Option<&[String]>) {
+	// Start at 0 to keep track of what number we need to start deleting old cookies from
+	let mut number_to_delete_from = 0;
 
-		// Starting at 0 so we handle the subscription cookie without a number first
-		for (subscriptions_number, list) in join_until_size_limit(&sub_list).into_iter().enumerate() {
-			let subscriptions_cookie = if subscriptions_number == 0 {
-				"subscriptions".to_string()
-			} else {
-				format!("subscriptions{subscriptions_number}")
-			};
+	if let Some(list) = list {
+		// Starting at 0 so we handle the cookie without a number first
+		for (number, chunk) in join_until_size_limit(list).into_iter().enumerate() {
+			let cookie = if number == 0 { name.to_string() } else { format!("{name}{number}") };
 
 			response.insert_cookie(
-				Cookie::build((subscriptions_cookie, list))
+				Cookie::build((cookie, chunk))
 					.path("/")
 					.http_only(true)
 					.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
 					.into(),
 			);
 
-			subscriptions_number_to_delete_from += 1;
-		}
-
-		// While subscriptionsNUMBER= is in the string of cookies add a response removing that cookie
-		while cookies_string.contains(&format!("subscriptions{subscriptions_number_to_delete_from}=")) {
-			// Remove that subscriptions cookie
-			response.remove_cookie(format!("subscriptions{subscriptions_number_to_delete_from}"));
-
-			// Increment subscriptions cookie number
-			subscriptions_number_to_delete_from += 1;
+			number_to_delete_from += 1;
 		}
 	} else {
-		// Remove unnumbered subscriptions cookie
-		response.remove_cookie("subscriptions".to_string());
-
-		// Starts at one to deal with the first numbered subscription cookie and onwards
-		let mut subscriptions_number_to_delete_from = 1;
+		// Remove unnumbered cookie
+		response.remove_cookie(name.to_string());
 
-		// While subscriptionsNUMBER= is in the string of cookies add a response removing that cookie
-		while cookies_string.contains(&format!("subscriptions{subscriptions_number_to_delete_from}=")) {
-			// Remove that subscriptions cookie
-			response.remove_cookie(format!("subscriptions{subscriptions_number_to_delete_from}"));
-
-			// Increment subscriptions cookie number
-			subscriptions_number_to_delete_from += 1;
-		}
+		// Start with the first numbered cookie
+		number_to_delete_from = 1;
 	}
 
-	// If there are filters to restore set them and delete any old filters cookies, otherwise delete them all
-	if let Some(filters) = filters {
-		let filters_list: Vec<String> = filters.split('+').map(str::to_string).collect();
+	// While nameNUMBER= is in the string of cookies add a response removing that cookie
+	while cookies_string.contains(&format!("{name}{number_to_delete_from}=")) {
+		response.remove_cookie(format!("{name}{number_to_delete_from}"));
+		number_to_delete_from += 1;
+	}
+}
 
-		// Start at 0 to keep track of what number we need to start deleting old subscription cookies from
-		let mut filters_number_to_delete_from = 0;
+/// Create or replace a subscription group
+pub async fn group(req: Request<Body>) -> Result<Response<Body>, String> {
+	let cookies_string = req.headers().get("cookie").and_then(|hv| hv.to_str().ok()).unwrap_or_default().to_string();
+	let mut groups = Preferences::new(&req).subscription_groups;
 
-		// Starting at 0 so we handle the subscription cookie without a number first
-		for (filters_number, list) in join_until_size_limit(&filters_list).into_iter().enumerate() {
-			let filters_cookie = if filters_number == 0 {
-				"filters".to_string()
-			} else {
-				format!("filters{filters_number}")
-			};
+	let body = hyper::body::to_bytes(req.into_body())
+		.await
+		.map_err(|e| format!("Failed to get bytes from request body: {e}"))?;
+	let form: Vec<(String, String)> = form_urlencoded::parse(&body).into_owned().collect();
 
-			response.insert_cookie(
-				Cookie::build((filters_cookie, list))
-					.path("/")
-					.http_only(true)
-					.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
-					.into(),
-			);
+	let name = form.iter().find(|(key, _)| key == "name").map(|(_, value)| value.trim().to_string()).unwrap_or_default();
+	if !valid_group_name(&name) {
+		return Err("Group names may only contain letters, numbers, '-' and '_'".to_string());
+	}
 
-			filters_number_to_delete_from += 1;
+	// Subreddits come from checkboxes and a free-form field separated by spaces, commas or '+'
+	let mut subs: Vec<String> = Vec::new();
+	for (_, value) in form.iter().filter(|(key, _)| key == "subs") {
+		for sub in value.split(|c: char| c == '+' || c == ',' || c.is_whitespace()) {
+			let sub = sub.trim_start_matches("r/").trim_start_matches("/r/");
+			if !sub.is_empty() && sub.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && !subs.iter().any(|s| s.eq_ignore_ascii_case(sub)) {
+				subs.push(sub.to_string());
+			}
 		}
+	}
 
-		// While filtersNUMBER= is in the string of cookies add a response removing that cookie
-		while cookies_string.contains(&format!("filters{filters_number_to_delete_from}=")) {
-			// Remove that filters cookie
-			response.remove_cookie(format!("filters{filters_number_to_delete_from}"));
+	groups.retain(|group| !group.name.eq_ignore_ascii_case(&name));
+	if !subs.is_empty() {
+		subs.sort_by_key(|a| a.to_lowercase());
+		groups.push(SubscriptionGroup { name, subs });
+		groups.sort_by_key(|group| group.name.to_lowercase());
+	}
 
-			// Increment filters cookie number
-			filters_number_to_delete_from += 1;
-		}
-	} else {
-		// Remove unnumbered filters cookie
-		response.remove_cookie("filters".to_string());
+	let path = form
+		.iter()
+		.find(|(key, _)| key == "redirect")
+		.map_or_else(|| "/settings".to_string(), |(_, value)| format!("/{}", value.trim_start_matches('/')));
 
-		// Starts at one to deal with the first numbered subscription cookie and onwards
-		let mut filters_number_to_delete_from = 1;
+	let mut response = redirect(&path);
+	let list: Vec<String> = groups.iter().map(ToString::to_string).collect();
+	set_list_cookies(&mut response, &cookies_string, "subscription_groups", (!list.is_empty()).then_some(&list[..]));
 
-		// While filtersNUMBER= is in the string of cookies add a response removing that cookie
-		while cookies_string.contains(&format!("filters{filters_number_to_delete_from}=")) {
-			// Remove that sfilters cookie
-			response.remove_cookie(format!("filters{filters_number_to_delete_from}"));
+	Ok(response)
+}
 
-			// Increment filters cookie number
-			filters_number_to_delete_from += 1;
-		}
+/// Delete a subscription group
+pub async fn delete_group(req: Request<Body>) -> Result<Response<Body>, String> {
+	let name = req.param("name").unwrap_or_default();
+	let cookies_string = req.headers().get("cookie").and_then(|hv| hv.to_str().ok()).unwrap_or_default().to_string();
+	let mut prefs = Preferences::new(&req);
+	prefs.subscription_groups.retain(|group| !group.name.eq_ignore_ascii_case(&name));
+
+	let mut response = redirect("/settings");
+	let list: Vec<String> = prefs.subscription_groups.iter().map(ToString::to_string).collect();
+	set_list_cookies(&mut response, &cookies_string, "subscription_groups", (!list.is_empty()).then_some(&list[..]));
+
+	// Don't leave the front page pointing at a group that no longer exists
+	if prefs.front_page.strip_prefix("g/").is_some_and(|group| group.eq_ignore_ascii_case(&name)) {
+		response.remove_cookie("front_page".to_string());
 	}
 
-	response
+	Ok(response)
 }
 
 /// Set cookies using response "Set-Cookie" header
@@ -292,10 +350,9 @@ pub async fn encoded_restore(req: Request<Body>) -> Result<Response<Body>, Strin
 		.await
 		.map_err(|e| format!("Failed to decompress bytes: {e}"))??;
 
-	let mut prefs: Preferences = timeout(std::time::Duration::from_secs(1), async { bincode::deserialize(&out) })
+	let mut prefs: Preferences = timeout(std::time::Duration::from_secs(1), async { Preferences::from_bincode(&out) })
 		.await
-		.map_err(|e| format!("Failed to deserialize preferences: {e}"))?
-		.map_err(|e| format!("Failed to deserialize bytes into Preferences struct: {e}"))?;
+		.map_err(|e| format!("Failed to deserialize preferences: {e}"))??;
 
 	prefs.available_themes = vec![];
 
@@ -303,3 +360,29 @@ pub async fn encoded_restore(req: Request<Body>) -> Result<Response<Body>, Strin
 
 	Ok(redirect(&url))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_invalid_prefs() {
+		let prefs = Preferences {
+			available_themes: vec!["system".to_string(), "dark".to_string()],
+			subscription_groups: vec![SubscriptionGroup::parse("news:worldnews").unwrap()],
+			..Default::default()
+		};
+		let form = |query: &'static str| form_urlencoded::parse(query.as_bytes()).collect::<HashMap<_, _>>();
+
+		assert!(invalid_prefs(
+			&prefs,
+			&form("theme=dark&layout=compact&wide=on&frontends=youtube:yewtu.be&front_page=g/news&redirect=r/rust")
+		)
+		.is_empty());
+		assert!(invalid_prefs(&prefs, &form("layout=")).is_empty());
+		// Restore links from other instances keep their theme and groups
+		assert!(invalid_prefs(&prefs, &form("theme=laserwave&front_page=g/tech&subscription_groups=tech:rust.programming")).is_empty());
+		assert_eq!(invalid_prefs(&prefs, &form("front_page=g/news&subscription_groups=tech:rust")), ["front_page"]);
+		assert_eq!(invalid_prefs(&prefs, &form("layout=huge&wide=on&frontends=myspace:example.com")), ["layout", "frontends"]);
+	}
+}
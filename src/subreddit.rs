@@ -1,26 +1,32 @@
 #![allow(clippy::cmp_owned)]
 
+use crate::config;
+use crate::feed::{self, Entry, Feed};
+use crate::markdown;
+use crate::text;
 use crate::utils::{
-	Post, Preferences, Subreddit, catch_random, error, filter_posts, format_num, format_url, get_filters, info, nsfw_landing, param, redirect, rewrite_urls, setting, template, to_absolute_url, val
+	catch_random, error, filter_posts, format_num, format_url, get_filters, info, nsfw_landing, param, redirect, rewrite_emotes, rewrite_urls, setting, template, time, val,
+	Post, Preferences, Subreddit, SubscriptionGroup,
 };
 use crate::{client::json, server::RequestExt, server::ResponseExt};
-use crate::{config, utils};
 use askama::Template;
 use cookie::Cookie;
-use htmlescape::decode_html;
 use hyper::{Body, Request, Response};
 
-use chrono::DateTime;
 use regex::Regex;
-use rss::{ChannelBuilder, Item, Enclosure};
+use serde_json::Value;
+use similar::{ChangeTag, TextDiff};
 use std::sync::LazyLock;
 use time::{Duration, OffsetDateTime};
+use url::form_urlencoded;
 
 // STRUCTS
 #[derive(Template)]
 #[template(path = "subreddit.html")]
 struct SubredditTemplate {
 	sub: Subreddit,
+	/// Where the listing comes from, if it isn't a subreddit.
+	source: FeedSource,
 	posts: Vec<Post>,
 	sort: (String, String),
 	ends: (String, String),
@@ -37,16 +43,131 @@ struct SubredditTemplate {
 	no_posts: bool,
 }
 
+/// A combined feed that isn't a single subreddit
+enum FeedSource {
+	Subreddit,
+	/// A user's multireddit on Reddit
+	Multi(Multireddit),
+	/// One of the user's local subscription groups
+	Group(SubscriptionGroup),
+}
+
+impl FeedSource {
+	/// Path that sort links should be relative to, when it differs from `/r/:sub`
+	fn root(&self) -> Option<String> {
+		match self {
+			Self::Subreddit => None,
+			Self::Multi(multi) => Some(multi.path()),
+			Self::Group(group) => Some(format!("/g/{}", group.name)),
+		}
+	}
+}
+
+/// A user's multireddit, as returned by `/api/multi/user/:name/m/:multi`
+struct Multireddit {
+	name: String,
+	owner: String,
+	title: String,
+	description: String,
+	icon: String,
+	subreddits: Vec<String>,
+	nsfw: bool,
+}
+
+impl Multireddit {
+	fn parse(data: &Value) -> Self {
+		let mut subreddits: Vec<String> = data["subreddits"]
+			.as_array()
+			.map(|list| list.iter().filter_map(|sub| sub["name"].as_str().map(ToString::to_string)).collect())
+			.unwrap_or_default();
+		subreddits.sort_by_key(|sub| sub.to_lowercase());
+
+		Self {
+			name: data["name"].as_str().unwrap_or_default().to_string(),
+			owner: data["owner"].as_str().unwrap_or_default().to_string(),
+			title: data["display_name"].as_str().unwrap_or_default().to_string(),
+			description: data["description_md"].as_str().unwrap_or_default().to_string(),
+			icon: format_url(data["icon_url"].as_str().unwrap_or_default()),
+			subreddits,
+			nsfw: data["over_18"].as_bool().unwrap_or_default(),
+		}
+	}
+
+	/// Path of the multireddit on both Reddit and Redlib
+	fn path(&self) -> String {
+		format!("/user/{}/m/{}", self.owner, self.name)
+	}
+}
+
 #[derive(Template)]
 #[template(path = "wiki.html")]
 struct WikiTemplate {
 	sub: String,
 	wiki: String,
 	page: String,
+	/// The revision being viewed, if an older revision was requested with `?v=`.
+	revision: Option<WikiRevision>,
+	/// Line diff between the viewed revision and the one before it.
+	diff: Vec<DiffLine>,
+	prefs: Preferences,
+	url: String,
+}
+
+#[derive(Template)]
+#[template(path = "wiki_pages.html")]
+struct WikiPagesTemplate {
+	sub: String,
+	pages: Vec<String>,
 	prefs: Preferences,
 	url: String,
 }
 
+#[derive(Template)]
+#[template(path = "wiki_revisions.html")]
+struct WikiRevisionsTemplate {
+	sub: String,
+	page: String,
+	revisions: Vec<WikiRevision>,
+	ends: (String, String),
+	prefs: Preferences,
+	url: String,
+}
+
+/// A single entry in the revision history of a wiki page
+struct WikiRevision {
+	id: String,
+	page: String,
+	author: String,
+	reason: String,
+	rel_time: String,
+	created: String,
+	hidden: bool,
+}
+
+impl WikiRevision {
+	fn parse(revision: &Value) -> Self {
+		let (rel_time, created) = time(revision["timestamp"].as_f64().unwrap_or_default());
+
+		Self {
+			id: revision["id"].as_str().unwrap_or_default().to_string(),
+			page: revision["page"].as_str().unwrap_or_default().to_string(),
+			author: revision["author"]["data"]["name"].as_str().unwrap_or("[deleted]").to_string(),
+			reason: revision["reason"].as_str().unwrap_or_default().to_string(),
+			rel_time,
+			created,
+			hidden: revision["revision_hidden"].as_bool().unwrap_or_default(),
+		}
+	}
+}
+
+/// A line of a wiki revision diff. `kind` is one of "added", "removed",
+/// "unchanged" or "skip" (a marker for omitted unchanged lines).
+#[derive(Debug, PartialEq, Eq)]
+struct DiffLine {
+	kind: &'static str,
+	text: String,
+}
+
 #[derive(Template)]
 #[template(path = "wall.html")]
 struct WallTemplate {
@@ -70,6 +191,13 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 	let post_sort = req.cookie("post_sort").map_or_else(|| "hot".to_string(), |c| c.value().to_string());
 	let sort = req.param("sort").unwrap_or_else(|| req.param("id").unwrap_or(post_sort));
 
+	// A subscription group chosen as the front page
+	if req.param("sub").is_none() {
+		if let Some(group) = front_page.strip_prefix("g/").and_then(|name| Preferences::new(&req).group(name).cloned()) {
+			return group_feed(req, group).await;
+		}
+	}
+
 	let sub_name = req.param("sub").unwrap_or(if front_page == "default" || front_page.is_empty() {
 		if subscribed.is_empty() {
 			"popular".to_string()
@@ -141,14 +269,84 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 
 	let path = format!("/r/{}/{sort}.json?{}{params}", sub_name.replace('+', "%2B"), req.uri().query().unwrap_or_default());
+	listing(req, sub_name, sub, sort, path, quarantined, FeedSource::Subreddit).await
+}
+
+pub async fn group(req: Request<Body>) -> Result<Response<Body>, String> {
+	let name = req.param("group").unwrap_or_default();
+	let Some(group) = Preferences::new(&req).group(&name).cloned() else {
+		return error(req, &format!("There is no subscription group named \"{name}\"")).await;
+	};
+	group_feed(req, group).await
+}
+
+/// Render the combined feed of a subscription group, like the subscription feed
+async fn group_feed(req: Request<Body>, group: SubscriptionGroup) -> Result<Response<Body>, String> {
+	let post_sort = req.cookie("post_sort").map_or_else(|| "hot".to_string(), |c| c.value().to_string());
+	let sort = req.param("sort").unwrap_or_else(|| req.param("id").unwrap_or(post_sort));
+
+	let sub_name = group.joined();
+	let sub = Subreddit {
+		name: sub_name.clone(),
+		title: group.name.clone(),
+		..Subreddit::default()
+	};
+
+	let path = format!("/r/{}/{sort}.json?{}&raw_json=1", sub_name.replace('+', "%2B"), req.uri().query().unwrap_or_default());
+	listing(req, sub_name, sub, sort, path, true, FeedSource::Group(group)).await
+}
+
+pub async fn multireddit(req: Request<Body>) -> Result<Response<Body>, String> {
+	let name = req.param("name").unwrap_or_default();
+	let multi_name = req.param("multi").unwrap_or_default();
+	let post_sort = req.cookie("post_sort").map_or_else(|| "hot".to_string(), |c| c.value().to_string());
+	let sort = req.param("sort").unwrap_or(post_sort);
+
+	// Request multireddit metadata
+	let multi = match json(format!("/api/multi/user/{name}/m/{multi_name}.json?raw_json=1"), false).await {
+		Ok(response) => Multireddit::parse(&response["data"]),
+		Err(msg) => return error(req, &msg).await,
+	};
+
+	if multi.subreddits.is_empty() {
+		return error(req, "This multireddit has no subreddits").await;
+	}
+
+	let sub_name = multi.subreddits.join("+");
+	let sub = Subreddit {
+		name: sub_name.clone(),
+		title: multi.title.clone(),
+		description: multi.description.clone(),
+		nsfw: multi.nsfw,
+		..Subreddit::default()
+	};
+
+	let req_url = req.uri().to_string();
+	// Return landing page if this is an NSFW multireddit but the user
+	// has disabled the display of NSFW content or if the instance is SFW-only.
+	if sub.nsfw && crate::utils::should_be_nsfw_gated(&req, &req_url) {
+		return Ok(nsfw_landing(req, req_url).await.unwrap_or_default());
+	}
+
+	let path = format!("{}/{sort}.json?{}&raw_json=1", multi.path(), req.uri().query().unwrap_or_default());
+	listing(req, sub_name, sub, sort, path, false, FeedSource::Multi(multi)).await
+}
+
+/// Fetch and render a listing of posts for a subreddit, a set of subreddits
+/// joined with '+' or a user's multireddit.
+async fn listing(req: Request<Body>, sub_name: String, sub: Subreddit, sort: String, path: String, quarantined: bool, source: FeedSource) -> Result<Response<Body>, String> {
 	let url = String::from(req.uri().path_and_query().map_or("", |val| val.as_str()));
 	let redirect_url = url[1..].replace('?', "%3F").replace('&', "%26").replace('+', "%2B");
 	let filters = get_filters(&req);
 
 	// If all requested subs are filtered, we don't need to fetch posts.
 	if sub_name.split('+').all(|s| filters.contains(s)) {
+		if text::wanted(&req) {
+			return Ok(text::listing(&req, &sub, &[], "", true));
+		}
 		Ok(template(&SubredditTemplate {
 			sub,
+			source,
 			posts: Vec::new(),
 			sort: (sort, param(&path, "t").unwrap_or_default()),
 			ends: (param(&path, "after").unwrap_or_default(), String::new()),
@@ -167,11 +365,15 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
 				if sort == "new" {
-					posts.sort_by(|a, b| b.created_ts.cmp(&a.created_ts));
-					posts.sort_by(|a, b| b.flags.stickied.cmp(&a.flags.stickied));
+					posts.sort_by_key(|p| std::cmp::Reverse(p.created_ts));
+					posts.sort_by_key(|p| std::cmp::Reverse(p.flags.stickied));
+				}
+				if text::wanted(&req) {
+					return Ok(text::listing(&req, &sub, &posts, &after, false));
 				}
 				Ok(template(&SubredditTemplate {
 					sub,
+					source,
 					posts,
 					sort: (sort, param(&path, "t").unwrap_or_default()),
 					ends: (param(&path, "after").unwrap_or_default(), after),
@@ -195,6 +397,9 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 }
 
 pub fn quarantine(req: &Request<Body>, sub: String, restriction: &str) -> Response<Body> {
+	if text::wanted(req) {
+		return text::message(403, &format!("r/{sub} is {restriction}. Opt in from the web interface to view it."));
+	}
 	let wall = WallTemplate {
 		title: format!("r/{sub} is {restriction}"),
 		msg: "Please click the button below to continue to this subreddit.".to_string(),
@@ -465,14 +670,112 @@ pub async fn wiki(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 
 	let page = req.param("page").unwrap_or_else(|| "index".to_string());
-	let path: String = format!("/r/{sub}/wiki/{page}.json?raw_json=1");
+	let query = format!("?{}", req.uri().query().unwrap_or_default());
+	let version = param(&query, "v").filter(|v| !v.is_empty());
+	let path = wiki_page_path(&sub, &page, version.as_deref());
 	let url = req.uri().to_string();
 
 	match json(path, quarantined).await {
-		Ok(response) => Ok(template(&WikiTemplate {
+		Ok(response) => {
+			// When viewing an older revision, compare it against the revision
+			// before it (or an explicitly requested `v2`).
+			let (revision, diff) = match &version {
+				Some(v) => {
+					let (revision, previous) = wiki_revision_pair(&sub, &page, v, quarantined).await;
+					let old = match param(&query, "v2").or(previous) {
+						Some(v2) => json(wiki_page_path(&sub, &page, Some(&v2)), quarantined)
+							.await
+							.map(|old| old["data"]["content_md"].as_str().unwrap_or_default().to_string())
+							.unwrap_or_default(),
+						None => String::new(),
+					};
+					let new = response["data"]["content_md"].as_str().unwrap_or_default();
+
+					(revision, wiki_diff(&old, new))
+				}
+				None => (None, Vec::new()),
+			};
+
+			Ok(template(&WikiTemplate {
+				wiki: rewrite_wiki_links(
+					&response["data"]["content_md"]
+						.as_str()
+						.map_or_else(|| "<h3>Wiki not found</h3>".to_string(), |content| rewrite_urls(&markdown::render(content, &Value::Null))),
+					&sub,
+				),
+				sub,
+				page,
+				revision,
+				diff,
+				prefs: Preferences::new(&req),
+				url,
+			}))
+		}
+		Err(msg) => {
+			if msg == "quarantined" || msg == "gated" {
+				Ok(quarantine(&req, sub, &msg))
+			} else {
+				error(req, &msg).await
+			}
+		}
+	}
+}
+
+pub async fn wiki_pages(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_else(|| "reddit.com".to_string());
+	let quarantined = can_access_quarantine(&req, &sub);
+	// Handle random subreddits
+	if let Ok(random) = catch_random(&sub, "/wiki/pages").await {
+		return Ok(random);
+	}
+
+	let path: String = format!("/r/{sub}/wiki/pages.json?raw_json=1");
+	let url = req.uri().to_string();
+
+	match json(path, quarantined).await {
+		Ok(response) => {
+			let mut pages: Vec<String> = response["data"]
+				.as_array()
+				.map(|list| list.iter().filter_map(|page| page.as_str().map(ToString::to_string)).collect())
+				.unwrap_or_default();
+			pages.sort_by_key(|page| page.to_lowercase());
+
+			Ok(template(&WikiPagesTemplate {
+				sub,
+				pages,
+				prefs: Preferences::new(&req),
+				url,
+			}))
+		}
+		Err(msg) => {
+			if msg == "quarantined" || msg == "gated" {
+				Ok(quarantine(&req, sub, &msg))
+			} else {
+				error(req, &msg).await
+			}
+		}
+	}
+}
+
+pub async fn wiki_revisions(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_else(|| "reddit.com".to_string());
+	let quarantined = can_access_quarantine(&req, &sub);
+	// Handle random subreddits
+	if let Ok(random) = catch_random(&sub, "/wiki/revisions").await {
+		return Ok(random);
+	}
+
+	let page = req.param("page").unwrap_or_else(|| "index".to_string());
+	let query = format!("?{}", req.uri().query().unwrap_or_default());
+	let cursor: Vec<(&str, String)> = ["after", "before"].into_iter().filter_map(|name| param(&query, name).map(|value| (name, value))).collect();
+	let url = req.uri().to_string();
+
+	match wiki_revision_list(&sub, &page, &cursor, quarantined).await {
+		Ok((revisions, after)) => Ok(template(&WikiRevisionsTemplate {
+			ends: (wiki_revisions_before(&cursor, &revisions), after),
 			sub,
-			wiki: rewrite_urls(response["data"]["content_html"].as_str().unwrap_or("<h3>Wiki not found</h3>")),
 			page,
+			revisions,
 			prefs: Preferences::new(&req),
 			url,
 		})),
@@ -486,6 +789,112 @@ pub async fn wiki(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+/// Fetch the revision history of a wiki page along with the "after" value
+async fn wiki_revision_list(sub: &str, page: &str, params: &[(&str, String)], quarantined: bool) -> Result<(Vec<WikiRevision>, String), String> {
+	let response = json(wiki_revisions_path(sub, page, params), quarantined).await?;
+
+	let revisions = response["data"]["children"]
+		.as_array()
+		.map(|list| list.iter().map(WikiRevision::parse).collect())
+		.unwrap_or_default();
+
+	Ok((revisions, response["data"]["after"].as_str().unwrap_or_default().to_string()))
+}
+
+/// The cursor for the page of history before this one: the fullname of its
+/// first revision. The first page has nothing before it.
+fn wiki_revisions_before(cursor: &[(&str, String)], revisions: &[WikiRevision]) -> String {
+	let paged = cursor.iter().any(|(_, value)| !value.is_empty());
+	revisions.first().filter(|_| paged).map(|rev| format!("WikiRevision_{}", rev.id)).unwrap_or_default()
+}
+
+/// Path of a wiki page, or of revision `v` of it
+fn wiki_page_path(sub: &str, page: &str, v: Option<&str>) -> String {
+	let mut query = form_urlencoded::Serializer::new(String::new());
+	if let Some(v) = v {
+		query.append_pair("v", v);
+	}
+	format!("/r/{sub}/wiki/{page}.json?{}", query.append_pair("raw_json", "1").finish())
+}
+
+/// Pages of revision history searched for the revision before the one being viewed
+const MAX_WIKI_REVISION_PAGES: usize = 10;
+
+/// Path of a wiki page's revision history, with `params` query-encoded
+fn wiki_revisions_path(sub: &str, page: &str, params: &[(&str, String)]) -> String {
+	let query = form_urlencoded::Serializer::new(String::new()).extend_pairs(params).append_pair("raw_json", "1").finish();
+	format!("/r/{sub}/wiki/revisions/{page}.json?{query}")
+}
+
+/// Find revision `v` of a wiki page along with the ID of the revision before
+/// it, paging through the history until `v` turns up
+async fn wiki_revision_pair(sub: &str, page: &str, v: &str, quarantined: bool) -> (Option<WikiRevision>, Option<String>) {
+	let mut params = vec![("limit", "100".to_string())];
+	let mut found = None;
+
+	for _ in 0..MAX_WIKI_REVISION_PAGES {
+		let Ok((revisions, after)) = wiki_revision_list(sub, page, &params, quarantined).await else {
+			break;
+		};
+		let mut revisions = revisions.into_iter();
+		if found.is_none() {
+			found = revisions.by_ref().find(|r| r.id == v);
+		}
+		if found.is_some() {
+			if let Some(previous) = revisions.next() {
+				return (found, Some(previous.id));
+			}
+		}
+		if after.is_empty() {
+			break;
+		}
+		params = vec![("limit", "100".to_string()), ("after", after)];
+	}
+
+	(found, None)
+}
+
+/// Build a line diff between two revisions of a wiki page's markdown, keeping
+/// three lines of context around each change.
+fn wiki_diff(old: &str, new: &str) -> Vec<DiffLine> {
+	let diff = TextDiff::from_lines(old, new);
+	let mut lines = Vec::new();
+
+	for (index, group) in diff.grouped_ops(3).iter().enumerate() {
+		if index > 0 {
+			lines.push(DiffLine {
+				kind: "skip",
+				text: String::new(),
+			});
+		}
+		for op in group {
+			for change in diff.iter_changes(op) {
+				lines.push(DiffLine {
+					kind: match change.tag() {
+						ChangeTag::Insert => "added",
+						ChangeTag::Delete => "removed",
+						ChangeTag::Equal => "unchanged",
+					},
+					text: change.value().trim_end_matches('\n').to_string(),
+				});
+			}
+		}
+	}
+
+	lines
+}
+
+// Relative links in wiki pages point at other pages of the same wiki
+static WIKI_RELATIVE_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"href="([^"/#?:][^":]*)""#).unwrap());
+// Old-style links to a subreddit wiki
+static WIKI_ABOUT_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"href="/r/([^"/]+)/about/wiki/"#).unwrap());
+
+/// Rewrite wiki-internal links so that they stay inside Redlib
+fn rewrite_wiki_links(html: &str, sub: &str) -> String {
+	let html = WIKI_ABOUT_LINK.replace_all(html, r#"href="/r/$1/wiki/"#);
+	WIKI_RELATIVE_LINK.replace_all(&html, format!(r#"href="/r/{sub}/wiki/$1""#)).to_string()
+}
+
 pub async fn sidebar(req: Request<Body>) -> Result<Response<Body>, String> {
 	let sub = req.param("sub").unwrap_or_else(|| "reddit.com".to_string());
 	let quarantined = can_access_quarantine(&req, &sub);
@@ -503,7 +912,10 @@ pub async fn sidebar(req: Request<Body>) -> Result<Response<Body>, String> {
 	match json(path, quarantined).await {
 		// If success, receive JSON in response
 		Ok(response) => Ok(template(&WikiTemplate {
-			wiki: rewrite_urls(&val(&response, "description_html")),
+			wiki: rewrite_emotes(
+				&response["data"]["media_metadata"],
+				markdown::render(&val(&response, "description"), &response["data"]["media_metadata"]),
+			),
 			// wiki: format!(
 			// 	"{}<hr><h1>Moderators</h1><br><ul>{}</ul>",
 			// 	rewrite_urls(&val(&response, "description_html"),
@@ -511,6 +923,8 @@ pub async fn sidebar(req: Request<Body>) -> Result<Response<Body>, String> {
 			// ),
 			sub,
 			page: "Sidebar".to_string(),
+			revision: None,
+			diff: Vec::new(),
 			prefs: Preferences::new(&req),
 			url,
 		})),
@@ -559,7 +973,7 @@ pub async fn sidebar(req: Request<Body>) -> Result<Response<Body>, String> {
 // }
 
 // SUBREDDIT
-async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
+pub(crate) async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
 	// Build the Reddit JSON API url
 	let path: String = format!("/r/{sub}/about.json?raw_json=1");
 
@@ -578,7 +992,7 @@ async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
 		name: val(&res, "display_name"),
 		title: val(&res, "title"),
 		description: val(&res, "public_description"),
-		info: rewrite_urls(&val(&res, "description_html")),
+		info: rewrite_emotes(&res["data"]["media_metadata"], markdown::render(&val(&res, "description"), &res["data"]["media_metadata"])),
 		// moderators: moderators_list(sub, quarantined).await.unwrap_or_default(),
 		icon: format_url(&icon),
 		members: format_num(members),
@@ -593,126 +1007,39 @@ pub async fn rss(req: Request<Body>) -> Result<Response<Body>, String> {
 		return Ok(error(req, "RSS is disabled on this instance.").await.unwrap_or_default());
 	}
 
-	use hyper::header::CONTENT_TYPE;
-
 	// Get subreddit
 	let sub = req.param("sub").unwrap_or_default();
+	let query = req.uri().query().unwrap_or_default();
+	let quarantined = can_access_quarantine(&req, &sub);
 	let post_sort = req.cookie("post_sort").map_or_else(|| "hot".to_string(), |c| c.value().to_string());
-	let sort = req.param("sort").unwrap_or_else(|| req.param("id").unwrap_or(post_sort));
+	let sort = req
+		.param("sort")
+		.or_else(|| param(&format!("?{query}"), "sort"))
+		.unwrap_or_else(|| req.param("id").unwrap_or(post_sort));
 
 	// Get path
-	let path = format!("/r/{sub}/{sort}.json?{}", req.uri().query().unwrap_or_default());
+	let path = format!("/r/{}/{sort}.json?{query}&raw_json=1", sub.replace('+', "%2B"));
 
-	// Get subreddit link
-	let subreddit_link: String = format!("{}/r/{sub}", config::get_setting("REDLIB_FULL_URL").unwrap_or_default());
-
-	// Get subreddit data
-	let subreddit = subreddit(&sub, false).await?;
+	// Combined feeds like `a+b+c`, `popular` and `all` have no metadata of their own
+	let (title, description) = if sub.contains('+') || sub == "popular" || sub == "all" {
+		(format!("r/{sub}"), String::new())
+	} else {
+		let subreddit = subreddit(&sub, quarantined).await?;
+		(subreddit.title, subreddit.description)
+	};
 
 	// Get posts
-	let (posts, _) = Post::fetch(&path, false).await?;
-
-	// Build the RSS feed
-	let channel = ChannelBuilder::default()
-		.title(&subreddit.title)
-		.description(&subreddit.description)
-		.link(&subreddit_link)
-		.items(
-			posts
-				.into_iter()
-				.map(|post| {
-					let mut item = Item {
-						title: Some(post.title.to_string()),
-						link: Some(format_url(&utils::get_post_url(&post))),
-						author: Some(post.author.name.to_string()),
-						content: Some(rewrite_urls(&decode_html(&post.body).unwrap())),
-						pub_date: Some(DateTime::from_timestamp(post.created_ts as i64, 0).unwrap_or_default().to_rfc2822()),
-						description: Some(format!("<a href='{}'>Comments</a>", to_absolute_url(&post.permalink))),
-						..Default::default()
-					};
-
-					apply_enclosure(&mut item, &post);
-					item
-				})
-				.collect::<Vec<_>>(),
-		)
-		.build();
-
-	// Serialize the feed to RSS
-	let body = channel.to_string().into_bytes();
-
-	// Create the HTTP response
-	let mut res = Response::new(Body::from(body));
-	res.headers_mut().insert(CONTENT_TYPE, hyper::header::HeaderValue::from_static("application/rss+xml"));
-
-	Ok(res)
-}
-
-// Set enclosure image for RSS feed item
-fn apply_enclosure(item: &mut Item, post: &Post) {
-	item.set_enclosure(get_rss_image(&post));
-
-	// Embed the number of gallery images in description and content since
-	// only the first image in the gallery is used for the enclosure
-	if post.post_type == "gallery" && post.gallery.len() > 1 {
-		item.set_description(
-			format!("<a href='{}'>Gallery with {} images</a>",
-				to_absolute_url(&post.permalink),
-				post.gallery.len()
-			)
-		);
-
-		if let Some(content) = item.content() {
-			let new_content = format!(
-				"{}<br/>{}",
-				item.description().unwrap_or(""),
-				content,
-			);
-			item.set_content(new_content);
-		}
-	}
-
-}
-
-fn get_rss_image(post: &Post) -> Option<Enclosure> {
-	let image_url = match post.post_type.as_str() {
-		"image" => Some(post.media.url.clone()),
-		"gallery" => post.gallery.get(0).and_then(|media| decode_html(&media.url).ok()),
-		"gif" | "video" => decode_html(&post.media.poster).ok(),
-		_ => None,
+	let (mut posts, _) = Post::fetch(&path, quarantined).await?;
+	feed::retain_visible(&req, &mut posts);
+
+	let feed = Feed {
+		title,
+		description,
+		link: format!("/r/{sub}"),
+		entries: posts.iter().map(Entry::from_post).collect(),
 	};
 
-	image_url.map(|url| {
-		let mut enclosure = Enclosure::default();
-		enclosure.set_mime_type(get_mime_type(&url));
-		enclosure.set_url(to_absolute_url(&url));
-		enclosure.set_length("0");
-		enclosure
-	})
-}
-
-/// Determines the MIME type based on file extension in a URL.
-/// Handles both absolute and relative URLs with query parameters.
-fn get_mime_type(url: &str) -> &'static str {
-    // Extract the path component, removing query parameters
-    let path = url.split('?').next().unwrap_or(url);
-    
-    // Get the file extension (everything after the last dot)
-    let extension = path
-        .rsplit('.')
-        .next()
-        .unwrap_or("")
-        .to_lowercase();
-    
-    // Match common image extensions
-    match extension.as_str() {
-        "jpg" | "jpeg" => "image/jpeg",
-        "png" => "image/png",
-        "gif" => "image/gif",
-        "webp" => "image/webp",
-        "svg" => "image/svg+xml",
-        _ => "application/octet-stream",
-    }
+	Ok(feed.respond(&req))
 }
 
 #[cfg(test)]
@@ -725,6 +1052,85 @@ mod tests {
 		assert!(subreddit.is_ok());
 	}
 
+	#[test]
+	fn test_parse_multireddit() {
+		let multi = Multireddit::parse(&serde_json::json!({
+			"name": "tech",
+			"owner": "someone",
+			"display_name": "Tech news",
+			"description_md": "Technology subreddits",
+			"icon_url": "",
+			"over_18": false,
+			"subreddits": [{ "name": "rust" }, { "name": "Linux" }, { "name": "programming" }]
+		}));
+		assert_eq!(multi.title, "Tech news");
+		assert_eq!(multi.subreddits, vec!["Linux", "programming", "rust"]);
+		assert_eq!(multi.path(), "/user/someone/m/tech");
+		assert!(!multi.nsfw);
+	}
+
+	#[test]
+	fn test_wiki_page_path() {
+		assert_eq!(wiki_page_path("rust", "faq", None), "/r/rust/wiki/faq.json?raw_json=1");
+		assert_eq!(wiki_page_path("rust", "faq", Some("abc&x=1")), "/r/rust/wiki/faq.json?v=abc%26x%3D1&raw_json=1");
+	}
+
+	#[test]
+	fn test_wiki_revisions_path() {
+		assert_eq!(wiki_revisions_path("rust", "faq", &[]), "/r/rust/wiki/revisions/faq.json?raw_json=1");
+		assert_eq!(
+			wiki_revisions_path("rust", "faq", &[("after", "WikiRevision_1&limit=1".to_string())]),
+			"/r/rust/wiki/revisions/faq.json?after=WikiRevision_1%26limit%3D1&raw_json=1"
+		);
+	}
+
+	#[test]
+	fn test_wiki_revisions_before() {
+		let revisions = [WikiRevision::parse(&serde_json::json!({"id": "b"})), WikiRevision::parse(&serde_json::json!({"id": "c"}))];
+
+		assert_eq!(wiki_revisions_before(&[], &revisions), "");
+		assert_eq!(wiki_revisions_before(&[("after", "WikiRevision_a".to_string())], &revisions), "WikiRevision_b");
+		assert_eq!(wiki_revisions_before(&[("before", "WikiRevision_d".to_string())], &revisions), "WikiRevision_b");
+		assert_eq!(wiki_revisions_before(&[("before", "WikiRevision_b".to_string())], &[]), "");
+	}
+
+	#[test]
+	fn test_wiki_diff() {
+		let old = "# FAQ\none\ntwo\nthree\nfour\nfive\nsix\nseven\neight\n";
+		let new = "# FAQ\none\ntwo\nthree\nfour\nfive\nsix\nseven\nnine\n";
+		let diff = wiki_diff(old, new);
+		let changed: Vec<(&str, &str)> = diff.iter().filter(|l| l.kind != "unchanged").map(|l| (l.kind, l.text.as_str())).collect();
+		assert_eq!(changed, vec![("removed", "eight"), ("added", "nine")]);
+		// Only three lines of context are kept around a change
+		assert_eq!(
+			diff.first(),
+			Some(&DiffLine {
+				kind: "unchanged",
+				text: "five".into()
+			})
+		);
+		assert!(wiki_diff(old, old).is_empty());
+	}
+
+	#[test]
+	fn test_rewrite_wiki_links() {
+		assert_eq!(rewrite_wiki_links(r#"<a href="faq">FAQ</a>"#, "rust"), r#"<a href="/r/rust/wiki/faq">FAQ</a>"#);
+		assert_eq!(
+			rewrite_wiki_links(r#"<a href="/r/rust/about/wiki/faq">FAQ</a>"#, "rust"),
+			r#"<a href="/r/rust/wiki/faq">FAQ</a>"#
+		);
+		assert_eq!(rewrite_wiki_links(r#"<a href="/r/rust/wiki/faq">FAQ</a>"#, "rust"), r#"<a href="/r/rust/wiki/faq">FAQ</a>"#);
+		assert_eq!(
+			rewrite_wiki_links(r#"<a href="https://example.com">x</a>"#, "rust"),
+			r#"<a href="https://example.com">x</a>"#
+		);
+		assert_eq!(rewrite_wiki_links(r##"<a href="#rules">x</a>"##, "rust"), r##"<a href="#rules">x</a>"##);
+		assert_eq!(
+			rewrite_wiki_links(r#"<a href="mailto:mods@example.com">x</a>"#, "rust"),
+			r#"<a href="mailto:mods@example.com">x</a>"#
+		);
+	}
+
 	#[tokio::test(flavor = "multi_thread")]
 	async fn test_gated_and_quarantined() {
 		let quarantined = subreddit("edgy", true).await;
@@ -0,0 +1,450 @@
+//! Optional server-side storage of preferences, keyed by a sync key that only
+//! the user holds. The key is never stored: entries are looked up by a hash of
+//! it and encrypted with a second key derived from it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+use crate::config::get_setting;
+use crate::server::{RequestExt, ResponseExt};
+use crate::settings::set_preference_cookies;
+use crate::utils::{deflate_decompress, enable_sync, redirect, Preferences};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use cookie::Cookie;
+use hyper::{Body, Request, Response};
+use log::error;
+use redb::{Database, ReadableTable, TableDefinition, TableError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
+use url::form_urlencoded;
+
+/// Encrypted preferences, keyed by the storage id of a sync key
+const ENTRIES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("preferences");
+
+/// Bookkeeping, currently only the total size of all entries
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+/// Largest single entry accepted, well above what fits in cookies
+const MAX_ENTRY_SIZE: usize = 256 * 1024;
+
+const DEFAULT_STORAGE_PATH: &str = "redlib-sync.redb";
+const DEFAULT_MAX_STORAGE_MB: u64 = 100;
+
+/// Length of the nonce prepended to every encrypted entry
+const NONCE_SIZE: usize = 12;
+
+/// The cookie remembering the sync key in a browser
+const SYNC_COOKIE: &str = "sync_key";
+
+/// Sync keys one address may create an hour, so nobody can fill the storage cap on their own
+const MAX_NEW_KEYS_PER_HOUR: usize = 5;
+
+/// When each address recently created a sync key
+static NEW_KEYS: LazyLock<Mutex<HashMap<Option<IpAddr>, Vec<Instant>>>> = LazyLock::new(Mutex::default);
+
+static STORE: LazyLock<Option<Store>> = LazyLock::new(|| {
+	if !enable_sync() {
+		return None;
+	}
+
+	let path = get_setting("REDLIB_SYNC_STORAGE_PATH").unwrap_or_else(|| DEFAULT_STORAGE_PATH.to_string());
+	let max_mb = get_setting("REDLIB_SYNC_MAX_STORAGE_MB")
+		.and_then(|mb| mb.parse::<u64>().ok())
+		.unwrap_or(DEFAULT_MAX_STORAGE_MB);
+
+	match Store::open(&path, max_mb * 1024 * 1024) {
+		Ok(store) => Some(store),
+		Err(e) => {
+			error!("Failed to open sync storage at {path}: {e}");
+			None
+		}
+	}
+});
+
+/// A random key identifying and encrypting a user's synced preferences
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SyncKey([u8; 16]);
+
+impl SyncKey {
+	pub fn generate() -> Self {
+		let mut bytes = [0; 16];
+		OsRng.fill_bytes(&mut bytes);
+		Self(bytes)
+	}
+
+	/// Parse a key as displayed, ignoring case, dashes and whitespace
+	pub fn parse(key: &str) -> Option<Self> {
+		let hex: Vec<char> = key.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect();
+		if hex.len() != 32 {
+			return None;
+		}
+
+		let mut bytes = [0; 16];
+		for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+			*byte = u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok()?;
+		}
+		Some(Self(bytes))
+	}
+
+	/// The id the entry is stored under. This doesn't reveal the key itself.
+	fn storage_id(&self) -> [u8; 32] {
+		Sha256::new().chain_update(b"redlib-sync-id").chain_update(self.0).finalize().into()
+	}
+
+	fn cipher(&self) -> ChaCha20Poly1305 {
+		let key: [u8; 32] = Sha256::new().chain_update(b"redlib-sync-key").chain_update(self.0).finalize().into();
+		ChaCha20Poly1305::new(Key::from_slice(&key))
+	}
+
+	fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+		let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let ciphertext = self.cipher().encrypt(&nonce, plaintext).map_err(|e| format!("Failed to encrypt preferences: {e}"))?;
+		Ok([nonce.as_slice(), &ciphertext].concat())
+	}
+
+	fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+		if data.len() < NONCE_SIZE {
+			return Err("Stored preferences are corrupted".to_string());
+		}
+		let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+		self
+			.cipher()
+			.decrypt(Nonce::from_slice(nonce), ciphertext)
+			.map_err(|_| "Failed to decrypt stored preferences".to_string())
+	}
+}
+
+/// Displayed as eight dash-separated groups of four hex digits
+impl fmt::Display for SyncKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, pair) in self.0.chunks(2).enumerate() {
+			if i > 0 {
+				write!(f, "-")?;
+			}
+			write!(f, "{:02x}{:02x}", pair[0], pair[1])?;
+		}
+		Ok(())
+	}
+}
+
+/// What gets encrypted and stored for a sync key
+#[derive(Serialize, Deserialize)]
+struct SyncedState {
+	/// Compressed bincode, as used by the settings export
+	prefs: Vec<u8>,
+	/// Bookmarks aren't part of the bincode export, so they're kept separately
+	bookmarks: Vec<String>,
+}
+
+impl SyncedState {
+	fn seal(prefs: &Preferences, key: &SyncKey) -> Result<Vec<u8>, String> {
+		let state = Self {
+			prefs: prefs.to_compressed_bincode()?,
+			bookmarks: prefs.bookmarks.clone(),
+		};
+		key.encrypt(&bincode::serialize(&state).map_err(|e| e.to_string())?)
+	}
+
+	fn open(data: &[u8], key: &SyncKey) -> Result<Preferences, String> {
+		let state: Self = bincode::deserialize(&key.decrypt(data)?).map_err(|e| format!("Failed to read stored preferences: {e}"))?;
+		let mut prefs = Preferences::from_bincode(&deflate_decompress(state.prefs)?)?;
+		prefs.bookmarks = state.bookmarks;
+		Ok(prefs)
+	}
+}
+
+/// An embedded key-value database with a cap on the total size of its entries
+pub struct Store {
+	db: Database,
+	max_size: u64,
+}
+
+impl Store {
+	pub fn open(path: &str, max_size: u64) -> Result<Self, String> {
+		let db = Database::create(path).map_err(|e| e.to_string())?;
+		Ok(Self { db, max_size })
+	}
+
+	pub fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>, String> {
+		let txn = self.db.begin_read().map_err(|e| e.to_string())?;
+		let table = match txn.open_table(ENTRIES) {
+			Ok(table) => table,
+			Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+			Err(e) => return Err(e.to_string()),
+		};
+		let value = table.get(id).map_err(|e| e.to_string())?;
+		Ok(value.map(|value| value.value().to_vec()))
+	}
+
+	/// Insert or replace an entry. Fails if it would grow storage past the cap.
+	pub fn put(&self, id: &[u8], data: &[u8]) -> Result<(), String> {
+		if data.len() > MAX_ENTRY_SIZE {
+			return Err("Your settings are too large to sync".to_string());
+		}
+
+		let txn = self.db.begin_write().map_err(|e| e.to_string())?;
+		{
+			let mut entries = txn.open_table(ENTRIES).map_err(|e| e.to_string())?;
+			let mut meta = txn.open_table(META).map_err(|e| e.to_string())?;
+
+			let old = entries.get(id).map_err(|e| e.to_string())?.map_or(0, |value| value.value().len() as u64);
+			let used = meta.get("size").map_err(|e| e.to_string())?.map_or(0, |value| value.value());
+			let new = used - old.min(used) + data.len() as u64;
+			if new > self.max_size && new > used {
+				return Err("Sync storage on this instance is full".to_string());
+			}
+
+			entries.insert(id, data).map_err(|e| e.to_string())?;
+			meta.insert("size", new).map_err(|e| e.to_string())?;
+		}
+		txn.commit().map_err(|e| e.to_string())
+	}
+
+	pub fn remove(&self, id: &[u8]) -> Result<(), String> {
+		let txn = self.db.begin_write().map_err(|e| e.to_string())?;
+		{
+			let mut entries = txn.open_table(ENTRIES).map_err(|e| e.to_string())?;
+			let mut meta = txn.open_table(META).map_err(|e| e.to_string())?;
+
+			let old = entries.remove(id).map_err(|e| e.to_string())?.map_or(0, |value| value.value().len() as u64);
+			let used = meta.get("size").map_err(|e| e.to_string())?.map_or(0, |value| value.value());
+			meta.insert("size", used - old.min(used)).map_err(|e| e.to_string())?;
+		}
+		txn.commit().map_err(|e| e.to_string())
+	}
+}
+
+fn store() -> Result<&'static Store, String> {
+	STORE.as_ref().ok_or_else(|| "Preference sync is not enabled on this instance".to_string())
+}
+
+/// Run a blocking storage operation off the async executor
+async fn blocking<T: Send + 'static>(f: impl FnOnce(&'static Store) -> Result<T, String> + Send + 'static) -> Result<T, String> {
+	let store = store()?;
+	tokio::task::spawn_blocking(move || f(store)).await.map_err(|e| e.to_string())?
+}
+
+/// The sync key remembered by this browser, if any
+pub fn cookie_key(req: &Request<Body>) -> Option<SyncKey> {
+	req.cookie(SYNC_COOKIE).and_then(|cookie| SyncKey::parse(cookie.value()))
+}
+
+/// Refuse cross-site form posts, which could connect a browser to someone
+/// else's sync key and have it upload its settings there
+fn same_origin(req: &Request<Body>) -> Result<(), String> {
+	let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+	let allowed = match (header("sec-fetch-site"), header("origin")) {
+		(Some(site), _) => site == "same-origin" || site == "none",
+		(None, Some(origin)) => origin.split_once("://").map(|(_, host)| host) == header("host"),
+		// Only browsers add cookies to a cross-site request, and they send one of the above
+		(None, None) => true,
+	};
+	if allowed {
+		Ok(())
+	} else {
+		Err("Sync settings can only be changed from this instance's settings page".to_string())
+	}
+}
+
+/// Count a new sync key against the address creating it. Fails if it has
+/// created too many in the last hour.
+fn allow_new_key(log: &mut HashMap<Option<IpAddr>, Vec<Instant>>, addr: Option<IpAddr>, now: Instant) -> Result<(), String> {
+	log.retain(|_, created| {
+		created.retain(|time| now.duration_since(*time).as_secs() < 3600);
+		!created.is_empty()
+	});
+	let created = log.entry(addr).or_default();
+	if created.len() >= MAX_NEW_KEYS_PER_HOUR {
+		return Err("Too many sync keys have been created from your address. Try again later".to_string());
+	}
+	created.push(now);
+	Ok(())
+}
+
+fn cookies_string(req: &Request<Body>) -> String {
+	req.headers().get("cookie").and_then(|hv| hv.to_str().ok()).unwrap_or_default().to_string()
+}
+
+fn remember_key(response: &mut Response<Body>, key: &SyncKey) {
+	response.insert_cookie(
+		Cookie::build((SYNC_COOKIE, key.to_string()))
+			.path("/")
+			.http_only(true)
+			.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+			.into(),
+	);
+}
+
+async fn upload(key: SyncKey, prefs: &Preferences) -> Result<(), String> {
+	let data = SyncedState::seal(prefs, &key)?;
+	blocking(move |store| store.put(&key.storage_id(), &data)).await
+}
+
+/// Generate a new sync key and store the current preferences under it
+pub async fn create(req: Request<Body>) -> Result<Response<Body>, String> {
+	same_origin(&req)?;
+	store()?;
+	allow_new_key(&mut *NEW_KEYS.lock().map_err(|e| e.to_string())?, req.client_ip(), Instant::now())?;
+	let key = SyncKey::generate();
+	upload(key, &Preferences::new(&req)).await?;
+
+	let mut response = redirect("/settings#settings_sync");
+	remember_key(&mut response, &key);
+	Ok(response)
+}
+
+/// Overwrite the stored preferences with the ones in this browser
+pub async fn push(req: Request<Body>) -> Result<Response<Body>, String> {
+	same_origin(&req)?;
+	let key = cookie_key(&req).ok_or_else(|| "This browser isn't connected to a sync key".to_string())?;
+	upload(key, &Preferences::new(&req)).await?;
+	Ok(redirect("/settings#settings_sync"))
+}
+
+/// Replace the preferences in this browser with the stored ones. The key comes
+/// from the form, or from the cookie if this browser is already connected.
+pub async fn pull(req: Request<Body>) -> Result<Response<Body>, String> {
+	same_origin(&req)?;
+	let cookies = cookies_string(&req);
+	let remembered = cookie_key(&req);
+
+	let body = hyper::body::to_bytes(req.into_body())
+		.await
+		.map_err(|e| format!("Failed to get bytes from request body: {e}"))?;
+	let key = match form_urlencoded::parse(&body).find(|(key, value)| key == "key" && !value.trim().is_empty()) {
+		Some((_, value)) => SyncKey::parse(&value).ok_or_else(|| "That doesn't look like a sync key".to_string())?,
+		None => remembered.ok_or_else(|| "Enter a sync key to load settings".to_string())?,
+	};
+
+	let data = blocking(move |store| store.get(&key.storage_id()))
+		.await?
+		.ok_or_else(|| "No settings are stored under that sync key".to_string())?;
+	let prefs = SyncedState::open(&data, &key)?;
+
+	let mut response = redirect("/settings#settings_sync");
+	set_preference_cookies(&mut response, &cookies, &prefs)?;
+	remember_key(&mut response, &key);
+	Ok(response)
+}
+
+/// Forget the sync key in this browser, keeping the stored preferences
+pub async fn disconnect(req: Request<Body>) -> Result<Response<Body>, String> {
+	same_origin(&req)?;
+	let mut response = redirect("/settings#settings_sync");
+	response.remove_cookie(SYNC_COOKIE.to_string());
+	Ok(response)
+}
+
+/// Delete the stored preferences and forget the sync key
+pub async fn delete(req: Request<Body>) -> Result<Response<Body>, String> {
+	same_origin(&req)?;
+	let key = cookie_key(&req).ok_or_else(|| "This browser isn't connected to a sync key".to_string())?;
+	blocking(move |store| store.remove(&key.storage_id())).await?;
+
+	let mut response = redirect("/settings#settings_sync");
+	response.remove_cookie(SYNC_COOKIE.to_string());
+	Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::SubscriptionGroup;
+
+	#[test]
+	fn test_sync_key() {
+		let key = SyncKey::generate();
+		let shown = key.to_string();
+		assert_eq!(shown.len(), 39);
+		assert_eq!(SyncKey::parse(&shown), Some(key));
+		assert_eq!(SyncKey::parse(&shown.replace('-', " ").to_uppercase()), Some(key));
+		assert_eq!(SyncKey::parse("not-a-key"), None);
+		assert_eq!(SyncKey::parse(&"zz".repeat(16)), None);
+		assert_ne!(key.storage_id(), SyncKey::generate().storage_id());
+	}
+
+	#[test]
+	fn test_sync_roundtrip() {
+		let prefs = Preferences {
+			theme: "dracula".to_string(),
+			subscriptions: vec!["rust".to_string(), "linux".to_string()],
+			subscription_groups: vec![SubscriptionGroup {
+				name: "news".to_string(),
+				subs: vec!["worldnews".to_string()],
+			}],
+			bookmarks: vec!["t3_abc".to_string()],
+			..Default::default()
+		};
+
+		let key = SyncKey::generate();
+		let sealed = SyncedState::seal(&prefs, &key).unwrap();
+		assert_eq!(SyncedState::open(&sealed, &key).unwrap(), prefs);
+		assert!(SyncedState::open(&sealed, &SyncKey::generate()).is_err());
+		assert!(SyncedState::open(&sealed[..4], &key).is_err());
+	}
+
+	#[test]
+	fn test_store_cap() {
+		let path = std::env::temp_dir().join(format!("redlib-sync-test-{}.redb", uuid::Uuid::new_v4()));
+		let store = Store::open(path.to_str().unwrap(), 100).unwrap();
+
+		assert_eq!(store.get(b"a").unwrap(), None);
+		store.put(b"a", &[1; 60]).unwrap();
+		assert_eq!(store.get(b"a").unwrap(), Some(vec![1; 60]));
+
+		// Growing past the cap is refused, but replacing an entry with a smaller one isn't
+		assert!(store.put(b"b", &[2; 60]).is_err());
+		store.put(b"a", &[3; 30]).unwrap();
+		store.put(b"b", &[2; 60]).unwrap();
+
+		store.remove(b"a").unwrap();
+		assert_eq!(store.get(b"a").unwrap(), None);
+		store.put(b"c", &[4; 40]).unwrap();
+
+		drop(store);
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn test_same_origin() {
+		let request = |headers: &[(&str, &str)]| {
+			let mut builder = Request::builder().method("POST").uri("/settings/sync/pull").header("host", "redlib.example.com");
+			for (name, value) in headers {
+				builder = builder.header(*name, *value);
+			}
+			builder.body(Body::empty()).unwrap()
+		};
+
+		assert!(same_origin(&request(&[("sec-fetch-site", "same-origin"), ("origin", "https://redlib.example.com")])).is_ok());
+		assert!(same_origin(&request(&[("sec-fetch-site", "cross-site"), ("origin", "https://evil.example")])).is_err());
+		assert!(same_origin(&request(&[("sec-fetch-site", "same-site")])).is_err());
+		assert!(same_origin(&request(&[("origin", "https://redlib.example.com")])).is_ok());
+		assert!(same_origin(&request(&[("origin", "https://evil.example")])).is_err());
+		assert!(same_origin(&request(&[("origin", "null")])).is_err());
+		assert!(same_origin(&request(&[])).is_ok());
+	}
+
+	#[test]
+	fn test_new_key_limit() {
+		let mut log = HashMap::new();
+		let start = Instant::now();
+		let addr = Some(IpAddr::from([192, 0, 2, 1]));
+
+		for _ in 0..MAX_NEW_KEYS_PER_HOUR {
+			allow_new_key(&mut log, addr, start).unwrap();
+		}
+		assert!(allow_new_key(&mut log, addr, start).is_err());
+		allow_new_key(&mut log, Some(IpAddr::from([192, 0, 2, 2])), start).unwrap();
+
+		// The limit resets an hour later, and old entries are forgotten
+		allow_new_key(&mut log, addr, start + std::time::Duration::from_secs(3600)).unwrap();
+		assert_eq!(log.len(), 1);
+	}
+}
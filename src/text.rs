@@ -0,0 +1,420 @@
+//! Plain-text pages for terminals, shell scripts and text browsers. Requests
+//! get them with `?format=txt` or by preferring `text/plain` in their `Accept`
+//! header. Text is wrapped to 80 columns and links become numbered references
+//! listed at the end of the page.
+
+use crate::blocks::{self, Block, Marker};
+use crate::outbound;
+use crate::utils::{param, to_absolute_url, Comment, Post, Preferences, Subreddit};
+
+use hyper::{Body, Request, Response};
+
+const WIDTH: usize = 80;
+
+/// Deeply nested comments still get this many columns
+const MIN_WIDTH: usize = 40;
+
+/// Whether a request asked for plain text, with `?format=txt` or an `Accept`
+/// header that rates `text/plain` above `text/html`
+pub fn wanted(req: &Request<Body>) -> bool {
+	if let Some(format) = param(&req.uri().to_string(), "format") {
+		return format == "txt";
+	}
+	let Some(accept) = req.headers().get("accept").and_then(|accept| accept.to_str().ok()) else {
+		return false;
+	};
+
+	let quality = |media_type: &str| {
+		accept
+			.split(',')
+			.filter_map(|range| {
+				let mut parts = range.split(';');
+				if !parts.next()?.trim().eq_ignore_ascii_case(media_type) {
+					return None;
+				}
+				let q = parts.find_map(|part| part.trim().strip_prefix("q=")).map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+				Some(q)
+			})
+			.next()
+			.unwrap_or(0.0)
+	};
+	let text = quality("text/plain");
+	text > 0.0 && text > quality("text/html")
+}
+
+fn response(status: u16, body: String) -> Response<Body> {
+	Response::builder()
+		.status(status)
+		.header("content-type", "text/plain; charset=utf-8")
+		.body(body.into())
+		.unwrap_or_default()
+}
+
+/// An error or notice in place of a page
+pub fn message(status: u16, msg: &str) -> Response<Body> {
+	response(status, format!("{msg}\n"))
+}
+
+/// A page of posts, from a subreddit, several joined with `+` or a multireddit
+pub fn listing(req: &Request<Body>, sub: &Subreddit, posts: &[Post], after: &str, filtered: bool) -> Response<Body> {
+	let mut page = Page::new(req);
+	page.heading(&format!("r/{}", sub.name));
+	if !sub.title.is_empty() {
+		page.wrap(&sub.title, 0);
+	}
+	if !sub.description.is_empty() {
+		page.wrap(&sub.description, 0);
+	}
+	if !sub.members.0.is_empty() {
+		page.line(&format!("{} members · {} active", sub.members.0, sub.active.0));
+	}
+	page.blank();
+
+	if filtered {
+		page.line("(Content from this subreddit has been filtered)");
+	} else if posts.is_empty() {
+		page.line("No posts were found.");
+	}
+	for post in posts {
+		page.summary(post);
+	}
+
+	if !after.is_empty() {
+		let path = req.uri().path();
+		let query = req
+			.uri()
+			.query()
+			.unwrap_or_default()
+			.split('&')
+			.filter(|pair| !pair.starts_with("after=") && !pair.is_empty());
+		let query = query.chain(std::iter::once(&*format!("after={after}"))).collect::<Vec<_>>().join("&");
+		let next = page.reference(&format!("{path}?{query}"));
+		page.line(&format!("Next page [{next}]"));
+	}
+	response(200, page.finish())
+}
+
+/// A post and its comments
+pub fn post(req: &Request<Body>, post: &Post, comments: &[Comment], sort: &str) -> Response<Body> {
+	let mut page = Page::new(req);
+	page.heading(&post.title);
+
+	let mut details = vec![format!("r/{}", post.community), format!("u/{}", post.author.name), post.rel_time.clone()];
+	details.push(format!("{} points", post.score.0));
+	details.push(format!("{}% upvoted", post.upvote_ratio));
+	if post.flags.nsfw {
+		details.push("NSFW".to_string());
+	}
+	page.wrap(&details.join(" · "), 0);
+	page.media(post);
+	page.blank();
+
+	if !post.body.is_empty() {
+		page.html(&post.body, 0);
+		page.blank();
+	}
+
+	let sort = if sort.is_empty() { String::new() } else { format!(", sorted by {sort}") };
+	page.heading(&format!("{} comments{sort}", post.comments.0));
+	for comment in comments {
+		page.comment(comment, 0);
+	}
+	response(200, page.finish())
+}
+
+/// A page being written, with the links it refers to
+struct Page {
+	out: String,
+	refs: Vec<String>,
+	prefs: Preferences,
+	/// Kept on links to other pages when the request asked for text with a parameter
+	format: bool,
+}
+
+impl Page {
+	fn new(req: &Request<Body>) -> Self {
+		Self {
+			out: String::new(),
+			refs: Vec::new(),
+			prefs: Preferences::new(req),
+			format: param(&req.uri().to_string(), "format").is_some_and(|format| format == "txt"),
+		}
+	}
+
+	fn line(&mut self, line: &str) {
+		self.out.push_str(line.trim_end());
+		self.out.push('\n');
+	}
+
+	fn blank(&mut self) {
+		if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+			self.out.push('\n');
+		}
+	}
+
+	fn heading(&mut self, text: &str) {
+		self.blank();
+		let lines = wrap(text, WIDTH);
+		let underline = lines.iter().map(|line| line.chars().count()).max().unwrap_or_default();
+		for line in lines {
+			self.line(&line);
+		}
+		self.line(&"=".repeat(underline));
+	}
+
+	fn wrap(&mut self, text: &str, indent: usize) {
+		let prefix = " ".repeat(indent);
+		for line in wrap(text, WIDTH.saturating_sub(indent).max(MIN_WIDTH)) {
+			self.line(&format!("{prefix}{line}"));
+		}
+	}
+
+	/// Number a link, reusing the number of one already on the page
+	fn reference(&mut self, url: &str) -> usize {
+		let url = if url.starts_with('/') && !url.starts_with("//") {
+			let url = if self.format && !url.starts_with("/img/") && !url.starts_with("/preview/") && !url.contains("format=txt") {
+				let separator = if url.contains('?') { '&' } else { '?' };
+				format!("{url}{separator}format=txt")
+			} else {
+				url.to_string()
+			};
+			to_absolute_url(&url)
+		} else {
+			outbound::rewrite_url(url, &self.prefs)
+		};
+		match self.refs.iter().position(|existing| *existing == url) {
+			Some(i) => i + 1,
+			None => {
+				self.refs.push(url);
+				self.refs.len()
+			}
+		}
+	}
+
+	/// A post in a listing. Comments in user listings are posts without a title.
+	fn summary(&mut self, post: &Post) {
+		let link = self.reference(&post.permalink);
+		let title = if post.title.is_empty() {
+			format!("Comment in r/{}", post.community)
+		} else {
+			post.title.clone()
+		};
+		self.wrap(&format!("{title} [{link}]"), 0);
+
+		let mut details = vec![format!("{} points", post.score.0), format!("{} comments", post.comments.0)];
+		details.push(format!("r/{}", post.community));
+		details.push(format!("u/{}", post.author.name));
+		details.push(post.rel_time.clone());
+		if post.flags.stickied {
+			details.push("pinned".to_string());
+		}
+		if post.flags.nsfw {
+			details.push("NSFW".to_string());
+		}
+		if post.post_type == "link" {
+			details.push(post.domain.clone());
+		}
+		self.wrap(&details.join(" · "), 2);
+		self.blank();
+	}
+
+	/// References to a post's media, served through the instance's proxy
+	fn media(&mut self, post: &Post) {
+		match post.post_type.as_str() {
+			"image" => {
+				let link = self.reference(&post.media.url);
+				self.line(&format!("Image [{link}]"));
+			}
+			"gif" | "video" => {
+				let link = self.reference(&post.media.url);
+				self.line(&format!("Video [{link}]"));
+			}
+			"link" => {
+				let link = self.reference(&post.media.url);
+				self.wrap(&format!("Link to {} [{link}]", post.domain), 0);
+			}
+			"gallery" => {
+				for (i, image) in post.gallery.iter().enumerate() {
+					let link = self.reference(&image.url);
+					let caption = if image.caption.is_empty() { String::new() } else { format!(": {}", image.caption) };
+					self.wrap(&format!("Image {} of {} [{link}]{caption}", i + 1, post.gallery.len()), 0);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn comment(&mut self, comment: &Comment, depth: usize) {
+		let indent = depth * 2;
+		if comment.kind == "more" {
+			if comment.parent_kind == "t1" {
+				let link = self.reference(&format!("{}{}", comment.post_link, comment.parent_id));
+				self.wrap(&format!("More replies ({}) [{link}]", comment.more_count), indent);
+				self.blank();
+			}
+			return;
+		}
+
+		let op = if comment.author.name == comment.post_author { " (OP)" } else { "" };
+		self.wrap(&format!("u/{}{op} · {} points · {}", comment.author.name, comment.score.0, comment.rel_time), indent);
+		if comment.is_filtered {
+			self.wrap("(Filtered content)", indent);
+		} else {
+			self.html(&comment.body, indent);
+		}
+		self.blank();
+
+		for reply in &comment.replies {
+			self.comment(reply, depth + 1);
+		}
+	}
+
+	/// Write rendered, sanitised HTML as text, with its links as references
+	fn html(&mut self, html: &str, indent: usize) {
+		let mut blocks = blocks::parse(html);
+		while blocks.last() == Some(&Block::Break) {
+			blocks.pop();
+		}
+
+		let prefix = " ".repeat(indent);
+		let width = WIDTH.saturating_sub(indent).max(MIN_WIDTH);
+		for block in blocks {
+			match block {
+				Block::Break => self.line(""),
+				Block::Rule => self.line(&format!("{prefix}{}", "-".repeat(20))),
+				Block::Pre(lines) => {
+					for line in lines {
+						self.line(&format!("{prefix}    {line}"));
+					}
+				}
+				Block::Line(line) => {
+					let mut text = String::new();
+					let mut last = 0;
+					for (range, href) in &line.links {
+						text.push_str(&line.text[last..range.end]);
+						text.push_str(&format!(" [{}]", self.reference(href)));
+						last = range.end;
+					}
+					text.push_str(&line.text[last..]);
+
+					let quote = "> ".repeat(line.quotes);
+					let nesting = "  ".repeat(line.lists.saturating_sub(1));
+					let (first, rest) = if line.lists == 0 {
+						let heading = if line.heading > 0 { format!("{} ", "#".repeat(line.heading)) } else { String::new() };
+						(format!("{quote}{heading}"), quote)
+					} else if let Some(marker) = line.marker {
+						let marker = match marker {
+							Marker::Bullet => "* ".to_string(),
+							Marker::Number(number) => format!("{number}. "),
+						};
+						(format!("{quote}{nesting}{marker}"), format!("{quote}{nesting}{}", " ".repeat(marker.len())))
+					} else {
+						let rest = format!("{quote}{nesting}  ");
+						(rest.clone(), rest)
+					};
+					for (i, wrapped) in wrap(text.trim(), width.saturating_sub(first.chars().count()).max(1)).iter().enumerate() {
+						self.line(&format!("{prefix}{}{wrapped}", if i == 0 { &first } else { &rest }));
+					}
+				}
+			}
+		}
+	}
+
+	fn finish(mut self) -> String {
+		if !self.refs.is_empty() {
+			self.heading("References");
+			for (i, url) in std::mem::take(&mut self.refs).iter().enumerate() {
+				self.line(&format!("[{}] {url}", i + 1));
+			}
+		}
+		self.out
+	}
+}
+
+/// Wrap text at spaces to lines of at most `width` characters. Words longer
+/// than that get a line of their own.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+	let mut lines = Vec::new();
+	let mut line = String::new();
+	for word in text.split_whitespace() {
+		if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+			lines.push(std::mem::take(&mut line));
+		}
+		if !line.is_empty() {
+			line.push(' ');
+		}
+		line.push_str(word);
+	}
+	if !line.is_empty() {
+		lines.push(line);
+	}
+	lines
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn request(uri: &str, accept: &str) -> Request<Body> {
+		Request::builder().uri(uri).header("accept", accept).body(Body::empty()).unwrap()
+	}
+
+	#[test]
+	fn test_wanted() {
+		assert!(wanted(&request("/r/rust?format=txt", "text/html")));
+		assert!(!wanted(&request("/r/rust?format=html", "text/plain")));
+		assert!(wanted(&request("/r/rust", "text/plain")));
+		assert!(wanted(&request("/r/rust", "text/html;q=0.5, text/plain")));
+		assert!(!wanted(&request("/r/rust", "*/*")));
+		assert!(!wanted(&request("/r/rust", "text/html, text/plain, text/sgml, */*;q=0.01")));
+		assert!(!wanted(&request("/r/rust", "text/plain;q=0")));
+	}
+
+	#[test]
+	fn test_wrap() {
+		assert_eq!(wrap("the quick brown fox jumps", 10), ["the quick", "brown fox", "jumps"]);
+		assert_eq!(wrap("a https://example.com/a/long/url b", 10), ["a", "https://example.com/a/long/url", "b"]);
+	}
+
+	#[test]
+	fn test_html() {
+		let mut page = Page::new(&request("/r/rust/comments/abc?format=txt", "*/*"));
+		let html = r#"<div class="md"><h2>Heading</h2><p>Some <strong>bold</strong> text with <a href="/r/rust">a link</a> and <a href="https://example.com/">another</a>.</p>
+<ul>
+<li>one</li>
+<li>two &amp; three, which is long enough to wrap onto a second line at this indent</li>
+</ul>
+<blockquote>
+<p>quoted</p>
+</blockquote>
+<pre><code>fn main() {}
+</code></pre>
+<ol><li>first</li><li>second</li></ol><p><a href="/r/rust">Again</a></p></div>"#;
+		page.html(html, 4);
+		assert_eq!(
+			page.finish(),
+			"    ## Heading
+
+    Some bold text with a link [1] and another [2].
+
+    * one
+    * two & three, which is long enough to wrap onto a second line at this
+      indent
+
+    > quoted
+
+        fn main() {}
+
+    1. first
+    2. second
+
+    Again [1]
+
+References
+==========
+[1] /r/rust?format=txt
+[2] https://example.com/
+"
+		);
+	}
+}
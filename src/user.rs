@@ -1,11 +1,10 @@
 #![allow(clippy::cmp_owned)]
 use crate::client::json;
+use crate::feed::{self, Entry, Feed};
 use crate::server::RequestExt;
 use crate::utils::{error, filter_posts, format_url, get_filters, nsfw_landing, param, setting, template, Post, Preferences, User};
 use crate::{config, utils};
 use askama::Template;
-use chrono::DateTime;
-use htmlescape::decode_html;
 use hyper::{Body, Request, Response};
 use time::{macros::format_description, OffsetDateTime};
 
@@ -105,7 +104,7 @@ pub async fn profile(req: Request<Body>) -> Result<Response<Body>, String> {
 }
 
 // USER
-async fn user(name: &str) -> Result<User, String> {
+pub(crate) async fn user(name: &str) -> Result<User, String> {
 	// Build the Reddit JSON API path
 	let path: String = format!("/user/{name}/about.json?raw_json=1");
 
@@ -136,14 +135,14 @@ pub async fn rss(req: Request<Body>) -> Result<Response<Body>, String> {
 	if config::get_setting("REDLIB_ENABLE_RSS").is_none() {
 		return Ok(error(req, "RSS is disabled on this instance.").await.unwrap_or_default());
 	}
-	use crate::utils::rewrite_urls;
-	use hyper::header::CONTENT_TYPE;
-	use rss::{ChannelBuilder, Item};
 
 	// Get user
 	let user_str = req.param("name").unwrap_or_default();
 
 	let listing = req.param("listing").unwrap_or_else(|| "overview".to_string());
+	if !["overview", "submitted", "comments"].contains(&listing.as_str()) {
+		return Ok(error(req, &format!("Unknown listing \"{listing}\"")).await.unwrap_or_default());
+	}
 
 	// Get path
 	let path = format!("/user/{user_str}/{listing}.json?{}&raw_json=1", req.uri().query().unwrap_or_default(),);
@@ -152,35 +151,17 @@ pub async fn rss(req: Request<Body>) -> Result<Response<Body>, String> {
 	let user_obj = user(&user_str).await.unwrap_or_default();
 
 	// Get posts
-	let (posts, _) = Post::fetch(&path, false).await?;
-
-	// Build the RSS feed
-	let channel = ChannelBuilder::default()
-		.title(user_str)
-		.description(user_obj.description)
-		.items(
-			posts
-				.into_iter()
-				.map(|post| Item {
-					title: Some(post.title.to_string()),
-					link: Some(format_url(&utils::get_post_url(&post))),
-					author: Some(post.author.name),
-					pub_date: Some(DateTime::from_timestamp(post.created_ts as i64, 0).unwrap_or_default().to_rfc2822()),
-					content: Some(rewrite_urls(&decode_html(&post.body).unwrap_or_else(|_| post.body.clone()))),
-					..Default::default()
-				})
-				.collect::<Vec<_>>(),
-		)
-		.build();
-
-	// Serialize the feed to RSS
-	let body = channel.to_string().into_bytes();
-
-	// Create the HTTP response
-	let mut res = Response::new(Body::from(body));
-	res.headers_mut().insert(CONTENT_TYPE, hyper::header::HeaderValue::from_static("application/rss+xml"));
-
-	Ok(res)
+	let (mut posts, _) = Post::fetch(&path, false).await?;
+	feed::retain_visible(&req, &mut posts);
+
+	let feed = Feed {
+		link: format!("/user/{user_str}/{listing}"),
+		title: if listing == "overview" { user_str } else { format!("{user_str} ({listing})") },
+		description: user_obj.description,
+		entries: posts.iter().map(Entry::from_post).collect(),
+	};
+
+	Ok(feed.respond(&req))
 }
 
 #[cfg(test)]
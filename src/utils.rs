@@ -2,6 +2,8 @@
 #![allow(clippy::cmp_owned)]
 
 use crate::config::{self, get_setting};
+use crate::embed::Embed;
+use crate::markdown;
 use crate::{client::json, server::RequestExt};
 use askama::Template;
 use cookie::Cookie;
@@ -9,11 +11,10 @@ use hyper::{Body, Request, Response};
 use libflate::deflate::{Decoder, Encoder};
 use log::error;
 use regex::Regex;
-use revision::revisioned;
+use revision::{revisioned, DeserializeRevisioned, Revisioned};
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
-use serde_json_path::{JsonPath, JsonPathExt};
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::io::{Read, Write};
@@ -62,6 +63,8 @@ pub struct Flair {
 pub struct FlairPart {
 	pub flair_part_type: String,
 	pub value: String,
+	/// Emoji's name, like `:snoo:`
+	pub alt: String,
 }
 
 impl FlairPart {
@@ -82,6 +85,7 @@ impl FlairPart {
 								"emoji" => format_url(value("u")),
 								_ => String::new(),
 							},
+							alt: value("a").to_string(),
 						}
 					})
 					.collect::<Vec<Self>>(),
@@ -92,6 +96,7 @@ impl FlairPart {
 				Some(text) => vec![Self {
 					flair_part_type: "text".to_string(),
 					value: text.to_string(),
+					alt: String::new(),
 				}],
 				None => Vec::new(),
 			},
@@ -172,6 +177,49 @@ pub struct Flags {
 	pub stickied: bool,
 }
 
+/// A smaller copy of an image, which Reddit makes for previews
+#[derive(Clone, Debug, Serialize)]
+pub struct Variant {
+	pub url: String,
+	pub width: i64,
+}
+
+impl Variant {
+	/// Parse the `resolutions` of a preview or the `p` of a gallery item, smallest first
+	fn parse(resolutions: &Value) -> Vec<Self> {
+		let mut variants: Vec<Self> = resolutions
+			.as_array()
+			.into_iter()
+			.flatten()
+			.filter_map(|resolution| {
+				let url = resolution["url"].as_str().or_else(|| resolution["u"].as_str())?;
+				let width = resolution["width"].as_i64().or_else(|| resolution["x"].as_i64())?;
+				Some(Self { url: format_url(url), width })
+			})
+			.filter(|variant| !variant.url.is_empty() && variant.width > 0)
+			.collect();
+		variants.sort_by_key(|variant| variant.width);
+		variants
+	}
+
+	/// The smallest variant at least `width` pixels wide, or the original if none are
+	fn fit<'a>(variants: &'a [Self], width: i64, original: &'a str) -> &'a str {
+		variants.iter().find(|variant| variant.width >= width).map_or(original, |variant| &variant.url)
+	}
+
+	/// A `srcset` of every variant and the original, or nothing if there are no variants
+	fn srcset(variants: &[Self], original: &str, original_width: i64) -> String {
+		if variants.is_empty() {
+			return String::new();
+		}
+		let mut srcset: Vec<String> = variants.iter().map(|variant| format!("{} {}w", variant.url, variant.width)).collect();
+		if original_width > variants.last().map_or(0, |variant| variant.width) {
+			srcset.push(format!("{original} {original_width}w"));
+		}
+		srcset.join(", ")
+	}
+}
+
 #[derive(Debug, Serialize)]
 pub struct Media {
 	pub url: String,
@@ -180,9 +228,21 @@ pub struct Media {
 	pub height: i64,
 	pub poster: String,
 	pub download_name: String,
+	/// Smaller copies of an image post or a video's poster
+	pub variants: Vec<Variant>,
 }
 
 impl Media {
+	/// The image to show in a space `width` pixels wide
+	pub fn image(&self, width: i64) -> &str {
+		Variant::fit(&self.variants, width, &self.url)
+	}
+
+	/// The poster to show for a video `width` pixels wide
+	pub fn poster_image(&self, width: i64) -> &str {
+		Variant::fit(&self.variants, width, &self.poster)
+	}
+
 	pub async fn parse(data: &Value) -> (String, Self, Vec<GalleryMedia>) {
 		let mut gallery = Vec::new();
 
@@ -250,6 +310,14 @@ impl Media {
 			("link", &data["url"], None)
 		};
 
+		// Media on other hosts can be shown inline if the instance resolves it
+		if post_type == "link" {
+			let url = data["url"].as_str().unwrap_or_default();
+			if let Some(resolved) = crate::resolvers::resolve(url, data["permalink"].as_str().unwrap_or_default()).await {
+				return resolved;
+			}
+		}
+
 		let source = &data["preview"]["images"][0]["source"];
 
 		let alt_url = alt_url_val.map_or(String::new(), |val| format_url(val.as_str().unwrap_or_default()));
@@ -274,6 +342,12 @@ impl Media {
 				height: source["height"].as_i64().unwrap_or_default(),
 				poster: format_url(source["url"].as_str().unwrap_or_default()),
 				download_name,
+				// GIFs are shown as MP4s, which have no smaller copies
+				variants: if post_type == "image" || post_type == "video" {
+					Variant::parse(&data["preview"]["images"][0]["resolutions"])
+				} else {
+					Vec::new()
+				},
 			},
 			gallery,
 		)
@@ -287,9 +361,19 @@ pub struct GalleryMedia {
 	pub height: i64,
 	pub caption: String,
 	pub outbound_url: String,
+	pub variants: Vec<Variant>,
 }
 
 impl GalleryMedia {
+	/// The image to show in a space `width` pixels wide
+	pub fn image(&self, width: i64) -> &str {
+		Variant::fit(&self.variants, width, &self.url)
+	}
+
+	pub fn srcset(&self) -> String {
+		Variant::srcset(&self.variants, &self.url, self.width)
+	}
+
 	fn parse(items: &Value, metadata: &Value) -> Vec<Self> {
 		items
 			.as_array()
@@ -301,10 +385,10 @@ impl GalleryMedia {
 				let image = &metadata[media_id]["s"];
 				let image_type = &metadata[media_id]["m"];
 
-				let url = if image_type == "image/gif" {
-					image["gif"].as_str().unwrap_or_default()
+				let (url, variants) = if image_type == "image/gif" {
+					(image["gif"].as_str().unwrap_or_default(), Vec::new())
 				} else {
-					image["u"].as_str().unwrap_or_default()
+					(image["u"].as_str().unwrap_or_default(), Variant::parse(&metadata[media_id]["p"]))
 				};
 
 				// Construct gallery items
@@ -314,6 +398,7 @@ impl GalleryMedia {
 					height: image["y"].as_i64().unwrap_or_default(),
 					caption: item["caption"].as_str().unwrap_or_default().to_string(),
 					outbound_url: item["outbound_url"].as_str().unwrap_or_default().to_string(),
+					variants,
 				}
 			})
 			.collect::<Vec<Self>>()
@@ -349,6 +434,7 @@ pub struct Post {
 	pub nsfw: bool,
 	pub out_url: Option<String>,
 	pub ws_url: String,
+	pub embed: Option<Embed>,
 }
 
 impl Post {
@@ -367,103 +453,111 @@ impl Post {
 			return Err("No posts found".to_string());
 		};
 
-		let mut posts: Vec<Self> = Vec::new();
-
-		// For each post from posts list
-		for post in post_list {
-			let data = &post["data"];
-
-			let (rel_time, created) = time(data["created_utc"].as_f64().unwrap_or_default());
-			let created_ts = data["created_utc"].as_f64().unwrap_or_default().round() as u64;
-			let score = data["score"].as_i64().unwrap_or_default();
-			let ratio: f64 = data["upvote_ratio"].as_f64().unwrap_or(1.0) * 100.0;
-			let title = val(post, "title");
-
-			// Determine the type of media along with the media URL
-			let (post_type, media, gallery) = Media::parse(data).await;
-			let awards = Awards::parse(&data["all_awardings"]);
+		// Parse the posts concurrently, since media on other hosts may have to be looked up
+		let tasks: Vec<_> = post_list.iter().cloned().map(|post| tokio::spawn(async move { Self::parse(&post).await })).collect();
+		let mut posts: Vec<Self> = Vec::with_capacity(tasks.len());
+		for task in tasks {
+			posts.push(task.await.map_err(|e| e.to_string())?);
+		}
+		Ok((posts, res["data"]["after"].as_str().unwrap_or_default().to_string()))
+	}
 
-			// selftext_html is set for text posts when browsing.
-			let mut body = rewrite_urls(&val(post, "selftext_html"));
-			if body.is_empty() {
-				body = rewrite_urls(&val(post, "body_html"));
-			}
+	/// Parse a post or comment from a listing
+	async fn parse(post: &Value) -> Self {
+		let data = &post["data"];
+
+		let (rel_time, created) = time(data["created_utc"].as_f64().unwrap_or_default());
+		let created_ts = data["created_utc"].as_f64().unwrap_or_default().round() as u64;
+		let score = data["score"].as_i64().unwrap_or_default();
+		let ratio: f64 = data["upvote_ratio"].as_f64().unwrap_or(1.0) * 100.0;
+		let title = val(post, "title");
+
+		// Determine the type of media along with the media URL
+		let (post_type, media, gallery) = Media::parse(data).await;
+		let embed = if post_type == "link" { Embed::parse(data) } else { None };
+		let awards = Awards::parse(&data["all_awardings"]);
+
+		// selftext is set for text posts when browsing.
+		let mut body = rewrite_emotes(&data["media_metadata"], markdown::render(&val(post, "selftext"), &data["media_metadata"]));
+		if body.is_empty() {
+			body = rewrite_emotes(&data["media_metadata"], markdown::render(&val(post, "body"), &data["media_metadata"]));
+		}
 
-			posts.push(Self {
-				id: val(post, "id"),
-				title,
-				community: val(post, "subreddit"),
-				body,
-				author: Author {
-					name: val(post, "author"),
-					flair: Flair {
-						flair_parts: FlairPart::parse(
-							data["author_flair_type"].as_str().unwrap_or_default(),
-							data["author_flair_richtext"].as_array(),
-							data["author_flair_text"].as_str(),
-						),
-						text: val(post, "link_flair_text"),
-						background_color: val(post, "author_flair_background_color"),
-						foreground_color: val(post, "author_flair_text_color"),
-					},
-					distinguished: val(post, "distinguished"),
-				},
-				score: if data["hide_score"].as_bool().unwrap_or_default() {
-					("\u{2022}".to_string(), "Hidden".to_string())
-				} else {
-					format_num(score)
-				},
-				upvote_ratio: ratio as i64,
-				post_type,
-				thumbnail: Media {
-					url: format_url(val(post, "thumbnail").as_str()),
-					alt_url: String::new(),
-					width: data["thumbnail_width"].as_i64().unwrap_or_default(),
-					height: data["thumbnail_height"].as_i64().unwrap_or_default(),
-					poster: String::new(),
-					download_name: String::new(),
-				},
-				media,
-				domain: val(post, "domain"),
+		Self {
+			id: val(post, "id"),
+			title,
+			community: val(post, "subreddit"),
+			body,
+			author: Author {
+				name: val(post, "author"),
 				flair: Flair {
 					flair_parts: FlairPart::parse(
-						data["link_flair_type"].as_str().unwrap_or_default(),
-						data["link_flair_richtext"].as_array(),
-						data["link_flair_text"].as_str(),
+						data["author_flair_type"].as_str().unwrap_or_default(),
+						data["author_flair_richtext"].as_array(),
+						data["author_flair_text"].as_str(),
 					),
 					text: val(post, "link_flair_text"),
-					background_color: val(post, "link_flair_background_color"),
-					foreground_color: if val(post, "link_flair_text_color") == "dark" {
-						"black".to_string()
-					} else {
-						"white".to_string()
-					},
+					background_color: val(post, "author_flair_background_color"),
+					foreground_color: val(post, "author_flair_text_color"),
 				},
-				flags: Flags {
-					spoiler: data["spoiler"].as_bool().unwrap_or_default(),
-					nsfw: data["over_18"].as_bool().unwrap_or_default(),
-					stickied: data["stickied"].as_bool().unwrap_or_default() || data["pinned"].as_bool().unwrap_or_default(),
+				distinguished: val(post, "distinguished"),
+			},
+			score: if data["hide_score"].as_bool().unwrap_or_default() {
+				("\u{2022}".to_string(), "Hidden".to_string())
+			} else {
+				format_num(score)
+			},
+			upvote_ratio: ratio as i64,
+			post_type,
+			thumbnail: Media {
+				url: format_url(val(post, "thumbnail").as_str()),
+				alt_url: String::new(),
+				width: data["thumbnail_width"].as_i64().unwrap_or_default(),
+				height: data["thumbnail_height"].as_i64().unwrap_or_default(),
+				poster: String::new(),
+				download_name: String::new(),
+				variants: Vec::new(),
+			},
+			media,
+			domain: val(post, "domain"),
+			flair: Flair {
+				flair_parts: FlairPart::parse(
+					data["link_flair_type"].as_str().unwrap_or_default(),
+					data["link_flair_richtext"].as_array(),
+					data["link_flair_text"].as_str(),
+				),
+				text: val(post, "link_flair_text"),
+				background_color: val(post, "link_flair_background_color"),
+				foreground_color: if val(post, "link_flair_text_color") == "dark" {
+					"black".to_string()
+				} else {
+					"white".to_string()
 				},
-				permalink: val(post, "permalink"),
-				link_title: val(post, "link_title"),
-				poll: Poll::parse(&data["poll_data"]),
-				rel_time,
-				created,
-				created_ts,
-				num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
-				comments: format_num(data["num_comments"].as_i64().unwrap_or_default()),
-				gallery,
-				awards,
-				nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
-				ws_url: val(post, "websocket_url"),
-				out_url: post["data"]["url_overridden_by_dest"].as_str().map(|a| a.to_string()),
-			});
+			},
+			flags: Flags {
+				spoiler: data["spoiler"].as_bool().unwrap_or_default(),
+				nsfw: data["over_18"].as_bool().unwrap_or_default(),
+				stickied: data["stickied"].as_bool().unwrap_or_default() || data["pinned"].as_bool().unwrap_or_default(),
+			},
+			permalink: val(post, "permalink"),
+			link_title: val(post, "link_title"),
+			poll: Poll::parse(&data["poll_data"]),
+			embed,
+			rel_time,
+			created,
+			created_ts,
+			num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
+			comments: format_num(data["num_comments"].as_i64().unwrap_or_default()),
+			gallery,
+			awards,
+			nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
+			ws_url: val(post, "websocket_url"),
+			out_url: post["data"]["url_overridden_by_dest"].as_str().map(|a| a.to_string()),
 		}
-		Ok((posts, res["data"]["after"].as_str().unwrap_or_default().to_string()))
 	}
 }
 
-#[derive(Template)]
+#[derive(Template, Serialize)]
 #[template(path = "comment.html")]
 /// Comment with content, post, score and data/time that it was posted
 pub struct Comment {
@@ -478,6 +572,7 @@ pub struct Comment {
 	pub score: (String, String),
 	pub rel_time: String,
 	pub created: String,
+	pub created_ts: i64,
 	pub edited: (String, String),
 	pub replies: Vec<Comment>,
 	pub highlighted: bool,
@@ -485,6 +580,7 @@ pub struct Comment {
 	pub collapsed: bool,
 	pub is_filtered: bool,
 	pub more_count: i64,
+	#[serde(skip)]
 	pub prefs: Preferences,
 }
 
@@ -579,7 +675,7 @@ pub struct NSFWLandingTemplate {
 	pub url: String,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize)]
 /// User struct containing metadata about user
 pub struct User {
 	pub name: String,
@@ -592,7 +688,7 @@ pub struct User {
 	pub nsfw: bool,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize)]
 /// Subreddit struct containing metadata about community
 pub struct Subreddit {
 	pub name: String,
@@ -617,8 +713,18 @@ pub struct Params {
 	pub before: Option<String>,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[revisioned(revision = 1)]
+/// Marks preferences encoded with their schema revision. Older exports are
+/// plain bincode and start with the length of the theme name instead.
+const VERSIONED_PREFS_MAGIC: &[u8] = b"\xffRP";
+
+/// User preferences, stored in cookies and exported as base2048 blobs.
+///
+/// To add a preference, bump `revision` and add the field with
+/// `#[revision(start = N)]` and `#[serde(default)]`. Blobs from older
+/// revisions are upgraded with the field's default when they're restored.
+/// Then add an export made with the previous revision to `KNOWN_GOOD_CONFIGS`.
+#[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[revisioned(revision = 6)]
 pub struct Preferences {
 	#[revision(start = 1)]
 	#[serde(skip_serializing, skip_deserializing)]
@@ -667,6 +773,146 @@ pub struct Preferences {
 	pub hide_score: String,
 	#[revision(start = 1)]
 	pub remove_default_feeds: String,
+	#[revision(start = 2)]
+	#[serde(default, serialize_with = "serialize_groups", deserialize_with = "deserialize_groups")]
+	pub subscription_groups: Vec<SubscriptionGroup>,
+	/// Fullnames of saved posts (`t3_`) and comments (`t1_`). These are exported
+	/// separately from `/saved`, so they aren't part of the encoded preferences.
+	#[revision(start = 2)]
+	#[serde(skip_serializing, skip_deserializing)]
+	pub bookmarks: Vec<String>,
+	#[revision(start = 3)]
+	#[serde(default)]
+	pub keep_tracking_params: String,
+	/// Alternative front-ends for outbound links, as `site:front-end` pairs joined with `+`
+	#[revision(start = 4)]
+	#[serde(default)]
+	pub frontends: String,
+	/// Code is highlighted when it's parsed, so this hides the colours with a class on the page
+	#[revision(start = 5)]
+	#[serde(default)]
+	pub disable_syntax_highlighting: String,
+	/// Leaves out media, fonts, scripts and decoration for slow or metered connections
+	#[revision(start = 6)]
+	#[serde(default)]
+	pub lite: String,
+}
+
+/// Exports made before schema versioning are plain bincode without a revision,
+/// so their layout is kept here. This is revision 1.
+#[derive(Deserialize)]
+struct PreferencesV1 {
+	#[serde(skip_deserializing)]
+	available_themes: Vec<String>,
+	theme: String,
+	front_page: String,
+	layout: String,
+	wide: String,
+	blur_spoiler: String,
+	show_nsfw: String,
+	blur_nsfw: String,
+	hide_hls_notification: String,
+	video_quality: String,
+	hide_sidebar_and_summary: String,
+	use_hls: String,
+	autoplay_videos: String,
+	fixed_navbar: String,
+	disable_visit_reddit_confirmation: String,
+	comment_sort: String,
+	post_sort: String,
+	#[serde(deserialize_with = "deserialize_vec_with_plus")]
+	subscriptions: Vec<String>,
+	#[serde(deserialize_with = "deserialize_vec_with_plus")]
+	filters: Vec<String>,
+	hide_awards: String,
+	hide_score: String,
+	remove_default_feeds: String,
+}
+
+impl From<PreferencesV1> for Preferences {
+	fn from(old: PreferencesV1) -> Self {
+		Self {
+			available_themes: old.available_themes,
+			theme: old.theme,
+			front_page: old.front_page,
+			layout: old.layout,
+			wide: old.wide,
+			blur_spoiler: old.blur_spoiler,
+			show_nsfw: old.show_nsfw,
+			blur_nsfw: old.blur_nsfw,
+			hide_hls_notification: old.hide_hls_notification,
+			video_quality: old.video_quality,
+			hide_sidebar_and_summary: old.hide_sidebar_and_summary,
+			use_hls: old.use_hls,
+			autoplay_videos: old.autoplay_videos,
+			fixed_navbar: old.fixed_navbar,
+			disable_visit_reddit_confirmation: old.disable_visit_reddit_confirmation,
+			comment_sort: old.comment_sort,
+			post_sort: old.post_sort,
+			subscriptions: old.subscriptions,
+			filters: old.filters,
+			hide_awards: old.hide_awards,
+			hide_score: old.hide_score,
+			remove_default_feeds: old.remove_default_feeds,
+			subscription_groups: Vec::new(),
+			bookmarks: Vec::new(),
+			keep_tracking_params: String::new(),
+			frontends: String::new(),
+			disable_syntax_highlighting: String::new(),
+			lite: String::new(),
+		}
+	}
+}
+
+/// A named group of subscriptions that can be browsed as a single feed.
+///
+/// Groups are stored as `name:sub1.sub2.sub3`, and several groups are joined
+/// with `+` like other list settings.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+#[revisioned(revision = 1)]
+pub struct SubscriptionGroup {
+	pub name: String,
+	pub subs: Vec<String>,
+}
+
+impl SubscriptionGroup {
+	/// Parse a single stored group, skipping it if it's malformed
+	pub fn parse(group: &str) -> Option<Self> {
+		let (name, subs) = group.split_once(':')?;
+		let subs: Vec<String> = subs.split('.').filter(|s| !s.is_empty()).map(String::from).collect();
+		(valid_group_name(name) && !subs.is_empty()).then(|| Self { name: name.to_string(), subs })
+	}
+
+	/// The combined name used to fetch posts for the group, like `sub1+sub2`
+	pub fn joined(&self) -> String {
+		self.subs.join("+")
+	}
+}
+
+impl std::fmt::Display for SubscriptionGroup {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}", self.name, self.subs.join("."))
+	}
+}
+
+/// Group names end up in URLs and cookies, so keep them simple
+pub fn valid_group_name(name: &str) -> bool {
+	!name.is_empty() && name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn serialize_groups<S>(groups: &[SubscriptionGroup], serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	serializer.serialize_str(&groups.iter().map(ToString::to_string).collect::<Vec<_>>().join("+"))
+}
+
+fn deserialize_groups<'de, D>(deserializer: D) -> Result<Vec<SubscriptionGroup>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let string = String::deserialize(deserializer)?;
+	Ok(string.split('+').filter_map(SubscriptionGroup::parse).collect())
 }
 
 fn serialize_vec_with_plus<S>(vec: &[String], serializer: S) -> Result<S::Ok, S::Error>
@@ -725,15 +971,39 @@ impl Preferences {
 			hide_awards: setting(req, "hide_awards"),
 			hide_score: setting(req, "hide_score"),
 			remove_default_feeds: setting(req, "remove_default_feeds"),
+			subscription_groups: setting(req, "subscription_groups").split('+').filter_map(SubscriptionGroup::parse).collect(),
+			bookmarks: setting(req, "bookmarks").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
+			keep_tracking_params: setting(req, "keep_tracking_params"),
+			frontends: setting(req, "frontends"),
+			disable_syntax_highlighting: setting(req, "disable_syntax_highlighting"),
+			lite: setting(req, "lite"),
 		}
 	}
 
+	/// Whether a post (`t3`) or comment (`t1`) has been saved
+	pub fn is_bookmarked(&self, kind: &str, id: &str) -> bool {
+		self.bookmarks.iter().any(|saved| saved.split_once('_') == Some((kind, id)))
+	}
+
+	/// Find a subscription group by name
+	pub fn group(&self, name: &str) -> Option<&SubscriptionGroup> {
+		self.subscription_groups.iter().find(|group| group.name.eq_ignore_ascii_case(name))
+	}
+
 	pub fn to_urlencoded(&self) -> Result<String, String> {
 		serde_urlencoded::to_string(self).map_err(|e| e.to_string())
 	}
 
+	/// Encode preferences with their schema revision, for exports and sync
 	pub fn to_bincode(&self) -> Result<Vec<u8>, String> {
-		bincode::serialize(self).map_err(|e| e.to_string())
+		// Themes come from the instance and bookmarks are exported separately
+		let prefs = Self {
+			available_themes: Vec::new(),
+			bookmarks: Vec::new(),
+			..self.clone()
+		};
+		let encoded = revision::to_vec(&prefs).map_err(|e| e.to_string())?;
+		Ok([VERSIONED_PREFS_MAGIC, &encoded].concat())
 	}
 	pub fn to_compressed_bincode(&self) -> Result<Vec<u8>, String> {
 		deflate_compress(self.to_bincode()?)
@@ -741,6 +1011,31 @@ impl Preferences {
 	pub fn to_bincode_str(&self) -> Result<String, String> {
 		Ok(base2048::encode(&self.to_compressed_bincode()?))
 	}
+
+	/// Decode exported preferences, upgrading those from older revisions
+	pub fn from_bincode(bytes: &[u8]) -> Result<Self, String> {
+		let Some(encoded) = bytes.strip_prefix(VERSIONED_PREFS_MAGIC) else {
+			return Self::from_unversioned(bytes);
+		};
+
+		let revision = u16::deserialize_revisioned(&mut &encoded[..]).map_err(|e| format!("Failed to read preferences revision: {e}"))?;
+		if revision > Self::revision() {
+			return Err(format!(
+				"These settings were exported from a newer version of Redlib (settings revision {revision}, this instance supports up to {}). Update this instance or export them from one running the same version.",
+				Self::revision()
+			));
+		}
+
+		// Fields added after the blob's revision are filled with their defaults
+		revision::from_slice(encoded).map_err(|e| format!("Failed to deserialize bytes into Preferences struct: {e}"))
+	}
+
+	/// Decode exports made before preferences carried their revision
+	fn from_unversioned(bytes: &[u8]) -> Result<Self, String> {
+		bincode::deserialize::<PreferencesV1>(bytes)
+			.map(Self::from)
+			.map_err(|e| format!("Failed to deserialize bytes into Preferences struct: {e}"))
+	}
 }
 
 pub fn deflate_compress(i: Vec<u8>) -> Result<Vec<u8>, String> {
@@ -809,15 +1104,7 @@ pub async fn parse_post(post: &Value) -> Post {
 			get_setting("REDLIB_PUSHSHIFT_FRONTEND").unwrap_or_else(|| String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
 		)
 	} else {
-		let selftext = val(post, "selftext");
-		if selftext.contains("```") {
-			let mut html_output = String::new();
-			let parser = pulldown_cmark::Parser::new(&selftext);
-			pulldown_cmark::html::push_html(&mut html_output, parser);
-			rewrite_urls(&html_output)
-		} else {
-			rewrite_urls(&val(post, "selftext_html"))
-		}
+		rewrite_emotes(&post["data"]["media_metadata"], markdown::render(&val(post, "selftext"), &post["data"]["media_metadata"]))
 	};
 
 	// Build a post using data parsed from Reddit post API
@@ -843,6 +1130,7 @@ pub async fn parse_post(post: &Value) -> Post {
 		permalink,
 		link_title: val(post, "link_title"),
 		poll,
+		embed: if post_type == "link" { Embed::parse(&post["data"]) } else { None },
 		score: format_num(score),
 		upvote_ratio: ratio as i64,
 		post_type,
@@ -854,6 +1142,7 @@ pub async fn parse_post(post: &Value) -> Post {
 			height: post["data"]["thumbnail_height"].as_i64().unwrap_or_default(),
 			poster: String::new(),
 			download_name: String::new(),
+			variants: Vec::new(),
 		},
 		flair: Flair {
 			flair_parts: FlairPart::parse(
@@ -909,57 +1198,28 @@ pub fn param(path: &str, value: &str) -> Option<String> {
 pub fn setting(req: &Request<Body>, name: &str) -> String {
 	// Parse a cookie value from request
 
-	// If this was called with "subscriptions" and the "subscriptions" cookie has a value
-	if name == "subscriptions" && req.cookie("subscriptions").is_some() {
-		// Create subscriptions string
-		let mut subscriptions = String::new();
-
-		// Default subscriptions cookie
-		if req.cookie("subscriptions").is_some() {
-			subscriptions.push_str(req.cookie("subscriptions").unwrap().value());
-		}
-
-		// Start with first numbered subscription cookie
-		let mut subscriptions_number = 1;
-
-		// While whatever subscriptionsNUMBER cookie we're looking at has a value
-		while req.cookie(&format!("subscriptions{subscriptions_number}")).is_some() {
-			// Push whatever subscriptionsNUMBER cookie we're looking at into the subscriptions string
-			subscriptions.push_str(req.cookie(&format!("subscriptions{subscriptions_number}")).unwrap().value());
-
-			// Increment subscription cookie number
-			subscriptions_number += 1;
-		}
-
-		// Return the subscriptions cookies as one large string
-		subscriptions
-	}
-	// If this was called with "filters" and the "filters" cookie has a value
-	else if name == "filters" && req.cookie("filters").is_some() {
-		// Create filters string
-		let mut filters = String::new();
-
-		// Default filters cookie
-		if req.cookie("filters").is_some() {
-			filters.push_str(req.cookie("filters").unwrap().value());
-		}
+	// If this was called with a list setting (like "subscriptions") and its cookie has a value
+	if matches!(name, "subscriptions" | "filters" | "subscription_groups" | "bookmarks") && req.cookie(name).is_some() {
+		// Create list string, starting with the default cookie
+		let mut list = String::new();
+		list.push_str(req.cookie(name).unwrap().value());
 
-		// Start with first numbered filters cookie
-		let mut filters_number = 1;
+		// Start with first numbered cookie
+		let mut number = 1;
 
-		// While whatever filtersNUMBER cookie we're looking at has a value
-		while req.cookie(&format!("filters{filters_number}")).is_some() {
-			// Push whatever filtersNUMBER cookie we're looking at into the filters string
-			filters.push_str(req.cookie(&format!("filters{filters_number}")).unwrap().value());
+		// While whatever nameNUMBER cookie we're looking at has a value
+		while let Some(cookie) = req.cookie(&format!("{name}{number}")) {
+			// Push whatever nameNUMBER cookie we're looking at into the list string
+			list.push_str(cookie.value());
 
-			// Increment filters cookie number
-			filters_number += 1;
+			// Increment cookie number
+			number += 1;
 		}
 
-		// Return the filters cookies as one large string
-		filters
+		// Return the list cookies as one large string
+		list
 	}
-	// The above two still come to this if there was no existing value
+	// The above still comes to this if there was no existing value
 	else {
 		req
 			.cookie(name)
@@ -1006,6 +1266,7 @@ static REGEX_URL_NP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://np
 static REGEX_URL_PLAIN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://reddit\.com/(.*)").unwrap());
 static REGEX_URL_VIDEOS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://v\.redd\.it/(.*)/DASH_([0-9]{2,4}(\.mp4|$|\?source=fallback))").unwrap());
 static REGEX_URL_VIDEOS_HLS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://v\.redd\.it/(.+)/(HLSPlaylist\.m3u8.*)$").unwrap());
+static REGEX_URL_VIDEO_PAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://v\.redd\.it/([A-Za-z0-9]+)/?$").unwrap());
 static REGEX_URL_IMAGES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://i\.redd\.it/(.*)").unwrap());
 static REGEX_URL_THUMBS_A: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://a\.thumbs\.redditmedia\.com/(.*)").unwrap());
 static REGEX_URL_THUMBS_B: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://b\.thumbs\.redditmedia\.com/(.*)").unwrap());
@@ -1038,7 +1299,7 @@ pub fn format_url(url: &str) -> String {
 					}
 				};
 
-				( $first_fn:expr, $($other_fns:expr), *) => {
+				( $first_fn:expr, $($other_fns:expr), * $(,)?) => {
 					{
 						let result = $first_fn;
 						if result.is_empty() {
@@ -1053,11 +1314,15 @@ pub fn format_url(url: &str) -> String {
 			}
 
 			match domain {
-				"www.reddit.com" => capture(&REGEX_URL_WWW, "/", 1),
-				"old.reddit.com" => capture(&REGEX_URL_OLD, "/", 1),
-				"np.reddit.com" => capture(&REGEX_URL_NP, "/", 1),
-				"reddit.com" => capture(&REGEX_URL_PLAIN, "/", 1),
-				"v.redd.it" => chain!(capture(&REGEX_URL_VIDEOS, "/vid/", 2), capture(&REGEX_URL_VIDEOS_HLS, "/hls/", 2)),
+				"www.reddit.com" => rewrite_reddit_aliases(&capture(&REGEX_URL_WWW, "/", 1)),
+				"old.reddit.com" => rewrite_reddit_aliases(&capture(&REGEX_URL_OLD, "/", 1)),
+				"np.reddit.com" => rewrite_reddit_aliases(&capture(&REGEX_URL_NP, "/", 1)),
+				"reddit.com" => rewrite_reddit_aliases(&capture(&REGEX_URL_PLAIN, "/", 1)),
+				"v.redd.it" => chain!(
+					capture(&REGEX_URL_VIDEOS, "/vid/", 2),
+					capture(&REGEX_URL_VIDEOS_HLS, "/hls/", 2),
+					capture(&REGEX_URL_VIDEO_PAGE, "/video/", 1)
+				),
 				"i.redd.it" => capture(&REGEX_URL_IMAGES, "/img/", 1),
 				"a.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_A, "/thumb/a/", 1),
 				"b.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_B, "/thumb/b/", 1),
@@ -1072,19 +1337,30 @@ pub fn format_url(url: &str) -> String {
 	}
 }
 
-static REGEX_BULLET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^- (.*)$").unwrap());
-static REGEX_BULLET_CONSECUTIVE_LINES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</ul>\n<ul>").unwrap());
+static REGEX_PATH_POST_ALIAS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^/(?:gallery|poll)/([A-Za-z0-9]+)/?$").unwrap());
+static REGEX_PATH_NEW_COMMENT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^/r/([^/]+)/comments/([A-Za-z0-9]+)/comment/([A-Za-z0-9]+)/?(\?.*)?$").unwrap());
 
-pub fn render_bullet_lists(input_text: &str) -> String {
-	// ref: https://stackoverflow.com/a/4902622
-	// First enclose each bullet with <ul> <li> tags
-	let text1 = REGEX_BULLET.replace_all(input_text, "<ul><li>$1</li></ul>").to_string();
-	// Then remove any consecutive </ul> <ul> tags
-	REGEX_BULLET_CONSECUTIVE_LINES.replace_all(&text1, "").to_string()
+/// Point Reddit paths that are other names for a post, comment or image at the
+/// page Redlib shows for them. Other paths are returned unchanged.
+fn rewrite_reddit_aliases(path: &str) -> String {
+	if let Some(caps) = REGEX_PATH_POST_ALIAS.captures(path) {
+		return format!("/comments/{}", &caps[1]);
+	}
+	if let Some(caps) = REGEX_PATH_NEW_COMMENT.captures(path) {
+		return format!("/r/{}/comments/{}/_/{}{}", &caps[1], &caps[2], &caps[3], caps.get(4).map_or("", |query| query.as_str()));
+	}
+	if path.starts_with("/media?") {
+		if let Some(media) = param(path, "url").map(|url| format_url(&url)).filter(|media| media.starts_with('/')) {
+			return media;
+		}
+	}
+	path.to_string()
 }
 
 // These are links we want to replace in-body
 static REDDIT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"href="(https|http|)://(www\.|old\.|np\.|amp\.|new\.|)(reddit\.com|redd\.it)/"#).unwrap());
+static REDDIT_ALIAS_REGEX: LazyLock<Regex> =
+	LazyLock::new(|| Regex::new(r#"href="(/(?:gallery|poll|media|r/[^/"]+/comments/[A-Za-z0-9]+/comment)\b[^"]*|https?://v\.redd\.it/[A-Za-z0-9]+/?)""#).unwrap());
 static REDDIT_PREVIEW_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://(external-preview|preview|i)\.redd\.it(.*)").unwrap());
 static REDDIT_EMOJI_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://(www|).redditstatic\.com/(.*)").unwrap());
 static REDLIB_PREVIEW_LINK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"/(img|preview/)(pre|external-pre)?/(.*?)>"#).unwrap());
@@ -1096,6 +1372,19 @@ pub fn rewrite_urls(input_text: &str) -> String {
 		// Rewrite Reddit links to Redlib
 		REDDIT_REGEX.replace_all(input_text, r#"href="/"#).to_string();
 
+	// Point links to galleries, polls, videos, Reddit's image viewer and new-style comment links at the matching pages
+	text1 = REDDIT_ALIAS_REGEX
+		.replace_all(&text1, |caps: &regex::Captures| {
+			let link = caps[1].replace("&amp;", "&");
+			let rewritten = if link.starts_with('/') { rewrite_reddit_aliases(&link) } else { format_url(&link) };
+			if rewritten == link {
+				caps[0].to_string()
+			} else {
+				format!("href=\"{}\"", rewritten.replace('&', "&amp;"))
+			}
+		})
+		.to_string();
+
 	loop {
 		if REDDIT_EMOJI_REGEX.find(&text1).is_none() {
 			break;
@@ -1161,84 +1450,62 @@ pub fn rewrite_urls(input_text: &str) -> String {
 // These links all follow a pattern of "https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/SUBREDDIT_ID/RANDOM_FILENAME.png"
 static REDDIT_EMOTE_LINK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/(.*)"#).unwrap());
 
-// These all follow a pattern of '"emote|SUBREDDIT_IT|NUMBER"', we want the number
-static REDDIT_EMOTE_ID_NUMBER_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#""emote\|.*\|(.*)""#).unwrap());
-
+/// Replace the `:name:` placeholders of emotes listed in `media_metadata` with
+/// their images, then rewrite the rest of the links.
 pub fn rewrite_emotes(media_metadata: &Value, comment: String) -> String {
-	/* Create the paths we'll use to look for our data inside the json.
-	Because we don't know the name of any given emote we use a wildcard to parse them. */
-	let link_path = JsonPath::parse("$[*].s.u").expect("valid JSON Path");
-	let id_path = JsonPath::parse("$[*].id").expect("valid JSON Path");
-	let size_path = JsonPath::parse("$[*].s.y").expect("valid JSON Path");
-
-	// Extract all of the results from those json paths
-	let link_nodes = media_metadata.json_path(&link_path);
-	let id_nodes = media_metadata.json_path(&id_path);
-
-	// Initialize our vectors
-	let mut id_vec = Vec::new();
-	let mut link_vec = Vec::new();
+	let mut comment = comment;
 
-	// Add the relevant data to each of our vectors so we can access it by number later
-	for current_id in id_nodes {
-		id_vec.push(current_id)
-	}
-	for current_link in link_nodes {
-		link_vec.push(current_link)
-	}
+	for emote in media_metadata.as_object().into_iter().flat_map(|media| media.values()) {
+		// Emotes have IDs like "emote|t5_31hpy|2028" for a subreddit's own, or "emote|free_emotes_pack|joy"
+		let Some(name) = emote["id"].as_str().and_then(|id| id.strip_prefix("emote|")).and_then(|id| id.rsplit('|').next()) else {
+			continue;
+		};
+		// Animated emotes have no still image, just a GIF
+		let Some(link) = emote["s"]["u"].as_str().or_else(|| emote["s"]["gif"].as_str()) else {
+			continue;
+		};
+		let src = REDDIT_EMOTE_LINK_REGEX
+			.captures(link)
+			.map_or_else(|| format_url(link), |caps| format!("/emote/{}", &caps[1]));
 
-	/* Set index to the length of link_vec.
-	This is one larger than we'll actually be looking at, but we correct that later */
-	let mut index = link_vec.len();
+		/* Reddit sends a size for the image based on whether it's alone or accompanied by text.
+		It's a good idea and makes everything look nicer, so we'll do the same. */
+		let size = emote["s"]["y"].as_i64().unwrap_or(20);
 
-	// Comment needs to be in scope for when we call rewrite_urls()
-	let mut comment = comment;
+		// Subreddit emotes are only known by number, so there's no better description for them
+		let alt = if name.chars().all(|c| c.is_ascii_digit()) {
+			"Emote".to_string()
+		} else {
+			format!(":{name}:")
+		};
 
-	/* Loop until index hits zero.
-	This also prevents us from trying to do anything on an empty vector */
-	while index != 0 {
-		/* Subtract 1 from index to get the real index we should be looking at.
-		Then continue on each subsequent loop to continue until we hit the last entry in the vector.
-		This is how we get this to deal with multiple emotes in a single message and properly replace each ID with it's link */
-		index -= 1;
-
-		// Convert our current index in id_vec into a string so we can search through it with regex
-		let current_id = id_vec[index].to_string();
-
-		/* The ID number can be multiple lengths, so we capture it with regex.
-		We also want to only attempt anything when we get matches to avoid panicking */
-		if let Some(id_capture) = REDDIT_EMOTE_ID_NUMBER_REGEX.captures(&current_id) {
-			// Format the ID to include the colons it has in the comment text
-			let id = format!(":{}:", &id_capture[1]);
-
-			// Convert current link to string to search through it with the regex
-			let link = link_vec[index].to_string();
-
-			// Make sure we only do operations when we get matches, otherwise we panic when trying to access the first match
-			if let Some(link_capture) = REDDIT_EMOTE_LINK_REGEX.captures(&link) {
-				/* Reddit sends a size for the image based on whether it's alone or accompanied by text.
-				It's a good idea and makes everything look nicer, so we'll do the same. */
-				let size = media_metadata.json_path(&size_path).first().unwrap().to_string();
-
-				// Replace the ID we found earlier in the comment with the respective image and it's link from the regex capture
-				let to_replace_with = format!(
-					"<img loading=\"lazy\" src=\"/emote/{} width=\"{size}\" height=\"{size}\" style=\"vertical-align:text-bottom\">",
-					&link_capture[1]
-				);
-
-				// Inside the comment replace the ID we found with the string that will embed the image
-				comment = comment.replace(&id, &to_replace_with);
-			}
-		}
+		comment = comment.replace(
+			&format!(":{name}:"),
+			&format!("<img loading=\"lazy\" src=\"{src}\" alt=\"{alt}\" title=\"{alt}\" width=\"{size}\" height=\"{size}\" style=\"vertical-align:text-bottom\">"),
+		);
 	}
 
-	// render bullet (unordered) lists
-	comment = render_bullet_lists(&comment);
-
 	// Call rewrite_urls() to transform any other Reddit links
 	rewrite_urls(&comment)
 }
 
+static IMG_TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<img\b[^>]*>").unwrap());
+static IMG_ALT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\balt="([^"]*)""#).unwrap());
+
+/// Replace the images inlined in a body, such as previews and emotes, with
+/// their alt text so lite mode loads none of them. Previews are already
+/// wrapped in a link to the image, so they can still be opened.
+pub fn lite_images(html: &str) -> String {
+	IMG_TAG_REGEX
+		.replace_all(html, |caps: &regex::Captures| {
+			let alt = IMG_ALT_REGEX
+				.captures(&caps[0])
+				.map(|alt| htmlescape::decode_html(&alt[1]).unwrap_or_else(|_| alt[1].to_string()));
+			htmlescape::encode_minimal(alt.as_deref().filter(|alt| !alt.is_empty()).unwrap_or("Image"))
+		})
+		.to_string()
+}
+
 /// Format vote count to a string that will be displayed.
 /// Append `m` and `k` for millions and thousands respectively, and
 /// round to the nearest tenth.
@@ -1319,6 +1586,9 @@ pub fn redirect(path: &str) -> Response<Body> {
 /// Renders a generic error landing page.
 pub async fn error(req: Request<Body>, msg: &str) -> Result<Response<Body>, String> {
 	error!("Error page rendered: {}", msg.split('|').next().unwrap_or_default());
+	if crate::text::wanted(&req) {
+		return Ok(crate::text::message(404, msg.split('|').next().unwrap_or_default()));
+	}
 	let url = req.uri().to_string();
 	let body = ErrorTemplate {
 		msg: msg.to_string(),
@@ -1369,6 +1639,16 @@ pub fn enable_rss() -> bool {
 	}
 }
 
+/// Returns true if the config/env variable REDLIB_ENABLE_SYNC is set to "on".
+/// If this variable is set as such, users can store their preferences on the
+/// instance under a sync key. Otherwise, preferences only live in cookies.
+pub fn enable_sync() -> bool {
+	match get_setting("REDLIB_ENABLE_SYNC") {
+		Some(val) => val == "on",
+		None => false,
+	}
+}
+
 /// Returns true if the config/env variable `REDLIB_ROBOTS_DISABLE_INDEXING` carries the
 /// value `on`.
 ///
@@ -1389,6 +1669,14 @@ pub fn should_be_nsfw_gated(req: &Request<Body>, _req_url: &str) -> bool {
 /// Renders the landing page for NSFW content when the user has not enabled
 /// "show NSFW posts" in settings.
 pub async fn nsfw_landing(req: Request<Body>, req_url: String) -> Result<Response<Body>, String> {
+	if crate::text::wanted(&req) {
+		let msg = if sfw_only() {
+			"This content is NSFW, and this instance only shows SFW content."
+		} else {
+			"This content is NSFW. Enable NSFW content in settings to view it."
+		};
+		return Ok(crate::text::message(403, msg));
+	}
 	let res_type: ResourceType;
 
 	// Determine from the request URL if the resource is a subreddit, a user
@@ -1449,9 +1737,31 @@ pub fn to_absolute_url(relative_path: &str) -> String {
 	format!("{}{}", config::get_setting("REDLIB_FULL_URL").unwrap_or_default(), relative_path)
 }
 
+/// Where this instance is reached: `REDLIB_FULL_URL`, or else the host the
+/// request was sent to
+pub fn base_url(req: &Request<Body>) -> Option<String> {
+	if let Some(full_url) = config::get_setting("REDLIB_FULL_URL").filter(|url| !url.is_empty()) {
+		return Some(full_url.trim_end_matches('/').to_string());
+	}
+
+	let host = req.headers().get("host")?.to_str().ok().filter(|host| !host.is_empty())?;
+	let local = host.starts_with("localhost") || host.starts_with("127.") || host.starts_with("[::1]");
+	let scheme = match req.headers().get("x-forwarded-proto").and_then(|proto| proto.to_str().ok()) {
+		Some(proto @ ("http" | "https")) => proto,
+		_ if local => "http",
+		_ => "https",
+	};
+	Some(format!("{scheme}://{host}"))
+}
+
 #[cfg(test)]
 mod tests {
-	use super::{deflate_compress, deflate_decompress, format_num, format_url, render_bullet_lists, rewrite_emotes, rewrite_urls, url_path_basename, Post, Preferences};
+	use super::{
+		base_url, deflate_compress, deflate_decompress, format_num, format_url, lite_images, rewrite_emotes, rewrite_urls, url_path_basename, GalleryMedia, Post, Preferences,
+		SubscriptionGroup, VERSIONED_PREFS_MAGIC,
+	};
+	use hyper::{Body, Request};
+	use revision::{revisioned, Revisioned, SerializeRevisioned};
 
 	#[test]
 	fn format_num_works() {
@@ -1478,6 +1788,19 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn lite_images_leaves_alt_text() {
+		let body = rewrite_urls("<p><a href=\"https://preview.redd.it/abc.png?width=640\">Caption</a></p>");
+		assert!(body.contains("<img"));
+		assert_eq!(
+			lite_images(&body),
+			r#"<figure><a href="/preview/pre/abc.png?width=640">Image</a><figcaption>Caption</figcaption></figure>"#
+		);
+
+		let emote = r#"<p>hi <img loading="lazy" src="/emote/t5_1/x.png" alt=":&lt;b&gt;:" title=":b:" width="20" height="20"></p>"#;
+		assert_eq!(lite_images(emote), "<p>hi :&lt;b&gt;:</p>");
+	}
+
 	#[test]
 	fn rewrite_urls_keeps_intentional_backslashes() {
 		assert_eq!(
@@ -1517,6 +1840,23 @@ mod tests {
 		assert_eq!(format_url("default"), "");
 		assert_eq!(format_url("nsfw"), "");
 		assert_eq!(format_url("spoiler"), "");
+
+		assert_eq!(format_url("https://www.reddit.com/gallery/abc123"), "/comments/abc123");
+		assert_eq!(format_url("https://reddit.com/poll/abc123/"), "/comments/abc123");
+		assert_eq!(format_url("https://v.redd.it/abc123"), "/video/abc123");
+		assert_eq!(format_url("https://www.reddit.com/media?url=https%3A%2F%2Fi.redd.it%2Fxyz.jpg"), "/img/xyz.jpg");
+		assert_eq!(
+			format_url("https://www.reddit.com/media?url=https%3A%2F%2Fexample.com%2Fxyz.jpg"),
+			"/media?url=https%3A%2F%2Fexample.com%2Fxyz.jpg"
+		);
+		assert_eq!(
+			format_url("https://www.reddit.com/r/rust/comments/abc123/comment/def456/?context=3"),
+			"/r/rust/comments/abc123/_/def456?context=3"
+		);
+		assert_eq!(
+			format_url("https://old.reddit.com/r/rust/comments/abc123/title/def456/?context=3"),
+			"/r/rust/comments/abc123/title/def456/?context=3"
+		);
 	}
 	#[test]
 	fn serialize_prefs() {
@@ -1543,10 +1883,39 @@ mod tests {
 			hide_awards: "off".to_owned(),
 			hide_score: "off".to_owned(),
 			remove_default_feeds: "off".to_owned(),
+			subscription_groups: vec![SubscriptionGroup {
+				name: "news".to_owned(),
+				subs: vec!["worldnews".to_owned(), "politics".to_owned()],
+			}],
+			bookmarks: vec!["t3_abc".to_owned()],
+			keep_tracking_params: "off".to_owned(),
+			frontends: "youtube:yewtu.be".to_owned(),
+			disable_syntax_highlighting: "on".to_owned(),
+			lite: "off".to_owned(),
 		};
 		let urlencoded = serde_urlencoded::to_string(prefs).expect("Failed to serialize Prefs");
 
-		assert_eq!(urlencoded, "theme=laserwave&front_page=default&layout=compact&wide=on&blur_spoiler=on&show_nsfw=off&blur_nsfw=on&hide_hls_notification=off&video_quality=best&hide_sidebar_and_summary=off&use_hls=on&autoplay_videos=on&fixed_navbar=on&disable_visit_reddit_confirmation=on&comment_sort=confidence&post_sort=top&subscriptions=memes%2Bmildlyinteresting&filters=&hide_awards=off&hide_score=off&remove_default_feeds=off");
+		assert_eq!(urlencoded, "theme=laserwave&front_page=default&layout=compact&wide=on&blur_spoiler=on&show_nsfw=off&blur_nsfw=on&hide_hls_notification=off&video_quality=best&hide_sidebar_and_summary=off&use_hls=on&autoplay_videos=on&fixed_navbar=on&disable_visit_reddit_confirmation=on&comment_sort=confidence&post_sort=top&subscriptions=memes%2Bmildlyinteresting&filters=&hide_awards=off&hide_score=off&remove_default_feeds=off&subscription_groups=news%3Aworldnews.politics&keep_tracking_params=off&frontends=youtube%3Ayewtu.be&disable_syntax_highlighting=on&lite=off");
+	}
+
+	#[test]
+	fn test_subscription_groups() {
+		let group = SubscriptionGroup::parse("programming:rust.golang").unwrap();
+		assert_eq!(group.name, "programming");
+		assert_eq!(group.joined(), "rust+golang");
+		assert_eq!(group.to_string(), "programming:rust.golang");
+
+		assert!(SubscriptionGroup::parse("no subs:").is_none());
+		assert!(SubscriptionGroup::parse("bad name:rust").is_none());
+		assert!(SubscriptionGroup::parse("rust").is_none());
+
+		let prefs = Preferences {
+			subscription_groups: vec![group],
+			..Preferences::default()
+		};
+		test_round_trip(&prefs, true);
+		let decoded = Preferences::from_bincode(&prefs.to_bincode().unwrap()).unwrap();
+		assert_eq!(decoded.group("PROGRAMMING").map(|g| g.subs.len()), Some(2));
 	}
 
 	#[test]
@@ -1590,6 +1959,27 @@ mod tests {
 		assert_eq!(rewrite_urls(input), output);
 	}
 
+	#[test]
+	fn test_rewriting_reddit_aliases() {
+		assert_eq!(
+			rewrite_urls(r#"<a href="https://www.reddit.com/gallery/abc123">gallery</a> <a href="https://reddit.com/poll/def456/">poll</a>"#),
+			r#"<a href="/comments/abc123">gallery</a> <a href="/comments/def456">poll</a>"#
+		);
+		assert_eq!(rewrite_urls(r#"<a href="https://v.redd.it/abc123">video</a>"#), r#"<a href="/video/abc123">video</a>"#);
+		assert_eq!(
+			rewrite_urls(r#"<a href="https://www.reddit.com/media?url=https%3A%2F%2Fi.redd.it%2Fxyz.jpg">image</a>"#),
+			r#"<a href="/img/xyz.jpg">image</a>"#
+		);
+		assert_eq!(
+			rewrite_urls(r#"<a href="https://www.reddit.com/r/rust/comments/abc123/comment/def456/?context=3&amp;utm_source=share">comment</a>"#),
+			r#"<a href="/r/rust/comments/abc123/_/def456?context=3&amp;utm_source=share">comment</a>"#
+		);
+		assert_eq!(
+			rewrite_urls(r#"<a href="https://www.reddit.com/r/rust/comments/abc123/title/def456/?context=3">comment</a>"#),
+			r#"<a href="/r/rust/comments/abc123/title/def456/?context=3">comment</a>"#
+		);
+	}
+
 	#[test]
 	fn test_url_path_basename() {
 		// without trailing slash
@@ -1606,37 +1996,45 @@ mod tests {
 		assert_eq!(url_path_basename("/"), "");
 	}
 
+	#[test]
+	fn test_gallery_variants() {
+		let items = serde_json::json!([{"media_id": "abc"}, {"media_id": "gif"}]);
+		let metadata = serde_json::json!({
+			"abc": {"m": "image/jpg", "s": {"u": "https://preview.redd.it/abc.jpg?width=4000&s=full", "x": 4000, "y": 3000},
+				"p": [{"u": "https://preview.redd.it/abc.jpg?width=640&s=b", "x": 640, "y": 480}, {"u": "https://preview.redd.it/abc.jpg?width=108&s=a", "x": 108, "y": 81}]},
+			"gif": {"m": "image/gif", "s": {"gif": "https://i.redd.it/gif.gif", "x": 200, "y": 200}, "p": [{"u": "https://preview.redd.it/gif.jpg?width=108", "x": 108, "y": 108}]}
+		});
+		let gallery = GalleryMedia::parse(&items, &metadata);
+
+		assert_eq!(gallery[0].image(100), "/preview/pre/abc.jpg?width=108&s=a");
+		assert_eq!(gallery[0].image(300), "/preview/pre/abc.jpg?width=640&s=b");
+		assert_eq!(gallery[0].image(1000), "/preview/pre/abc.jpg?width=4000&s=full");
+		assert_eq!(
+			gallery[0].srcset(),
+			"/preview/pre/abc.jpg?width=108&s=a 108w, /preview/pre/abc.jpg?width=640&s=b 640w, /preview/pre/abc.jpg?width=4000&s=full 4000w"
+		);
+
+		// GIFs' previews are stills, so they're left out
+		assert_eq!(gallery[1].image(100), "/img/gif.gif");
+		assert_eq!(gallery[1].srcset(), "");
+	}
+
 	#[test]
 	fn test_rewriting_emotes() {
 		let json_input = serde_json::from_str(r#"{"emote|t5_31hpy|2028":{"e":"Image","id":"emote|t5_31hpy|2028","m":"image/png","s":{"u":"https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/t5_31hpy/PW6WsOaLcd.png","x":60,"y":60},"status":"valid","t":"sticker"}}"#).expect("Valid JSON");
 		let comment_input = r#"<div class="comment_body "><div class="md"><p>:2028:</p></div></div>"#;
-		let output = r#"<div class="comment_body "><div class="md"><p><img loading="lazy" src="/emote/t5_31hpy/PW6WsOaLcd.png" width="60" height="60" style="vertical-align:text-bottom"></p></div></div>"#;
+		let output = r#"<div class="comment_body "><div class="md"><p><img loading="lazy" src="/emote/t5_31hpy/PW6WsOaLcd.png" alt="Emote" title="Emote" width="60" height="60" style="vertical-align:text-bottom"></p></div></div>"#;
 		assert_eq!(rewrite_emotes(&json_input, comment_input.to_string()), output);
-	}
 
-	#[test]
-	fn test_rewriting_bullet_list() {
-		let input = r#"<div class="md"><p>Hi, I&#39;ve bought this very same monitor and found no calibration whatsoever. I have an ICC profile that has been set up since I&#39;ve installed its driver from the LG website and it works ok. I also used <a href="http://www.lagom.nl/lcd-test/">http://www.lagom.nl/lcd-test/</a> to calibrate it. After some good tinkering I&#39;ve found the following settings + the color profile from the driver gets me past all the tests perfectly:
-- Brightness 50 (still have to settle on this one, it&#39;s personal preference, it controls the backlight, not the colors)
-- Contrast 70 (which for me was the default one)
-- Picture mode Custom
-- Super resolution + Off (it looks horrible anyway)
-- Sharpness 50 (default one I think)
-- Black level High (low messes up gray colors)
-- DFC Off
-- Response Time Middle (personal preference, <a href="https://www.blurbusters.com/">https://www.blurbusters.com/</a> show horrible overdrive with it on high)
-- Freesync doesn&#39;t matter
-- Black stabilizer 50
-- Gamma setting on 0
-- Color Temp Medium
-How`s your monitor by the way? Any IPS bleed whatsoever? I either got lucky or the panel is pretty good, 0 bleed for me, just the usual IPS glow. How about the pixels? I see the pixels even at one meter away, especially on Microsoft Edge&#39;s icon for example, the blue background is just blocky, don&#39;t know why.</p>
-</div>"#;
-		let output = r#"<div class="md"><p>Hi, I&#39;ve bought this very same monitor and found no calibration whatsoever. I have an ICC profile that has been set up since I&#39;ve installed its driver from the LG website and it works ok. I also used <a href="http://www.lagom.nl/lcd-test/">http://www.lagom.nl/lcd-test/</a> to calibrate it. After some good tinkering I&#39;ve found the following settings + the color profile from the driver gets me past all the tests perfectly:
-<ul><li>Brightness 50 (still have to settle on this one, it&#39;s personal preference, it controls the backlight, not the colors)</li><li>Contrast 70 (which for me was the default one)</li><li>Picture mode Custom</li><li>Super resolution + Off (it looks horrible anyway)</li><li>Sharpness 50 (default one I think)</li><li>Black level High (low messes up gray colors)</li><li>DFC Off</li><li>Response Time Middle (personal preference, <a href="https://www.blurbusters.com/">https://www.blurbusters.com/</a> show horrible overdrive with it on high)</li><li>Freesync doesn&#39;t matter</li><li>Black stabilizer 50</li><li>Gamma setting on 0</li><li>Color Temp Medium</li></ul>
-How`s your monitor by the way? Any IPS bleed whatsoever? I either got lucky or the panel is pretty good, 0 bleed for me, just the usual IPS glow. How about the pixels? I see the pixels even at one meter away, especially on Microsoft Edge&#39;s icon for example, the blue background is just blocky, don&#39;t know why.</p>
-</div>"#;
-
-		assert_eq!(render_bullet_lists(input), output);
+		// Animated emotes only have a GIF, and named ones are described by name
+		let json_input = serde_json::from_str(
+			r#"{"emote|t5_31hpy|1234":{"e":"AnimatedImage","id":"emote|t5_31hpy|1234","m":"image/gif","s":{"gif":"https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/t5_31hpy/dance.gif","x":20,"y":20},"status":"valid","t":"sticker"},
+			"emote|free_emotes_pack|joy":{"e":"Image","id":"emote|free_emotes_pack|joy","m":"image/gif","s":{"u":"https://www.redditstatic.com/marketplace-assets/v1/core/emotes/snoomoji_emotes/free_emotes_pack/joy.gif","x":20,"y":20},"status":"valid","t":"sticker"}}"#,
+		)
+		.expect("Valid JSON");
+		let output = rewrite_emotes(&json_input, "<p>:1234: :joy:</p>".to_string());
+		assert!(output.contains(r#"<img loading="lazy" src="/emote/t5_31hpy/dance.gif" alt="Emote" title="Emote" width="20" height="20""#));
+		assert!(output.contains(r#"src="/static/marketplace-assets/v1/core/emotes/snoomoji_emotes/free_emotes_pack/joy.gif" alt=":joy:""#));
 	}
 
 	#[test]
@@ -1654,37 +2052,150 @@ How`s your monitor by the way? Any IPS bleed whatsoever? I either got lucky or t
 		test_round_trip(&prefs, true);
 	}
 
-	static KNOWN_GOOD_CONFIGS: &[&str] = &[
-		"ఴӅβØØҞÉဏႢձĬ༧ȒʯऌԔӵ୮༏",
-		"ਧՊΥÀÃǎƱГ۸ඣമĖฤ႙ʟาúໜϾௐɥঀĜໃહཞઠѫҲɂఙ࿔ǲઉƲӟӻĻฅΜδ໖ԜǗဖငƦơ৶Ą௩ԹʛใЛʃශаΏ",
-		"ਧԩΥÀÃÎŠ౭൩ඔႠϼҭöҪƸռઇԾॐნɔາǒՍҰच௨ಖມŃЉŐདƦ๙ϩএఠȝഽйʮჯඒϰळՋ௮ສ৵ऎΦѧਹಧଟƙŃ३î༦ŌပղयƟแҜ།",
+	/// Exports from every schema revision, oldest first. Unversioned exports
+	/// predate `VERSIONED_PREFS_MAGIC`.
+	static KNOWN_GOOD_CONFIGS: &[(&str, &str)] = &[
+		("unversioned revision 1", "ఴӅβØØҞÉဏႢձĬ༧ȒʯऌԔӵ୮༏"),
+		("unversioned revision 1", "ਧՊΥÀÃǎƱГ۸ඣമĖฤ႙ʟาúໜϾௐɥঀĜໃહཞઠѫҲɂఙ࿔ǲઉƲӟӻĻฅΜδ໖ԜǗဖငƦơ৶Ą௩ԹʛใЛʃශаΏ"),
+		("unversioned revision 1", "ਧԩΥÀÃÎŠ౭൩ඔႠϼҭöҪƸռઇԾॐნɔາǒՍҰच௨ಖມŃЉŐདƦ๙ϩএఠȝഽйʮჯඒϰळՋ௮ສ৵ऎΦѧਹಧଟƙŃ३î༦ŌပղयƟแҜ།"),
+		("revision 2", "ɮϠΥºÅǃϊȌဈଚϛՂƔΠचഺĳ౪ဓঐჯ၇Ⴃཤഐജ५ЋಫਵƩ๒༤ஸƖµǙღɰԾஐҴЭҸɖඍąɶ࿈θßทĤດত೫ѩဝƄşµ།"),
+		("revision 3", "пʒടºÃÀπΨ႐ష१ǇĨฅଭஆद೪ပ൯ԒදযဏஇถปīƌӫౡఇဖಔഒίƬਲಌఙငჯ൵Ā๑૯Р೮"),
+		("revision 4", "пͲΫºÅǁδÕ႐ɉμ೮Ѵùҵ൪ɓăɘĊરԱଆჲՒუพഴങßဍಒใťΝੳગϭȍԵଉஇඤвচණʘѕȊଜϠҒไҰĺตఒҠή"),
+		("revision 5", "пͲΫºÅǁδङ႐ɉμ೮ѴûҚദਓ൪ගण୩ધ࿏྾ແʛరɈఈऒʟಖସΩΦɞюзƘȠӫ௫ཌŬχฒϴĳĲஞԞϩʘֆฒಗѓభඟഒÀ"),
 	];
 
+	fn decode_known_good(config: &str) -> Result<Preferences, String> {
+		let bytes = base2048::decode(config).unwrap();
+		let decompressed = deflate_decompress(bytes).unwrap();
+		Preferences::from_bincode(&decompressed)
+	}
+
 	#[test]
 	fn test_known_good_configs_deserialization() {
-		for config in KNOWN_GOOD_CONFIGS {
-			let bytes = base2048::decode(config).unwrap();
-			let decompressed = deflate_decompress(bytes).unwrap();
-			assert!(bincode::deserialize::<Preferences>(&decompressed).is_ok());
+		for (revision, config) in KNOWN_GOOD_CONFIGS {
+			let prefs = decode_known_good(config).unwrap_or_else(|e| panic!("{revision}: {e}"));
+			let number: u16 = revision.rsplit(' ').next().and_then(|number| number.parse().ok()).unwrap();
+			if number >= 2 {
+				assert_eq!(prefs.theme, "dracula", "{revision}");
+				assert_eq!(prefs.subscriptions, vec!["rust", "linux"], "{revision}");
+				assert_eq!(prefs.group("news").map(|group| group.subs.len()), Some(2), "{revision}");
+			}
+			if number >= 3 {
+				assert_eq!(prefs.keep_tracking_params, "on", "{revision}");
+			}
+			if number >= 4 {
+				assert_eq!(prefs.frontends, "youtube:yewtu.be", "{revision}");
+			}
+			if number >= 5 {
+				assert_eq!(prefs.disable_syntax_highlighting, "on", "{revision}");
+			}
 		}
 	}
 
 	#[test]
 	fn test_known_good_configs_full_round_trip() {
-		for config in KNOWN_GOOD_CONFIGS {
-			let bytes = base2048::decode(config).unwrap();
-			let decompressed = deflate_decompress(bytes).unwrap();
-			let prefs: Preferences = bincode::deserialize(&decompressed).unwrap();
+		for (_, config) in KNOWN_GOOD_CONFIGS {
+			let prefs = decode_known_good(config).unwrap();
 			test_round_trip(&prefs, false);
 			test_round_trip(&prefs, true);
 		}
 	}
 
+	/// A versioned export from before subscription groups, as revision 1 would
+	/// have written it
+	#[revisioned(revision = 1)]
+	struct VersionedPreferencesV1 {
+		available_themes: Vec<String>,
+		theme: String,
+		front_page: String,
+		layout: String,
+		wide: String,
+		blur_spoiler: String,
+		show_nsfw: String,
+		blur_nsfw: String,
+		hide_hls_notification: String,
+		video_quality: String,
+		hide_sidebar_and_summary: String,
+		use_hls: String,
+		autoplay_videos: String,
+		fixed_navbar: String,
+		disable_visit_reddit_confirmation: String,
+		comment_sort: String,
+		post_sort: String,
+		subscriptions: Vec<String>,
+		filters: Vec<String>,
+		hide_awards: String,
+		hide_score: String,
+		remove_default_feeds: String,
+	}
+
+	#[test]
+	fn test_versioned_upgrade() {
+		let old = VersionedPreferencesV1 {
+			available_themes: vec![],
+			theme: "dark".to_owned(),
+			front_page: String::new(),
+			layout: "clean".to_owned(),
+			wide: String::new(),
+			blur_spoiler: String::new(),
+			show_nsfw: String::new(),
+			blur_nsfw: String::new(),
+			hide_hls_notification: String::new(),
+			video_quality: String::new(),
+			hide_sidebar_and_summary: String::new(),
+			use_hls: String::new(),
+			autoplay_videos: String::new(),
+			fixed_navbar: String::new(),
+			disable_visit_reddit_confirmation: String::new(),
+			comment_sort: String::new(),
+			post_sort: String::new(),
+			subscriptions: vec!["rust".to_owned()],
+			filters: vec![],
+			hide_awards: String::new(),
+			hide_score: String::new(),
+			remove_default_feeds: "on".to_owned(),
+		};
+		let bytes = [VERSIONED_PREFS_MAGIC, &revision::to_vec(&old).unwrap()].concat();
+
+		let prefs = Preferences::from_bincode(&bytes).unwrap();
+		assert_eq!(prefs.layout, "clean");
+		assert_eq!(prefs.subscriptions, vec!["rust"]);
+		assert_eq!(prefs.remove_default_feeds, "on");
+		assert!(prefs.subscription_groups.is_empty());
+	}
+
+	#[test]
+	fn test_base_url() {
+		let request = |headers: &[(&str, &str)]| {
+			let mut builder = Request::builder();
+			for (name, value) in headers {
+				builder = builder.header(*name, *value);
+			}
+			builder.body(Body::empty()).unwrap()
+		};
+
+		assert_eq!(base_url(&request(&[("host", "redlib.example.com")])).unwrap(), "https://redlib.example.com");
+		assert_eq!(base_url(&request(&[("host", "localhost:8080")])).unwrap(), "http://localhost:8080");
+		assert_eq!(base_url(&request(&[("host", "redlib.lan"), ("x-forwarded-proto", "http")])).unwrap(), "http://redlib.lan");
+		assert_eq!(base_url(&request(&[])), None);
+	}
+
+	#[test]
+	fn test_newer_revision_rejected() {
+		let mut encoded = Vec::new();
+		(Preferences::revision() + 1).serialize_revisioned(&mut encoded).unwrap();
+		let bytes = [VERSIONED_PREFS_MAGIC, &encoded, b"future fields"].concat();
+
+		let err = Preferences::from_bincode(&bytes).unwrap_err();
+		assert!(err.contains("newer version of Redlib"), "{err}");
+	}
+
 	fn test_round_trip(input: &Preferences, compression: bool) {
-		let serialized = bincode::serialize(input).unwrap();
+		let serialized = input.to_bincode().unwrap();
+		assert!(serialized.starts_with(VERSIONED_PREFS_MAGIC));
 		let compressed = if compression { deflate_compress(serialized).unwrap() } else { serialized };
 		let decompressed = if compression { deflate_decompress(compressed).unwrap() } else { compressed };
-		let deserialized: Preferences = bincode::deserialize(&decompressed).unwrap();
+		let deserialized = Preferences::from_bincode(&decompressed).unwrap();
 		assert_eq!(*input, deserialized);
 	}
 }
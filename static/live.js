@@ -0,0 +1,28 @@
+// Periodically load new updates of a live thread without reloading the page.
+const POLL_INTERVAL = 30000;
+
+async function pollLiveUpdates() {
+    try {
+        const response = await fetch(window.location.pathname);
+        const text = await response.text();
+        const doc = new DOMParser().parseFromString(text, "text/html");
+        const container = document.getElementById('live_updates');
+
+        const fresh = Array.from(doc.querySelectorAll('#live_updates > .live_update'))
+            .filter(update => !document.getElementById(update.id));
+
+        // Updates are newest first, so insert them in reverse order at the top
+        fresh.reverse().forEach(update => container.prepend(document.adoptNode(update)));
+
+        const thread = doc.getElementById('live_thread');
+        if (thread && thread.dataset.state !== 'live') {
+            return;
+        }
+    } catch (error) {
+        console.error('Error fetching live updates:', error);
+    }
+
+    setTimeout(pollLiveUpdates, POLL_INTERVAL);
+}
+
+setTimeout(pollLiveUpdates, POLL_INTERVAL);
@@ -0,0 +1,10 @@
+// Load a chosen settings file into the import textarea
+function load_file(event) {
+    const file = event.target.files[0];
+    if (!file) return;
+    file.text().then(text => document.getElementById('import_contents').value = text);
+}
+
+window.addEventListener('load', () => {
+    document.getElementById('import_file').addEventListener('change', load_file);
+});